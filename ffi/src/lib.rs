@@ -0,0 +1,5 @@
+//! The C API of the `biblearchive` crate built as the `biblearchive_ffi` shared and static
+//! libraries. The functions are in `biblearchive::ffi` and the header is
+//! `include/biblearchive.h`.
+
+pub use biblearchive::ffi::*;
@@ -0,0 +1,39 @@
+#![cfg(unix)]
+use std::env;
+use std::path::PathBuf;
+use std::process::Command;
+
+// Compile tests/c/ffi_test.c against the static library built alongside this test and run it
+#[test]
+fn test_c_api() {
+    // Test executables live in target/<profile>/deps and the libraries one level up
+    let exe = env::current_exe().unwrap();
+    let profile_dir = exe.parent().unwrap().parent().unwrap();
+    let library = profile_dir.join("libbiblearchive_ffi.a");
+    assert!(library.exists(), "{} not built", library.display());
+
+    // The C sources, header and test data belong to the main crate
+    let manifest_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("..");
+    let program = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("ffi_test");
+    let compiler = env::var("CC").unwrap_or("cc".to_string());
+    let status = Command::new(compiler)
+        .arg(manifest_dir.join("tests/c/ffi_test.c"))
+        .arg("-I")
+        .arg(manifest_dir.join("include"))
+        .arg(&library)
+        .args(["-lpthread", "-ldl", "-lm", "-o"])
+        .arg(&program)
+        .status()
+        .expect("Failed to run C compiler");
+    assert!(status.success(), "Failed to compile tests/c/ffi_test.c");
+
+    let output = Command::new(&program)
+        .arg(manifest_dir.join("tests/data/KJV.ibar"))
+        .output()
+        .expect("Failed to run ffi_test");
+    assert!(
+        output.status.success(),
+        "ffi_test failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
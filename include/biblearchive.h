@@ -0,0 +1,182 @@
+#ifndef BIBLEARCHIVE_H
+#define BIBLEARCHIVE_H
+
+/* Generated with cbindgen from src/ffi.rs. Do not edit by hand. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Status codes returned by the C API. These mirror the variants of `BARFileError`.
+ */
+typedef enum BarStatus {
+  BAR_STATUS_OK = 0,
+  BAR_STATUS_INVALID_FILE_FORMAT = 1,
+  BAR_STATUS_COMPRESSION_ERROR = 2,
+  BAR_STATUS_REFERENCE_ERROR = 3,
+  BAR_STATUS_IO_ERROR = 4,
+  /**
+   * A book, chapter or verse is not present in the archive
+   */
+  BAR_STATUS_NOT_FOUND = 5,
+  /**
+   * A NULL pointer or invalid UTF-8 was passed in
+   */
+  BAR_STATUS_INVALID_ARGUMENT = 6,
+  /**
+   * Rust code panicked. The archive should not be used again.
+   */
+  BAR_STATUS_PANIC = 7,
+} BarStatus;
+
+/**
+ * Opaque handle to an open archive
+ */
+typedef struct BarArchive BarArchive;
+
+/**
+ * A verse matching a search. `text` is a NUL terminated UTF-8 string.
+ */
+typedef struct BarSearchHit {
+  uint8_t book;
+  uint8_t chapter;
+  uint8_t verse;
+  char *text;
+} BarSearchHit;
+
+/**
+ * Message describing the last failure on this thread, or NULL if there has not been one.
+ * The string is owned by the library and is valid until the next failing call on this thread.
+ */
+const char *bar_last_error(void);
+
+/**
+ * Open the archive at `path`.
+ *
+ * # Safety
+ * `path` must be a NUL terminated string and `out` must be valid for writes.
+ */
+enum BarStatus bar_open(const char *path, struct BarArchive **out);
+
+/**
+ * Open an archive held in memory. The data is copied so the buffer can be freed afterwards.
+ *
+ * # Safety
+ * `data` must be valid for reads of `len` bytes and `out` must be valid for writes.
+ */
+enum BarStatus bar_open_memory(const uint8_t *data, size_t len, struct BarArchive **out);
+
+/**
+ * Close an archive opened with `bar_open` or `bar_open_memory`. NULL is ignored.
+ *
+ * # Safety
+ * `archive` must have come from this library and must not be used afterwards.
+ */
+void bar_close(struct BarArchive *archive);
+
+/**
+ * Number of books in the archive, or 0 if `archive` is NULL
+ *
+ * # Safety
+ * `archive` must be NULL or a live handle.
+ */
+size_t bar_book_count(const struct BarArchive *archive);
+
+/**
+ * Write the numbers of the books in the archive (1=Genesis 66=Revelation) in canonical order
+ * into `books`, which has room for `capacity` entries. `count` is set to the number of books
+ * in the archive, which may be more than `capacity`.
+ *
+ * # Safety
+ * `books` must be valid for writes of `capacity` bytes and `count` must be valid for writes.
+ */
+enum BarStatus bar_books(const struct BarArchive *archive,
+                         uint8_t *books,
+                         size_t capacity,
+                         size_t *count);
+
+/**
+ * Full name of a book, eg. "Genesis". Free the result with `bar_string_free`.
+ */
+char *bar_book_name(uint8_t book);
+
+/**
+ * Abbreviation of a book, eg. "Ge". Free the result with `bar_string_free`.
+ */
+char *bar_book_abbrev(uint8_t book);
+
+/**
+ * Number of chapters in a book
+ *
+ * # Safety
+ * `archive` must be a live handle and `out` must be valid for writes.
+ */
+enum BarStatus bar_chapter_count(const struct BarArchive *archive, uint8_t book, uint8_t *out);
+
+/**
+ * Number of verses in a chapter
+ *
+ * # Safety
+ * `archive` must be a live handle and `out` must be valid for writes.
+ */
+enum BarStatus bar_verse_count(const struct BarArchive *archive,
+                               uint8_t book,
+                               uint8_t chapter,
+                               uint8_t *out);
+
+/**
+ * Text of a single verse as UTF-8. Free the result with `bar_string_free`.
+ *
+ * # Safety
+ * `archive` must be a live handle and `out` must be valid for writes.
+ */
+enum BarStatus bar_verse_text(const struct BarArchive *archive,
+                              uint8_t book,
+                              uint8_t chapter,
+                              uint8_t verse,
+                              char **out);
+
+/**
+ * Text of a whole chapter as UTF-8, one verse per line. Free the result with `bar_string_free`.
+ *
+ * # Safety
+ * `archive` must be a live handle and `out` must be valid for writes.
+ */
+enum BarStatus bar_chapter_text(const struct BarArchive *archive,
+                                uint8_t book,
+                                uint8_t chapter,
+                                char **out);
+
+/**
+ * Free a string returned by this library. NULL is ignored.
+ *
+ * # Safety
+ * `value` must have come from this library and must not be used afterwards.
+ */
+void bar_string_free(char *value);
+
+/**
+ * Find every verse containing `needle`. The hits are written to `hits` as an array of `count`
+ * entries which must be freed with `bar_search_free`.
+ *
+ * # Safety
+ * `archive` must be a live handle, `needle` a NUL terminated string and `hits` and `count`
+ * must be valid for writes.
+ */
+enum BarStatus bar_search(const struct BarArchive *archive,
+                          const char *needle,
+                          struct BarSearchHit **hits,
+                          size_t *count);
+
+/**
+ * Free the results of `bar_search`. NULL is ignored.
+ *
+ * # Safety
+ * `hits` and `count` must be exactly as returned by `bar_search`.
+ */
+void bar_search_free(struct BarSearchHit *hits, size_t count);
+
+#endif  /* BIBLEARCHIVE_H */
@@ -0,0 +1,351 @@
+//! Async reading of BARFiles over tokio [`AsyncRead`] + [`AsyncSeek`] sources.
+//!
+//! Requires the `async` feature. [`AsyncBARFile`] mirrors the read API of
+//! [`BARFile`](crate::BARFile) and shares its header, index and block parsing; only the I/O is
+//! different. Everything here is `Send` so it can be used from a multi-threaded runtime
+//! without `spawn_blocking`.
+//!
+//! # Example
+//! ```rust
+//! # #[tokio::main(flavor = "current_thread")]
+//! # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use biblearchive::asyncbar::AsyncBARFile;
+//! let bar = AsyncBARFile::open("tests/data/KJV.ibar").await?;
+//! let book = bar.book_from_abbrev("Ge").await.unwrap();
+//! let chapter = book.chapter(1).await.unwrap();
+//! assert_eq!(
+//!     chapter.verse_text(1).await?,
+//!     "In the beginning God created the heaven and the earth."
+//! );
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::barbook::barchapter::{BlockEntry, BlockHeader, BlockText, decompress_block};
+use crate::barbook::{self, BARBookHeader, BARChapterIndexEntry};
+use crate::error::{BARFileError, BARResult};
+use crate::markup;
+use crate::{BARBookIndexEntry, BARFileHeader, BARVersion, BinaryStruct};
+use futures_util::stream::{self, Stream};
+use std::collections::VecDeque;
+use std::io::{self, SeekFrom};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeek, AsyncSeekExt};
+use tokio::sync::Mutex;
+
+async fn read_at<R: AsyncRead + AsyncSeek + Unpin>(
+    reader: &Mutex<R>,
+    file_offset: u64,
+    size: usize,
+) -> io::Result<Vec<u8>> {
+    let mut reader = reader.lock().await;
+    reader.seek(SeekFrom::Start(file_offset)).await?;
+    let mut buf: Vec<u8> = vec![0; size];
+    reader.read_exact(&mut buf[..]).await?;
+    Ok(buf)
+}
+
+pub struct AsyncBARFile<R> {
+    reader: Arc<Mutex<R>>,
+    header: BARFileHeader,
+    book_index: Vec<BARBookIndexEntry>,
+}
+
+impl AsyncBARFile<tokio::fs::File> {
+    pub async fn open(file_path: impl AsRef<Path>) -> BARResult<Self> {
+        let file = tokio::fs::File::open(file_path).await?;
+        Self::read(file).await
+    }
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin> AsyncBARFile<R> {
+    pub async fn read(reader: R) -> BARResult<Self> {
+        let reader = Mutex::new(reader);
+        let buf = read_at(&reader, 0, BARFileHeader::byte_size()).await?;
+        let header = BARFileHeader::read_from(&mut buf.as_slice())?;
+        header.check().map_err(BARFileError::InvalidFileFormat)?;
+        let number_of_books = usize::from(header.number_of_books);
+        let buf = read_at(
+            &reader,
+            BARFileHeader::byte_size() as u64,
+            number_of_books * BARBookIndexEntry::byte_size(),
+        )
+        .await?;
+        let book_index = BARBookIndexEntry::read_array(number_of_books, &mut buf.as_slice())?;
+        Ok(Self {
+            reader: Arc::new(reader),
+            header,
+            book_index,
+        })
+    }
+
+    pub fn archive_version(&self) -> BARVersion {
+        BARVersion(self.header.major_version, self.header.minor_version)
+    }
+
+    pub fn bible_version(&self) -> &String {
+        &self.header.version_abbrev
+    }
+
+    pub fn number_of_books(&self) -> i32 {
+        self.book_index
+            .iter()
+            .take_while(|entry| matches!(entry, BARBookIndexEntry::Live { .. }))
+            .count() as i32
+    }
+
+    pub async fn book(&self, book_number: u8) -> Option<AsyncBARBook<R>> {
+        let file_offset = crate::book_offset(&self.book_index, book_number)?;
+        AsyncBARBook::build(
+            Arc::clone(&self.reader),
+            book_number,
+            file_offset,
+            self.header.major_version,
+            barbook::has_markup(self.header.major_version, self.header.minor_version),
+        )
+        .await
+        .ok()
+    }
+
+    pub async fn book_from_abbrev(&self, abbrev: &str) -> Option<AsyncBARBook<R>> {
+        let number = bible_data::parse_book_abbrev(abbrev)?;
+        self.book(number as u8 + 1).await
+    }
+}
+
+pub struct AsyncBARBook<R> {
+    reader: Arc<Mutex<R>>,
+    file_version: u8,
+    markup: bool,
+    file_offset: u32,
+    header: BARBookHeader,
+    chapter_index: Vec<BARChapterIndexEntry>,
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin> AsyncBARBook<R> {
+    async fn build(
+        reader: Arc<Mutex<R>>,
+        book_number: u8,
+        file_offset: u32,
+        file_version: u8,
+        markup: bool,
+    ) -> BARResult<Self> {
+        let buf = read_at(&reader, u64::from(file_offset), BARBookHeader::byte_size()).await?;
+        let header = BARBookHeader::read_from(&mut buf.as_slice())?;
+        if header.book_number != book_number {
+            return Err(BARFileError::InvalidFileFormat(format!(
+                "Book index number mismatch. Expected: {}. Got: {}",
+                book_number, header.book_number
+            )));
+        }
+        let number_of_chapters = usize::from(header.number_of_chapters);
+        let buf = read_at(
+            &reader,
+            u64::from(file_offset) + BARBookHeader::byte_size() as u64,
+            number_of_chapters * BARChapterIndexEntry::byte_size(),
+        )
+        .await?;
+        let chapter_index =
+            BARChapterIndexEntry::read_array(number_of_chapters, &mut buf.as_slice())?;
+        Ok(AsyncBARBook {
+            reader,
+            file_version,
+            markup,
+            file_offset,
+            header,
+            chapter_index,
+        })
+    }
+
+    /// Return the book number 1=Genesis 66=Revelation
+    pub fn book_number(&self) -> u8 {
+        self.header.book_number
+    }
+
+    /// Return the number of chapters
+    pub fn number_of_chapters(&self) -> u8 {
+        self.header.number_of_chapters
+    }
+
+    pub fn book_name(&self) -> &str {
+        barbook::book_name(self.book_number())
+    }
+
+    pub fn book_abbrev(&self) -> &str {
+        barbook::book_abbrev(self.book_number())
+    }
+
+    pub async fn chapter(&self, chapter_number: u8) -> Option<AsyncBARChapter<R>> {
+        // First chapter is 1 but array starts at zero
+        if chapter_number == 0 {
+            return None;
+        }
+        let index = usize::from(chapter_number - 1);
+        match self.chapter_index.get(index)? {
+            BARChapterIndexEntry::Empty => None,
+            BARChapterIndexEntry::Live { additional_offset } => AsyncBARChapter::build(
+                Arc::clone(&self.reader),
+                self.header.book_number,
+                chapter_number,
+                self.file_offset + additional_offset,
+                self.file_version,
+                self.markup,
+            )
+            .await
+            .ok(),
+        }
+    }
+}
+
+pub struct AsyncBARChapter<R> {
+    reader: Arc<Mutex<R>>,
+    file_version: u8,
+    markup: bool,
+    book_number: u8,
+    chapter_number: u8,
+    directory: Vec<BlockEntry>,
+}
+
+impl<R: AsyncRead + AsyncSeek + Unpin> AsyncBARChapter<R> {
+    async fn build(
+        reader: Arc<Mutex<R>>,
+        book_number: u8,
+        chapter_number: u8,
+        file_offset: u32,
+        file_version: u8,
+        markup: bool,
+    ) -> BARResult<Self> {
+        let directory = Self::read_directory(&reader, file_offset, file_version).await?;
+        Ok(AsyncBARChapter {
+            reader,
+            file_version,
+            markup,
+            book_number,
+            chapter_number,
+            directory,
+        })
+    }
+
+    // Walk the block headers of the chapter the same way as the blocking reader
+    async fn read_directory(
+        reader: &Mutex<R>,
+        file_offset: u32,
+        file_version: u8,
+    ) -> BARResult<Vec<BlockEntry>> {
+        let header_size = BlockHeader::size_for_version(file_version)?;
+        let eof_pos = reader.lock().await.seek(SeekFrom::End(0)).await?;
+        let mut directory: Vec<BlockEntry> = Vec::new();
+        let mut chapter_number: Option<u8> = None;
+        let mut file_offset = file_offset;
+        while directory.is_empty() || u64::from(file_offset) + header_size as u64 <= eof_pos {
+            let buf = read_at(reader, u64::from(file_offset), header_size).await?;
+            let header = BlockHeader::from_bytes(&buf, file_version)?;
+            match chapter_number {
+                Some(number) if number != header.chapter_number() => break,
+                _ => chapter_number = Some(header.chapter_number()),
+            }
+            let entry = header.entry(file_offset);
+            file_offset += header_size as u32 + entry.block_size;
+            directory.push(entry);
+        }
+        Ok(directory)
+    }
+
+    pub fn chapter_number(&self) -> u8 {
+        self.chapter_number
+    }
+
+    pub fn book_number(&self) -> u8 {
+        self.book_number
+    }
+
+    /// The blocks that make up this chapter in file order
+    pub fn blocks(&self) -> &[BlockEntry] {
+        &self.directory
+    }
+
+    pub fn number_of_verses(&self) -> u8 {
+        self.directory.last().unwrap().end_verse
+    }
+
+    pub async fn chapter_text(&self) -> BARResult<String> {
+        let mut result = String::new();
+        for entry in &self.directory {
+            result.push_str(&self.block_text(entry).await?);
+        }
+        Ok(result)
+    }
+
+    pub async fn verse_text(&self, num: u32) -> BARResult<String> {
+        let index = self
+            .directory
+            .partition_point(|entry| u32::from(entry.end_verse) < num);
+        let entry = match self.directory.get(index) {
+            Some(entry) if u32::from(entry.start_verse) <= num => entry,
+            _ => {
+                return Err(BARFileError::ReferenceError(format!(
+                    "Could not retrieve verse {} for chapter {} in {}",
+                    num,
+                    self.chapter_number,
+                    barbook::book_name(self.book_number)
+                )));
+            }
+        };
+        let verses = self.block_verses(entry).await?;
+        let index = (num - u32::from(entry.start_verse)) as usize;
+        verses.into_iter().nth(index).ok_or_else(|| {
+            BARFileError::InvalidFileFormat(
+                "Unable to get verse from block that should have contained it".to_string(),
+            )
+        })
+    }
+
+    /// Stream the verses of the chapter in order, decompressing one block at a time
+    pub fn verses(&self) -> impl Stream<Item = BARResult<String>> + '_ {
+        let state = (0, VecDeque::<String>::new(), false);
+        stream::unfold(
+            state,
+            move |(mut block_index, mut pending, failed)| async move {
+                if failed {
+                    return None;
+                }
+                loop {
+                    if let Some(verse) = pending.pop_front() {
+                        return Some((Ok(verse), (block_index, pending, false)));
+                    }
+                    let entry = self.directory.get(block_index)?;
+                    block_index += 1;
+                    match self.block_verses(entry).await {
+                        Ok(verses) => pending = verses.into(),
+                        Err(err) => return Some((Err(err), (block_index, pending, true))),
+                    }
+                }
+            },
+        )
+    }
+
+    async fn block_text(&self, entry: &BlockEntry) -> BARResult<String> {
+        let header_size = BlockHeader::size_for_version(self.file_version)?;
+        let data = read_at(
+            &self.reader,
+            u64::from(entry.file_offset) + header_size as u64,
+            entry.block_size as usize,
+        )
+        .await?;
+        let text = decompress_block(&entry.compression_algorithm, &data)?;
+        // Only plain text is returned
+        match self.markup {
+            true => Ok(markup::strip(&text)),
+            false => Ok(text),
+        }
+    }
+
+    async fn block_verses(&self, entry: &BlockEntry) -> BARResult<Vec<String>> {
+        let text = BlockText::new(self.block_text(entry).await?);
+        Ok((0..text.len())
+            .filter_map(|index| text.verse(index))
+            .map(|verse| verse.to_string())
+            .collect())
+    }
+}
@@ -1,32 +1,43 @@
 use crate::BinaryStruct;
+use crate::cache::BlockCache;
+use crate::error::{BARFileError, BARResult};
 use std::cell::RefCell;
-use std::error::Error;
 use std::io;
 use std::rc::Rc;
 
 use bible_data::{BOOK_ABBREVS, BOOK_NAMES};
 
+pub mod annotations;
 pub mod barchapter;
+pub mod interlinear;
+pub mod notes;
 use barchapter::BARChapter;
 
 #[allow(dead_code)]
 pub struct BARBook<T: io::Read + io::Seek> {
     reader: Rc<RefCell<T>>,
+    cache: Rc<RefCell<BlockCache>>,
     file_version: u8,
+    minor_version: u8,
     file_offset: u32,
     header: BARBookHeader,
     chapter_index: Vec<BARChapterIndexEntry>,
+    verse_counts: Option<Vec<u8>>, // 0 when the count is not known
+    markup: bool,
+    annotations: Option<u32>, // file offset of the annotations section
+    notes: Option<u32>,       // file offset of the notes section
+    interlinear: Option<u32>, // file offset of the interlinear section
     iterator_index: Option<usize>,
 }
 
 #[allow(dead_code)]
-struct BARBookHeader {
-    book_number: u8,
-    number_of_chapters: u8,
+pub(crate) struct BARBookHeader {
+    pub(crate) book_number: u8,
+    pub(crate) number_of_chapters: u8,
 }
 
 #[allow(dead_code)]
-enum BARChapterIndexEntry {
+pub(crate) enum BARChapterIndexEntry {
     Live {
         additional_offset: u32, // file offset of chapter from start of book entry
     },
@@ -88,31 +99,109 @@ impl BinaryStruct for BARChapterIndexEntry {
     }
 }
 
+/// Version 2.3 added the verse count table after the chapter index
+pub(crate) fn has_verse_counts(major_version: u8, minor_version: u8) -> bool {
+    (major_version, minor_version) >= (2, 3)
+}
+
+/// Version 2.4 allowed markup in the block text
+pub(crate) fn has_markup(major_version: u8, minor_version: u8) -> bool {
+    (major_version, minor_version) >= (2, 4)
+}
+
+/// Version 2.5 added the annotations offset after the verse count table
+pub(crate) fn has_annotations(major_version: u8, minor_version: u8) -> bool {
+    (major_version, minor_version) >= (2, 5)
+}
+
+/// Version 2.6 added the notes offset after the annotations offset
+pub(crate) fn has_notes(major_version: u8, minor_version: u8) -> bool {
+    (major_version, minor_version) >= (2, 6)
+}
+
+/// Version 2.7 added the interlinear offset after the notes offset
+pub(crate) fn has_interlinear(major_version: u8, minor_version: u8) -> bool {
+    (major_version, minor_version) >= (2, 7)
+}
+
+// Read an offset from the book header. Zero means there is nothing there.
+fn read_offset(reader: &mut impl io::Read, file_offset: u32) -> BARResult<Option<u32>> {
+    let mut buf: [u8; 4] = [0; 4];
+    reader.read_exact(&mut buf)?;
+    match u32::from_le_bytes(buf) {
+        0 => Ok(None),
+        offset => Ok(Some(file_offset + offset)),
+    }
+}
+
+pub(crate) fn book_name(book_number: u8) -> &'static str {
+    let i = usize::from(book_number);
+    if i > 0 && i <= 66 {
+        return BOOK_NAMES[i - 1];
+    }
+    "Unknown"
+}
+
+pub(crate) fn book_abbrev(book_number: u8) -> &'static str {
+    let i = usize::from(book_number);
+    if i > 0 && i <= 66 {
+        return BOOK_ABBREVS[i - 1];
+    }
+    "???"
+}
+
 impl<T: io::Read + io::Seek> BARBook<T> {
     pub fn build(
         shared_reader: Rc<RefCell<T>>,
+        cache: Rc<RefCell<BlockCache>>,
         book_number: u8,
         file_offset: u32,
         file_version: u8,
-    ) -> Result<Self, Box<dyn Error>> {
+        minor_version: u8,
+    ) -> BARResult<Self> {
         let reader = &mut *shared_reader.borrow_mut();
         reader.seek(io::SeekFrom::Start(u64::from(file_offset)))?;
         let header = BARBookHeader::read_from(reader)?;
         if header.book_number != book_number {
-            return Err(format!(
+            return Err(BARFileError::InvalidFileFormat(format!(
                 "Book index number mismatch. Expected: {}. Got: {}",
                 book_number, header.book_number
-            )
-            .into());
+            )));
         }
         let chapter_index =
             BARChapterIndexEntry::read_array(usize::from(header.number_of_chapters), reader)?;
+        let verse_counts = if has_verse_counts(file_version, minor_version) {
+            let mut buf: Vec<u8> = vec![0; usize::from(header.number_of_chapters)];
+            reader.read_exact(&mut buf)?;
+            Some(buf)
+        } else {
+            None
+        };
+        let annotations = match has_annotations(file_version, minor_version) {
+            true => read_offset(reader, file_offset)?,
+            false => None,
+        };
+        let notes = match has_notes(file_version, minor_version) {
+            true => read_offset(reader, file_offset)?,
+            false => None,
+        };
+        let interlinear = match has_interlinear(file_version, minor_version) {
+            true => read_offset(reader, file_offset)?,
+            false => None,
+        };
         Ok(BARBook {
             reader: Rc::clone(&shared_reader),
+            cache,
             file_offset,
             header,
             chapter_index,
+            verse_counts,
+            markup: has_markup(file_version, minor_version),
+            annotations,
+            notes,
+            interlinear,
             file_version,
+            minor_version,
             iterator_index: None,
         })
     }
@@ -128,19 +217,81 @@ impl<T: io::Read + io::Seek> BARBook<T> {
     }
 
     pub fn book_name(&self) -> &str {
-        let i = usize::from(self.book_number());
-        if i > 0 && i <= 66 {
-            return BOOK_NAMES[i - 1];
-        }
-        "Unknown"
+        book_name(self.book_number())
     }
 
     pub fn book_abbrev(&self) -> &str {
-        let i = usize::from(self.book_number());
-        if i > 0 && i <= 66 {
-            return BOOK_ABBREVS[i - 1];
+        book_abbrev(self.book_number())
+    }
+
+    pub(crate) fn file_offset(&self) -> u32 {
+        self.file_offset
+    }
+
+    /// Bytes taken by the book header, chapter index, verse count table and section offsets
+    pub(crate) fn header_size(&self) -> u32 {
+        let verse_counts = self.verse_counts.as_ref().map_or(0, |counts| counts.len());
+        let offsets = [
+            has_annotations(self.file_version, self.minor_version),
+            has_notes(self.file_version, self.minor_version),
+            has_interlinear(self.file_version, self.minor_version),
+        ];
+        let offsets = offsets.iter().filter(|present| **present).count() * 4;
+        (BARBookHeader::byte_size()
+            + self.chapter_index.len() * BARChapterIndexEntry::byte_size()
+            + verse_counts
+            + offsets) as u32
+    }
+
+    /// File range of the annotations section, if the book has one
+    pub(crate) fn annotations_range(&self) -> BARResult<Option<(u64, u64)>> {
+        let Some(offset) = self.annotations else {
+            return Ok(None);
+        };
+        let reader = &mut *self.reader.borrow_mut();
+        reader.seek(io::SeekFrom::Start(u64::from(offset)))?;
+        let mut buf: [u8; 4] = [0; 4];
+        reader.read_exact(&mut buf)?;
+        let end = u64::from(offset) + 4 + u64::from(u32::from_le_bytes(buf));
+        Ok(Some((u64::from(offset), end)))
+    }
+
+    /// File range of the notes section, if the book has one
+    pub(crate) fn notes_range(&self) -> BARResult<Option<(u64, u64)>> {
+        self.text_section_range(self.notes)
+    }
+
+    /// File range of the interlinear section, if the book has one
+    pub(crate) fn interlinear_range(&self) -> BARResult<Option<(u64, u64)>> {
+        self.text_section_range(self.interlinear)
+    }
+
+    // Range of a compressed text section: compression, size then the data
+    fn text_section_range(&self, offset: Option<u32>) -> BARResult<Option<(u64, u64)>> {
+        let Some(offset) = offset else {
+            return Ok(None);
+        };
+        let reader = &mut *self.reader.borrow_mut();
+        reader.seek(io::SeekFrom::Start(u64::from(offset) + 1))?;
+        let mut buf: [u8; 4] = [0; 4];
+        reader.read_exact(&mut buf)?;
+        let end = u64::from(offset) + 5 + u64::from(u32::from_le_bytes(buf));
+        Ok(Some((u64::from(offset), end)))
+    }
+
+    /// Number of verses in each chapter, 0 for chapters not in the archive.
+    ///
+    /// Archives from version 2.3 store the counts so this does not need to read any chapter. For
+    /// older archives, or where a count was not stored, the block headers of the chapter are read.
+    pub fn verse_counts(&self) -> BARResult<Vec<u8>> {
+        let mut counts: Vec<u8> = Vec::new();
+        for chapter in self.chapters() {
+            match chapter {
+                Some(chapter) => counts.push(chapter.number_of_verses()?),
+                None => counts.push(0),
+            }
         }
-        "???"
+        Ok(counts)
     }
 
     pub fn chapter(&self, chapter_number: u8) -> Option<BARChapter<T>> {
@@ -154,24 +305,56 @@ impl<T: io::Read + io::Seek> BARBook<T> {
             BARChapterIndexEntry::Empty => None,
             BARChapterIndexEntry::Live { additional_offset } => {
                 let file_offset = self.file_offset + additional_offset;
+                let verse_count = self
+                    .verse_counts
+                    .as_ref()
+                    .and_then(|counts| counts.get(usize::from(index)))
+                    .filter(|count| **count != 0);
                 BARChapter::build(
                     Rc::clone(&self.reader),
+                    Rc::clone(&self.cache),
                     self.header.book_number,
                     chapter_number,
                     file_offset,
                     self.file_version,
                 )
                 .ok()
+                .map(|chapter| {
+                    chapter
+                        .with_verse_count(verse_count.copied())
+                        .with_markup(self.markup)
+                        .with_annotations(self.annotations)
+                        .with_notes(self.notes)
+                        .with_interlinear(self.interlinear)
+                })
             }
         }
     }
 
+    /// Like [`BARBook::chapter`] but tells a chapter that is not in the archive, `Ok(None)`,
+    /// from one that can not be read. The block headers of the chapter are read to check it.
+    pub fn try_chapter(&self, chapter_number: u8) -> BARResult<Option<BARChapter<T>>> {
+        let Some(chapter) = self.chapter(chapter_number) else {
+            return Ok(None);
+        };
+        chapter.blocks()?;
+        Ok(Some(chapter))
+    }
+
     pub fn chapters<'a>(&'a self) -> BARBookIterator<'a, T> {
         BARBookIterator {
             barbook: self,
             index: 1,
         }
     }
+
+    /// Every chapter slot of the book, as returned by [`BARBook::try_chapter`]
+    pub fn try_chapters<'a>(&'a self) -> TryBARBookIterator<'a, T> {
+        TryBARBookIterator {
+            barbook: self,
+            index: 1,
+        }
+    }
 }
 
 pub struct BARBookIterator<'a, T: io::Seek + io::Read> {
@@ -191,3 +374,129 @@ impl<'a, T: io::Seek + io::Read> Iterator for BARBookIterator<'a, T> {
         Some(self.barbook.chapter(current_index))
     }
 }
+
+pub struct TryBARBookIterator<'a, T: io::Seek + io::Read> {
+    barbook: &'a BARBook<T>,
+    index: u8,
+}
+
+impl<'a, T: io::Seek + io::Read> Iterator for TryBARBookIterator<'a, T> {
+    type Item = BARResult<Option<BARChapter<T>>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let current_index = self.index;
+        if current_index as usize > self.barbook.chapter_index.len() {
+            return None;
+        }
+        self.index += 1;
+        Some(self.barbook.try_chapter(current_index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // Book 1 with three chapters. Chapter 3 is not present.
+    fn test_book(minor_version: u8, verse_counts: &[u8]) -> BARBook<Cursor<Vec<u8>>> {
+        let chapters: [&[(u8, u8, &str)]; 2] = [
+            &[(1, 3, "One\nTwo\nThree\n")],
+            &[(1, 1, "A\n"), (2, 2, "B\n")],
+        ];
+        let mut data: Vec<u8> = Vec::new();
+        let mut chapter_offsets: Vec<u32> = Vec::new();
+        let start = BARBookHeader::byte_size() + 3 * BARChapterIndexEntry::byte_size();
+        let start = start + verse_counts.len();
+        for (i, blocks) in chapters.iter().enumerate() {
+            chapter_offsets.push((start + data.len()) as u32);
+            for (start_verse, end_verse, text) in blocks.iter() {
+                data.extend_from_slice(&[i as u8 + 1, *start_verse, *end_verse, 0]);
+                data.extend_from_slice(&(text.len() as u32).to_le_bytes());
+                data.extend_from_slice(text.as_bytes());
+            }
+        }
+        data.push(0);
+
+        let mut buf = BARBookHeader {
+            book_number: 1,
+            number_of_chapters: 3,
+        }
+        .to_bytes();
+        for offset in chapter_offsets {
+            buf.append(
+                &mut BARChapterIndexEntry::Live {
+                    additional_offset: offset,
+                }
+                .to_bytes(),
+            );
+        }
+        buf.append(&mut BARChapterIndexEntry::Empty.to_bytes());
+        buf.extend_from_slice(verse_counts);
+        buf.append(&mut data);
+        BARBook::build(
+            Rc::new(RefCell::new(Cursor::new(buf))),
+            Rc::new(RefCell::new(BlockCache::default())),
+            1,
+            0,
+            2,
+            minor_version,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_verse_count_table() {
+        let book = test_book(3, &[3, 0, 0]);
+        assert_eq!(book.header_size(), 2 + 12 + 3);
+        let chapter = book.chapter(1).unwrap();
+        assert_eq!(chapter.number_of_verses().unwrap(), 3);
+        // The count came from the table so the block headers were not read
+        assert!(book.cache.borrow().directory(17).is_none());
+        // Unknown counts fall back to the blocks
+        assert_eq!(book.chapter(2).unwrap().number_of_verses().unwrap(), 2);
+        assert_eq!(book.verse_counts().unwrap(), vec![3, 2, 0]);
+        assert_eq!(&*chapter.verse_text(3).unwrap(), "Three");
+    }
+
+    #[test]
+    fn test_verse_counts_without_table() {
+        let book = test_book(2, &[]);
+        assert_eq!(book.header_size(), 2 + 12);
+        assert_eq!(book.verse_counts().unwrap(), vec![3, 2, 0]);
+        assert!(book.cache.borrow().directory(14).is_some());
+    }
+
+    #[test]
+    fn test_try_chapters() {
+        let book = test_book(3, &[3, 0, 0]);
+        let chapters: Vec<BARResult<Option<BARChapter<_>>>> = book.try_chapters().collect();
+        assert_eq!(chapters.len(), 3);
+        assert_eq!(
+            chapters[1]
+                .as_ref()
+                .unwrap()
+                .as_ref()
+                .unwrap()
+                .chapter_number(),
+            2
+        );
+        assert!(matches!(chapters[2], Ok(None)));
+
+        // Point chapter 2 at the block of chapter 1
+        let reader = Rc::clone(&book.reader);
+        reader.borrow_mut().get_mut()[6..10].copy_from_slice(&17u32.to_le_bytes());
+        let book = BARBook::build(
+            reader,
+            Rc::new(RefCell::new(BlockCache::default())),
+            1,
+            0,
+            2,
+            3,
+        )
+        .unwrap();
+        assert!(book.chapter(2).is_some());
+        assert!(book.try_chapter(2).is_err());
+        assert!(book.try_chapter(1).unwrap().is_some());
+    }
+}
@@ -0,0 +1,172 @@
+//! Section headings and paragraph breaks (format version 2.5 on).
+//!
+//! The annotations of a book are kept in one section after its last block, found through an
+//! offset stored after the verse count table. Each chapter that has any headings or paragraph
+//! breaks has a record in the section. Readers before 2.5 never look past the end of the book
+//! data so are not affected.
+
+use crate::error::{BARFileError, BARResult};
+
+/// A heading shown before a verse
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Heading {
+    /// The verse the heading comes before
+    pub verse: u8,
+    /// 1 for a section heading, 2 and up for lesser headings
+    pub level: u8,
+    pub text: String,
+}
+
+/// Headings and paragraph starts of one chapter
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub(crate) struct ChapterAnnotations {
+    pub(crate) paragraphs: Vec<u8>, // verses that start a paragraph
+    pub(crate) headings: Vec<Heading>,
+}
+
+impl ChapterAnnotations {
+    pub(crate) fn is_empty(&self) -> bool {
+        self.paragraphs.is_empty() && self.headings.is_empty()
+    }
+}
+
+fn truncated() -> BARFileError {
+    BARFileError::InvalidFileFormat("Chapter annotations are truncated".to_string())
+}
+
+// Take n bytes from the front of data
+fn take<'a>(data: &mut &'a [u8], n: usize) -> BARResult<&'a [u8]> {
+    if data.len() < n {
+        return Err(truncated());
+    }
+    let (bytes, rest) = data.split_at(n);
+    *data = rest;
+    Ok(bytes)
+}
+
+/// Find the record for a chapter in the annotations section of a book, without the size
+pub(crate) fn find(section: &[u8], chapter_number: u8) -> BARResult<ChapterAnnotations> {
+    let mut data = section;
+    while !data.is_empty() {
+        let header = take(&mut data, 3)?;
+        let size = usize::from(u16::from_le_bytes([header[1], header[2]]));
+        let record = take(&mut data, size)?;
+        if header[0] == chapter_number {
+            return decode(record);
+        }
+    }
+    Ok(ChapterAnnotations::default())
+}
+
+/// The section without the record for a chapter, and whether there was one
+pub(crate) fn remove(section: &[u8], chapter_number: u8) -> BARResult<(Vec<u8>, bool)> {
+    let mut data = section;
+    let mut result: Vec<u8> = Vec::new();
+    let mut found = false;
+    while !data.is_empty() {
+        let header = take(&mut data, 3)?;
+        let size = usize::from(u16::from_le_bytes([header[1], header[2]]));
+        let record = take(&mut data, size)?;
+        if header[0] == chapter_number {
+            found = true;
+        } else {
+            result.extend_from_slice(header);
+            result.extend_from_slice(record);
+        }
+    }
+    Ok((result, found))
+}
+
+fn decode(mut data: &[u8]) -> BARResult<ChapterAnnotations> {
+    let count = take(&mut data, 1)?[0];
+    let paragraphs = take(&mut data, usize::from(count))?.to_vec();
+    let count = take(&mut data, 1)?[0];
+    let mut headings: Vec<Heading> = Vec::new();
+    for _ in 0..count {
+        let header = take(&mut data, 4)?;
+        let size = usize::from(u16::from_le_bytes([header[2], header[3]]));
+        let text = String::from_utf8(take(&mut data, size)?.to_vec()).map_err(|_| {
+            BARFileError::InvalidFileFormat("Heading is not valid UTF-8".to_string())
+        })?;
+        headings.push(Heading {
+            verse: header[0],
+            level: header[1],
+            text,
+        });
+    }
+    Ok(ChapterAnnotations {
+        paragraphs,
+        headings,
+    })
+}
+
+/// The record for one chapter, to be added to the annotations section of a book
+pub(crate) fn encode(chapter_number: u8, annotations: &ChapterAnnotations) -> BARResult<Vec<u8>> {
+    if annotations.paragraphs.len() > usize::from(u8::MAX)
+        || annotations.headings.len() > usize::from(u8::MAX)
+    {
+        return Err(BARFileError::InvalidFileFormat(format!(
+            "Too many headings or paragraphs in chapter {}",
+            chapter_number
+        )));
+    }
+    let mut record: Vec<u8> = vec![annotations.paragraphs.len() as u8];
+    record.extend_from_slice(&annotations.paragraphs);
+    record.push(annotations.headings.len() as u8);
+    for heading in &annotations.headings {
+        let size = u16::try_from(heading.text.len()).map_err(|_| {
+            BARFileError::InvalidFileFormat(format!(
+                "Heading in chapter {} is too long",
+                chapter_number
+            ))
+        })?;
+        record.push(heading.verse);
+        record.push(heading.level);
+        record.extend_from_slice(&size.to_le_bytes());
+        record.extend_from_slice(heading.text.as_bytes());
+    }
+    let size = u16::try_from(record.len()).map_err(|_| {
+        BARFileError::InvalidFileFormat(format!(
+            "Annotations for chapter {} are too long",
+            chapter_number
+        ))
+    })?;
+    let mut result: Vec<u8> = vec![chapter_number];
+    result.extend_from_slice(&size.to_le_bytes());
+    result.append(&mut record);
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let first = ChapterAnnotations {
+            paragraphs: vec![1, 3],
+            headings: vec![Heading {
+                verse: 1,
+                level: 1,
+                text: "The Creation".to_string(),
+            }],
+        };
+        let third = ChapterAnnotations {
+            paragraphs: vec![8],
+            headings: vec![],
+        };
+        let mut section = encode(1, &first).unwrap();
+        section.append(&mut encode(3, &third).unwrap());
+        assert_eq!(find(&section, 1).unwrap(), first);
+        assert_eq!(find(&section, 3).unwrap(), third);
+        assert!(find(&section, 2).unwrap().is_empty());
+        assert!(find(&section[..section.len() - 1], 3).is_err());
+
+        let (rest, found) = remove(&section, 1).unwrap();
+        assert!(found);
+        assert!(find(&rest, 1).unwrap().is_empty());
+        assert_eq!(find(&rest, 3).unwrap(), third);
+        assert_eq!(remove(&rest, 1).unwrap(), (rest.clone(), false));
+    }
+}
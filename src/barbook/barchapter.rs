@@ -1,18 +1,27 @@
 use crate::BinaryStruct;
+use crate::barbook::annotations::{self, ChapterAnnotations, Heading};
+use crate::barbook::interlinear::{self, InterlinearWord};
+use crate::barbook::notes::{CrossReference, Note, NoteLine};
+use crate::cache::BlockCache;
 use crate::error::{BARFileError, BARResult};
+use crate::markup::{self, Span, Style};
 use compress::CompressionError;
 use rcsubstring::RcSubstring;
 use std::cell::RefCell;
 use std::fmt::Display;
 use std::io;
 use std::iter::Zip;
+use std::ops::Range;
 use std::rc::Rc;
 use std::time::{Duration, Instant};
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum CompressionAlgorithm {
     None,
+    #[cfg_attr(feature = "serde", serde(rename = "LZO"))]
     Lzo,
+    #[cfg_attr(feature = "serde", serde(rename = "ZLIB"))]
     ZLib,
     GZip,
     Unknown,
@@ -57,7 +66,7 @@ impl Display for CompressionAlgorithm {
 }
 
 #[allow(dead_code)]
-struct BlockHeaderV2 {
+pub(crate) struct BlockHeaderV2 {
     chapter_number: u8,
     start_verse: u8,
     end_verse: u8,
@@ -103,7 +112,7 @@ impl BinaryStruct for BlockHeaderV2 {
 }
 
 #[allow(dead_code)]
-struct BlockHeaderV1 {
+pub(crate) struct BlockHeaderV1 {
     chapter_number: u8,
     start_verse: u8,
     end_verse: u8,
@@ -153,11 +162,77 @@ macro_rules! header_value {
 }
 
 #[allow(dead_code)]
-enum BlockHeader {
+pub(crate) enum BlockHeader {
     Ver1(BlockHeaderV1),
     Ver2(BlockHeaderV2),
 }
 impl BlockHeader {
+    /// Size of the block header used by a given major file version
+    pub(crate) fn size_for_version(file_version: u8) -> BARResult<usize> {
+        match file_version {
+            1 => Ok(BlockHeaderV1::byte_size()),
+            2 => Ok(BlockHeaderV2::byte_size()),
+            _ => Err(CompressionError(
+                CompressionAlgorithm::Unknown,
+                "Unsuported Compression Algorithm".to_string(),
+            )
+            .into()),
+        }
+    }
+    pub(crate) fn from_bytes(buf: &[u8], file_version: u8) -> BARResult<Self> {
+        if buf.len() != Self::size_for_version(file_version)? {
+            return Err(BARFileError::InvalidFileFormat(
+                "Block header is the wrong size".to_string(),
+            ));
+        }
+        match file_version {
+            1 => Ok(BlockHeader::Ver1(BlockHeaderV1::from_bytes(buf))),
+            _ => Ok(BlockHeader::Ver2(BlockHeaderV2::from_bytes(buf))),
+        }
+    }
+    pub(crate) fn new(
+        file_version: u8,
+        chapter_number: u8,
+        start_verse: u8,
+        end_verse: u8,
+        compression_algorithm: CompressionAlgorithm,
+        block_size: u32,
+    ) -> Self {
+        match file_version {
+            1 => BlockHeader::Ver1(BlockHeaderV1 {
+                chapter_number,
+                start_verse,
+                end_verse,
+                block_size,
+            }),
+            _ => BlockHeader::Ver2(BlockHeaderV2 {
+                chapter_number,
+                start_verse,
+                end_verse,
+                compression_algorithm,
+                block_size,
+            }),
+        }
+    }
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        match self {
+            BlockHeader::Ver1(header) => header.to_bytes(),
+            BlockHeader::Ver2(header) => header.to_bytes(),
+        }
+    }
+    pub(crate) fn entry(&self, file_offset: u32) -> BlockEntry {
+        let compression_algorithm = match self {
+            BlockHeader::Ver1(..) => CompressionAlgorithm::Lzo,
+            BlockHeader::Ver2(header) => header.compression_algorithm.clone(),
+        };
+        BlockEntry {
+            file_offset,
+            start_verse: self.start_verse(),
+            end_verse: self.end_verse(),
+            block_size: self.block_size(),
+            compression_algorithm,
+        }
+    }
     fn block_size(&self) -> u32 {
         header_value!(self, block_size)
     }
@@ -167,7 +242,7 @@ impl BlockHeader {
     fn end_verse(&self) -> u8 {
         header_value!(self, end_verse)
     }
-    fn chapter_number(&self) -> u8 {
+    pub(crate) fn chapter_number(&self) -> u8 {
         header_value!(self, chapter_number)
     }
     fn header_size(&self) -> usize {
@@ -178,47 +253,237 @@ impl BlockHeader {
     }
 }
 
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct ChapterDetails {
     pub number_of_blocks: u32,
     pub compression_algorithm: CompressionAlgorithm,
     pub compressed_size: u32,
     pub uncompressed_size: u32,
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            rename = "decompress_time_us",
+            with = "crate::serialize::duration_micros"
+        )
+    )]
     pub decompress_time: Duration,
 }
 
+/// Decompress the data of a block with the algorithm given in its header
+pub(crate) fn decompress_block(algorithm: &CompressionAlgorithm, data: &[u8]) -> BARResult<String> {
+    match algorithm {
+        CompressionAlgorithm::None => Ok(compress::none::decompress(data)?),
+        CompressionAlgorithm::Lzo => Ok(compress::lzo::decompress(data)?),
+        CompressionAlgorithm::GZip => Ok(compress::gzip::decompress(data)?),
+        CompressionAlgorithm::ZLib => Ok(compress::zlib::decompress(data)?),
+        CompressionAlgorithm::Unknown => Err(compress::CompressionError(
+            CompressionAlgorithm::Unknown,
+            "Unsupported compression algorithm".to_string(),
+        )
+        .into()),
+    }
+}
+
+/// Compress the text of a block with an algorithm that can be given in its header
+pub(crate) fn compress_block(algorithm: &CompressionAlgorithm, text: &str) -> BARResult<Vec<u8>> {
+    let data = text.as_bytes();
+    match algorithm {
+        CompressionAlgorithm::None => Ok(data.to_vec()),
+        CompressionAlgorithm::Lzo => Ok(compress::lzo::compress(data)?),
+        CompressionAlgorithm::GZip => Ok(compress::gzip::compress(data)?),
+        CompressionAlgorithm::ZLib => Ok(compress::zlib::compress(data)?),
+        CompressionAlgorithm::Unknown => Err(compress::CompressionError(
+            CompressionAlgorithm::Unknown,
+            "Unsupported compression algorithm".to_string(),
+        )
+        .into()),
+    }
+}
+
+/// Decompressed text of a block together with the byte range of each verse in it.
+///
+/// The ranges are worked out once when the block is decompressed so individual verses can be
+/// handed out as [`RcSubstring`]s without scanning the text again. If the block has
+/// [markup](crate::markup) the text is kept without it, and the marked up text is kept as well.
+pub struct BlockText {
+    text: Rc<String>,
+    verses: Vec<Range<usize>>,
+    markup: Option<(Rc<String>, Vec<Range<usize>>)>,
+}
+
+// Byte range of each line, with the same line splitting rules as str::lines()
+fn line_ranges(text: &str) -> Vec<Range<usize>> {
+    let mut lines: Vec<Range<usize>> = Vec::new();
+    let mut start = 0;
+    while start < text.len() {
+        let end = match text[start..].find('\n') {
+            Some(pos) => start + pos,
+            None => text.len(),
+        };
+        let next = end + 1;
+        let end = if text[start..end].ends_with('\r') {
+            end - 1
+        } else {
+            end
+        };
+        lines.push(start..end);
+        start = next;
+    }
+    lines
+}
+
+impl BlockText {
+    pub fn new(text: String) -> Self {
+        BlockText {
+            verses: line_ranges(&text),
+            text: Rc::new(text),
+            markup: None,
+        }
+    }
+
+    /// Text that may contain markup
+    pub fn with_markup(text: String) -> Self {
+        if !text.contains(markup::ESCAPE) {
+            return BlockText::new(text);
+        }
+        let marked_verses = line_ranges(&text);
+        // Strip each verse on its own so that a tag can never join two verses
+        let mut plain = String::with_capacity(text.len());
+        for range in &marked_verses {
+            plain.push_str(&markup::strip(&text[range.clone()]));
+            plain.push('\n');
+        }
+        BlockText {
+            markup: Some((Rc::new(text), marked_verses)),
+            ..BlockText::new(plain)
+        }
+    }
+
+    /// The whole decompressed text of the block
+    pub fn text(&self) -> Rc<String> {
+        Rc::clone(&self.text)
+    }
+
+    /// Number of verses in the block
+    pub fn len(&self) -> usize {
+        self.verses.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.verses.is_empty()
+    }
+
+    /// Verse `index` of the block, counting from zero
+    pub fn verse(&self, index: usize) -> Option<RcSubstring> {
+        let range = self.verses.get(index)?;
+        Some(RcSubstring::new(Rc::clone(&self.text), range.clone()))
+    }
+
+    /// Verse `index` of the block as styled spans
+    pub fn verse_spans(&self, index: usize) -> Option<Vec<Span>> {
+        match &self.markup {
+            Some((text, verses)) => Some(markup::spans(&text[verses.get(index)?.clone()])),
+            // Unmarked text is one plain span, even if it happens to look like markup
+            None => Some(vec![Span {
+                text: self.text[self.verses.get(index)?.clone()].to_string(),
+                style: Style::default(),
+                new_line: false,
+            }]),
+        }
+    }
+
+    /// Approximate number of bytes of memory used
+    pub fn byte_size(&self) -> usize {
+        let markup = match &self.markup {
+            Some((text, verses)) => text.len() + verses.len() * std::mem::size_of::<Range<usize>>(),
+            None => 0,
+        };
+        self.text.len() + self.verses.len() * std::mem::size_of::<Range<usize>>() + markup
+    }
+}
+
+fn missing_verse() -> BARFileError {
+    BARFileError::InvalidFileFormat(
+        "Unable to get verse from block that should have contained it".to_string(),
+    )
+}
+
+/// Location and verse range of one block of a chapter
+#[derive(Debug, Clone)]
+pub struct BlockEntry {
+    pub file_offset: u32,
+    pub start_verse: u8,
+    pub end_verse: u8,
+    pub block_size: u32,
+    pub compression_algorithm: CompressionAlgorithm,
+}
+
 #[allow(dead_code)]
 struct BARBlock<T> {
     reader: Rc<RefCell<T>>,
+    cache: Rc<RefCell<BlockCache>>,
     header: BlockHeader,
     file_offset: u32,
-    text: RefCell<Option<Rc<String>>>,
+    text: RefCell<Option<Rc<BlockText>>>,
     is_known_last: RefCell<bool>,
+    markup: bool,
 }
 #[allow(dead_code)]
 impl<T: io::Read + io::Seek> BARBlock<T> {
-    fn build(shared_reader: Rc<RefCell<T>>, file_offset: u32, file_version: u8) -> BARResult<Self> {
+    fn build(
+        shared_reader: Rc<RefCell<T>>,
+        cache: Rc<RefCell<BlockCache>>,
+        file_offset: u32,
+        file_version: u8,
+    ) -> BARResult<Self> {
         let reader = &mut *shared_reader.borrow_mut();
         reader.seek(io::SeekFrom::Start(u64::from(file_offset)))?;
-        let header: BlockHeader = match file_version {
-            1 => BlockHeader::Ver1(BlockHeaderV1::read_from(reader)?),
-            2 => BlockHeader::Ver2(BlockHeaderV2::read_from(reader)?),
-            _ => {
-                return Err(CompressionError(
-                    CompressionAlgorithm::Unknown,
-                    "Unsuported Compression Algorithm".to_string(),
-                )
-                .into());
-            }
-        };
+        let mut buf: Vec<u8> = vec![0; BlockHeader::size_for_version(file_version)?];
+        reader.read_exact(&mut buf[..])?;
+        let header = BlockHeader::from_bytes(&buf, file_version)?;
         Ok(BARBlock {
             reader: Rc::clone(&shared_reader),
+            cache,
             header,
             file_offset,
             text: RefCell::new(None),
             is_known_last: RefCell::new(false),
+            markup: false,
         })
     }
 
+    fn from_entry(
+        shared_reader: Rc<RefCell<T>>,
+        cache: Rc<RefCell<BlockCache>>,
+        chapter_number: u8,
+        entry: &BlockEntry,
+        file_version: u8,
+        markup: bool,
+    ) -> Self {
+        let header = BlockHeader::new(
+            file_version,
+            chapter_number,
+            entry.start_verse,
+            entry.end_verse,
+            entry.compression_algorithm.clone(),
+            entry.block_size,
+        );
+        BARBlock {
+            reader: shared_reader,
+            cache,
+            header,
+            file_offset: entry.file_offset,
+            text: RefCell::new(None),
+            is_known_last: RefCell::new(false),
+            markup,
+        }
+    }
+
+    fn entry(&self) -> BlockEntry {
+        self.header.entry(self.file_offset)
+    }
+
     fn data(&self) -> BARResult<Vec<u8>> {
         let reader = &mut *self.reader.borrow_mut();
         let header_size = match &self.header {
@@ -246,22 +511,32 @@ impl<T: io::Read + io::Seek> BARBlock<T> {
 
     fn decompress(&self) -> BARResult<String> {
         let data = self.data()?;
-        match self.compression_algorith() {
-            CompressionAlgorithm::None => Ok(compress::none::decompress(&data)?),
-            CompressionAlgorithm::Lzo => Ok(compress::lzo::decompress(&data)?),
-            CompressionAlgorithm::GZip => Ok(compress::gzip::decompress(&data)?),
-            CompressionAlgorithm::ZLib => Ok(compress::zlib::decompress(&data)?),
-            CompressionAlgorithm::Unknown => Err(compress::CompressionError(
-                CompressionAlgorithm::Unknown,
-                "Unsupported compression algorithm".to_string(),
-            )
-            .into()),
-        }
+        decompress_block(self.compression_algorith(), &data)
     }
 
     fn text(&self) -> BARResult<Rc<String>> {
+        Ok(self.block_text()?.text())
+    }
+
+    fn block_text(&self) -> BARResult<Rc<BlockText>> {
         if self.text.borrow().is_none() {
-            *self.text.borrow_mut() = Some(Rc::new(self.decompress()?));
+            // Another chapter object may already have decompressed this block
+            let cached = self.cache.borrow_mut().get(self.file_offset);
+            let text = match cached {
+                Some(text) => text,
+                None => {
+                    let text = self.decompress()?;
+                    let text = Rc::new(match self.markup {
+                        true => BlockText::with_markup(text),
+                        false => BlockText::new(text),
+                    });
+                    self.cache
+                        .borrow_mut()
+                        .insert(self.file_offset, Rc::clone(&text));
+                    text
+                }
+            };
+            *self.text.borrow_mut() = Some(text);
         }
         Ok(Rc::clone(self.text.borrow_mut().as_ref().unwrap()))
     }
@@ -289,7 +564,12 @@ impl<T: io::Read + io::Seek> BARBlock<T> {
         }
         let file_offset =
             self.file_offset + self.header.header_size() as u32 + self.header.block_size();
-        let next = BARBlock::build(Rc::clone(&self.reader), file_offset, self.file_version());
+        let next = BARBlock::build(
+            Rc::clone(&self.reader),
+            Rc::clone(&self.cache),
+            file_offset,
+            self.file_version(),
+        );
         if let Err(BARFileError::IOError(_)) = next {
             // Check if we are at the end of the file
             let reader = &mut *self.reader.borrow_mut();
@@ -315,17 +595,25 @@ impl<T: io::Read + io::Seek> BARBlock<T> {
 #[allow(dead_code)]
 pub struct BARChapter<T> {
     reader: Rc<RefCell<T>>,
+    cache: Rc<RefCell<BlockCache>>,
     book_number: u8,
     chapter_number: u8,
     file_version: u8,
     file_offset: u32,
-    current_block: RefCell<Option<BARBlock<T>>>,
+    directory: RefCell<Option<Rc<Vec<BlockEntry>>>>,
+    current_block: RefCell<Option<(u32, Rc<BlockText>)>>, // (file offset, text)
+    verse_count: Option<u8>,                              // from the book's verse count table
+    markup: bool,
+    annotations: Option<u32>, // file offset of the book's annotations section
+    notes: Option<u32>,       // file offset of the book's notes section
+    interlinear: Option<u32>, // file offset of the book's interlinear section
 }
 
 #[allow(dead_code)]
 impl<T: io::Read + io::Seek> BARChapter<T> {
     pub fn build(
         shared_reader: Rc<RefCell<T>>,
+        cache: Rc<RefCell<BlockCache>>,
         book_number: u8,
         chapter_number: u8,
         file_offset: u32,
@@ -333,14 +621,55 @@ impl<T: io::Read + io::Seek> BARChapter<T> {
     ) -> BARResult<Self> {
         Ok(BARChapter {
             reader: shared_reader,
+            cache,
             book_number,
             chapter_number,
             file_version,
             file_offset,
+            directory: RefCell::new(None),
             current_block: RefCell::new(None),
+            verse_count: None,
+            markup: false,
+            annotations: None,
+            notes: None,
+            interlinear: None,
         })
     }
 
+    /// Blocks may contain [markup](crate::markup)
+    pub(crate) fn with_markup(self, markup: bool) -> Self {
+        BARChapter { markup, ..self }
+    }
+
+    /// Headings and paragraphs are in the book's annotations section at this file offset
+    pub(crate) fn with_annotations(self, annotations: Option<u32>) -> Self {
+        BARChapter {
+            annotations,
+            ..self
+        }
+    }
+
+    /// Footnotes and cross references are in the book's notes section at this file offset
+    pub(crate) fn with_notes(self, notes: Option<u32>) -> Self {
+        BARChapter { notes, ..self }
+    }
+
+    /// Lexical data for the words is in the book's interlinear section at this file offset
+    pub(crate) fn with_interlinear(self, interlinear: Option<u32>) -> Self {
+        BARChapter {
+            interlinear,
+            ..self
+        }
+    }
+
+    /// Use a verse count stored in the book rather than reading the block headers
+    pub(crate) fn with_verse_count(self, verse_count: Option<u8>) -> Self {
+        BARChapter {
+            verse_count,
+            ..self
+        }
+    }
+
     pub fn chapter_number(&self) -> u8 {
         self.chapter_number
     }
@@ -349,101 +678,269 @@ impl<T: io::Read + io::Seek> BARChapter<T> {
         self.book_number
     }
 
+    /// The blocks that make up this chapter in file order.
+    ///
+    /// The block headers are only walked the first time the directory is needed for a chapter.
+    /// After that it is kept in the archive-wide cache, so building the same chapter again does
+    /// not touch the file.
+    pub fn blocks(&self) -> BARResult<Rc<Vec<BlockEntry>>> {
+        if let Some(directory) = self.directory.borrow().as_ref() {
+            return Ok(Rc::clone(directory));
+        }
+        let cached = self.cache.borrow().directory(self.file_offset);
+        let directory = match cached {
+            Some(directory) => directory,
+            None => {
+                let directory = Rc::new(self.read_directory()?);
+                self.cache
+                    .borrow_mut()
+                    .insert_directory(self.file_offset, Rc::clone(&directory));
+                directory
+            }
+        };
+        *self.directory.borrow_mut() = Some(Rc::clone(&directory));
+        Ok(directory)
+    }
+
     pub fn chapter_text(&self) -> BARResult<Rc<String>> {
-        self.fetch_first_block()?;
-        let mut result = self.current_block.borrow().as_ref().unwrap().text()?;
-        while self.fetch_next_block()? {
-            Rc::make_mut(&mut result)
-                .push_str(&self.current_block.borrow().as_ref().unwrap().text()?);
+        let directory = self.blocks()?;
+        let mut result = Rc::new(String::new());
+        for entry in directory.iter() {
+            let text = self.block(entry).text()?;
+            if result.is_empty() {
+                result = text;
+            } else {
+                Rc::make_mut(&mut result).push_str(&text);
+            }
         }
         Ok(result)
     }
 
-    pub fn verse_text(&self, num: u32) -> BARResult<String> {
-        if self.current_block.borrow().is_none()
-            || self.current_block.borrow().as_ref().unwrap().start_verse() as u32 > num
-        {
-            self.fetch_first_block()?;
+    pub fn verse_text(&self, num: u32) -> BARResult<RcSubstring> {
+        let (text, index) = self.verse_location(num)?;
+        text.verse(index).ok_or_else(missing_verse)
+    }
+
+    /// The verse split into styled spans. For archives without markup this is a single span
+    /// of plain text.
+    pub fn verse_spans(&self, num: u32) -> BARResult<Vec<Span>> {
+        let (text, index) = self.verse_location(num)?;
+        text.verse_spans(index).ok_or_else(missing_verse)
+    }
+
+    /// Section headings in verse order. Empty for archives from before version 2.5.
+    pub fn headings(&self) -> BARResult<Vec<Heading>> {
+        Ok(self.annotations()?.headings)
+    }
+
+    /// The verses that start a new paragraph, in order. Empty for archives from before
+    /// version 2.5.
+    pub fn paragraphs(&self) -> BARResult<Vec<u8>> {
+        Ok(self.annotations()?.paragraphs)
+    }
+
+    fn annotations(&self) -> BARResult<ChapterAnnotations> {
+        let Some(offset) = self.annotations else {
+            return Ok(ChapterAnnotations::default());
+        };
+        let reader = &mut *self.reader.borrow_mut();
+        reader.seek(io::SeekFrom::Start(u64::from(offset)))?;
+        let mut buf: [u8; 4] = [0; 4];
+        reader.read_exact(&mut buf)?;
+        let mut section: Vec<u8> = vec![0; u32::from_le_bytes(buf) as usize];
+        reader.read_exact(&mut section)?;
+        annotations::find(&section, self.chapter_number)
+    }
+
+    /// Footnotes on a verse in the order they come in the verse. Empty for archives from
+    /// before version 2.6.
+    pub fn notes(&self, verse: u8) -> BARResult<Vec<Note>> {
+        let mut result: Vec<Note> = Vec::new();
+        for line in self.note_lines(verse)? {
+            if let NoteLine::Note(_, note) = line {
+                result.push(note);
+            }
+        }
+        Ok(result)
+    }
+
+    /// Cross references from a verse. Empty for archives from before version 2.6.
+    pub fn cross_references(&self, verse: u8) -> BARResult<Vec<CrossReference>> {
+        let mut result: Vec<CrossReference> = Vec::new();
+        for line in self.note_lines(verse)? {
+            if let NoteLine::CrossReference(_, cross_reference) = line {
+                result.push(cross_reference);
+            }
+        }
+        Ok(result)
+    }
+
+    fn note_lines(&self, verse: u8) -> BARResult<Vec<NoteLine>> {
+        let Some(offset) = self.notes else {
+            return Ok(Vec::new());
+        };
+        let mut result: Vec<NoteLine> = Vec::new();
+        for line in self.section_lines(offset, &format!("{} {} ", self.chapter_number, verse))? {
+            result.push(NoteLine::parse(&line)?);
+        }
+        Ok(result)
+    }
+
+    /// The words of a verse with their Strong's numbers, lemmas and morphology codes. Empty
+    /// for verses without interlinear data and archives from before version 2.7.
+    pub fn interlinear(&self, verse: u8) -> BARResult<Vec<InterlinearWord>> {
+        Ok(self
+            .interlinear_verses()?
+            .into_iter()
+            .find(|(number, _)| *number == verse)
+            .map(|(_, words)| words)
+            .unwrap_or_default())
+    }
+
+    /// (verse, words) for each verse of the chapter with interlinear data
+    pub(crate) fn interlinear_verses(&self) -> BARResult<Vec<(u8, Vec<InterlinearWord>)>> {
+        let Some(offset) = self.interlinear else {
+            return Ok(Vec::new());
+        };
+        let mut result: Vec<(u8, Vec<InterlinearWord>)> = Vec::new();
+        for line in self.section_lines(offset, &format!("{} ", self.chapter_number))? {
+            let (_, verse, words) = interlinear::parse(&line)?;
+            let text = self.verse_text(u32::from(verse))?;
+            result.push((verse, interlinear::align(&text, words)?));
+        }
+        Ok(result)
+    }
+
+    // Lines of a compressed text section of the book that start with prefix
+    fn section_lines(&self, offset: u32, prefix: &str) -> BARResult<Vec<RcSubstring>> {
+        // The decompressed section is cached like block text
+        let cached = self.cache.borrow_mut().get(offset);
+        let text = match cached {
+            Some(text) => text,
+            None => {
+                let (algorithm, data) = {
+                    let reader = &mut *self.reader.borrow_mut();
+                    reader.seek(io::SeekFrom::Start(u64::from(offset)))?;
+                    let mut buf: [u8; 5] = [0; 5];
+                    reader.read_exact(&mut buf)?;
+                    let mut size: [u8; 4] = [0; 4];
+                    size.copy_from_slice(&buf[1..5]);
+                    let mut data: Vec<u8> = vec![0; u32::from_le_bytes(size) as usize];
+                    reader.read_exact(&mut data)?;
+                    (CompressionAlgorithm::from(buf[0]), data)
+                };
+                let text = Rc::new(BlockText::new(decompress_block(&algorithm, &data)?));
+                self.cache.borrow_mut().insert(offset, Rc::clone(&text));
+                text
+            }
+        };
+        let mut result: Vec<RcSubstring> = Vec::new();
+        for index in 0..text.len() {
+            let line = text.verse(index).ok_or_else(missing_verse)?;
+            if line.starts_with(prefix) {
+                result.push(line);
+            }
         }
-        while u32::from(self.current_block.borrow().as_ref().unwrap().end_verse()) < num {
-            let ok = self.fetch_next_block()?;
-            if !ok {
-                let book = super::BOOK_NAMES[self.book_number as usize - 1];
+        Ok(result)
+    }
+
+    // Block text holding a verse and the index of the verse in it
+    fn verse_location(&self, num: u32) -> BARResult<(Rc<BlockText>, usize)> {
+        let directory = self.blocks()?;
+        // Blocks are in verse order so binary search for the first one ending at or after num
+        let index = directory.partition_point(|entry| u32::from(entry.end_verse) < num);
+        let entry = match directory.get(index) {
+            Some(entry) if u32::from(entry.start_verse) <= num => entry,
+            _ => {
                 return Err(BARFileError::ReferenceError(format!(
                     "Could not retrieve verse {} for chapter {} in {}",
-                    num, self.chapter_number, book
+                    num,
+                    self.chapter_number,
+                    super::book_name(self.book_number)
                 )));
             }
-        }
-        let index = num - self.current_block.borrow().as_ref().unwrap().start_verse() as u32;
-        let verse = self
-            .current_block
-            .borrow()
-            .as_ref()
-            .unwrap()
-            .text()?
-            .lines()
-            .nth(index as usize)
-            .map(|str| str.to_owned());
+        };
+        let text = self.current_text(entry)?;
+        let index = num - u32::from(entry.start_verse);
+        Ok((text, index as usize))
+    }
 
-        match verse {
-            None => Err(BARFileError::InvalidFileFormat(
-                "Unable to get verse from block that should have contained it".to_string(),
-            )),
-            Some(text) => Ok(text),
+    /// The verses from `first` to `last` inclusive
+    pub fn verse_range(&self, first: u32, last: u32) -> BARResult<Vec<RcSubstring>> {
+        let directory = self.blocks()?;
+        let last_verse = u32::from(directory.last().ok_or_else(|| self.no_blocks())?.end_verse);
+        let first_verse = u32::from(directory[0].start_verse);
+        if first > last || first < first_verse || last > last_verse {
+            return Err(BARFileError::ReferenceError(format!(
+                "Could not retrieve verses {}-{} for chapter {} in {}",
+                first,
+                last,
+                self.chapter_number,
+                super::book_name(self.book_number)
+            )));
         }
+        let mut result: Vec<RcSubstring> = Vec::new();
+        let index = directory.partition_point(|entry| u32::from(entry.end_verse) < first);
+        for entry in &directory[index..] {
+            if u32::from(entry.start_verse) > last {
+                break;
+            }
+            let text = self.current_text(entry)?;
+            let start = first.max(u32::from(entry.start_verse));
+            let end = last.min(u32::from(entry.end_verse));
+            for num in start..=end {
+                let verse = text.verse((num - u32::from(entry.start_verse)) as usize);
+                match verse {
+                    None => {
+                        return Err(BARFileError::InvalidFileFormat(
+                            "Unable to get verse from block that should have contained it"
+                                .to_string(),
+                        ));
+                    }
+                    Some(verse) => result.push(verse),
+                }
+            }
+        }
+        Ok(result)
     }
 
     pub fn verses<'a>(&'a self) -> BARChapterIterator<'a, T> {
         BARChapterIterator {
             chapter: self,
-            block: None,
+            block_index: 0,
             text: None,
-            newline_pos: 0,
+            verse_index: 0,
+        }
+    }
+
+    /// Like [`BARChapter::verses`] but a block that cannot be read, or that holds fewer verses
+    /// than its header says, is reported as an error rather than ending the iteration
+    pub fn try_verses<'a>(&'a self) -> TryBARChapterIterator<'a, T> {
+        TryBARChapterIterator {
+            chapter: self,
+            directory: None,
+            block_index: 0,
+            text: None,
+            verse_index: 0,
+            finished: false,
         }
     }
 
     pub fn details(&self) -> BARResult<ChapterDetails> {
-        self.fetch_first_block()?;
-        let compression_algorithm = self
-            .current_block
-            .borrow()
-            .as_ref()
-            .unwrap()
-            .compression_algorith()
-            .clone();
-        let mut compressed_size: u32 = self
-            .current_block
-            .borrow()
-            .as_ref()
-            .unwrap()
-            .header
-            .block_size();
-        let now = Instant::now();
-        self.current_block
-            .borrow_mut()
-            .as_ref()
-            .unwrap()
-            .decompress()?;
-        let mut decompress_time = now.elapsed();
-        let mut uncompressed_size: u32 =
-            self.current_block.borrow().as_ref().unwrap().text()?.len() as u32;
-        let mut number_of_blocks = 1;
-        while self.fetch_next_block()? {
-            number_of_blocks += 1;
-            compressed_size += self
-                .current_block
-                .borrow()
-                .as_ref()
-                .unwrap()
-                .header
-                .block_size();
+        let directory = self.blocks()?;
+        let compression_algorithm = directory[0].compression_algorithm.clone();
+        let mut compressed_size: u32 = 0;
+        let mut uncompressed_size: u32 = 0;
+        let mut decompress_time = Duration::ZERO;
+        for entry in directory.iter() {
+            compressed_size += entry.block_size;
+            // Always decompress rather than use text() so the timing is not skewed by the block cache
             let now = Instant::now();
-            uncompressed_size += self.current_block.borrow().as_ref().unwrap().text()?.len() as u32;
+            uncompressed_size += self.block(entry).decompress()?.len() as u32;
             decompress_time += now.elapsed();
         }
         Ok(ChapterDetails {
-            number_of_blocks,
+            number_of_blocks: directory.len() as u32,
             compression_algorithm,
             compressed_size,
             uncompressed_size,
@@ -454,123 +951,410 @@ impl<T: io::Read + io::Seek> BARChapter<T> {
     pub fn enumerated_verses<'a>(
         &'a self,
     ) -> Zip<std::ops::RangeFrom<u8>, BARChapterIterator<'a, T>> {
-        let _ = self.fetch_first_block();
-        let start = self.current_block.borrow().as_ref().unwrap().start_verse();
-        (start..).zip(BARChapterIterator {
-            chapter: self,
-            block: None,
-            text: None,
-            newline_pos: 0,
-        })
+        let start = match self.blocks() {
+            Ok(directory) => directory[0].start_verse,
+            Err(_) => 1,
+        };
+        (start..).zip(self.verses())
     }
 
     fn first_block(&self) -> BARResult<BARBlock<T>> {
-        BARBlock::build(Rc::clone(&self.reader), self.file_offset, self.file_version)
+        BARBlock::build(
+            Rc::clone(&self.reader),
+            Rc::clone(&self.cache),
+            self.file_offset,
+            self.file_version,
+        )
     }
 
-    fn fetch_first_block(&self) -> BARResult<()> {
-        if self.current_block.borrow().is_none()
-            || self.current_block.borrow().as_ref().unwrap().start_verse() != 0u8
-        {
-            *self.current_block.borrow_mut() = Some(self.first_block()?);
+    fn read_directory(&self) -> BARResult<Vec<BlockEntry>> {
+        let mut directory: Vec<BlockEntry> = Vec::new();
+        let first = self.first_block()?;
+        if first.header.chapter_number() != self.chapter_number {
+            return Err(BARFileError::InvalidFileFormat(format!(
+                "Chapter index points to a block of chapter {} not chapter {}",
+                first.header.chapter_number(),
+                self.chapter_number
+            )));
+        }
+        let mut block = Some(first);
+        while let Some(current) = block {
+            directory.push(current.entry());
+            block = current.next_block()?;
         }
-        Ok(())
+        Ok(directory)
     }
 
-    fn fetch_next_block(&self) -> BARResult<bool> {
-        let next = self.current_block.borrow().as_ref().unwrap().next_block()?;
-        if next.is_none() {
-            return Ok(false);
+    // Text of the block for entry, keeping hold of it in case the next lookup is in the same block
+    fn current_text(&self, entry: &BlockEntry) -> BARResult<Rc<BlockText>> {
+        if let Some((file_offset, text)) = self.current_block.borrow().as_ref()
+            && *file_offset == entry.file_offset
+        {
+            return Ok(Rc::clone(text));
         }
-        *self.current_block.borrow_mut() = next;
-        Ok(true)
+        let text = self.block(entry).block_text()?;
+        *self.current_block.borrow_mut() = Some((entry.file_offset, Rc::clone(&text)));
+        Ok(text)
+    }
+
+    /// Decompress one of the blocks of this chapter without going through the block cache
+    pub(crate) fn decompress_uncached(&self, entry: &BlockEntry) -> BARResult<String> {
+        self.block(entry).decompress()
+    }
+
+    fn block(&self, entry: &BlockEntry) -> BARBlock<T> {
+        BARBlock::from_entry(
+            Rc::clone(&self.reader),
+            Rc::clone(&self.cache),
+            self.chapter_number,
+            entry,
+            self.file_version,
+            self.markup,
+        )
     }
 
     pub fn number_of_verses(&self) -> BARResult<u8> {
-        if self.current_block.borrow().is_none() {
-            self.fetch_first_block()?;
+        if let Some(count) = self.verse_count {
+            return Ok(count);
         }
-        while self.fetch_next_block()? {}
-        Ok(self.current_block.borrow().as_ref().unwrap().end_verse())
+        let directory = self.blocks()?;
+        let last = directory.last().ok_or_else(|| self.no_blocks())?;
+        Ok(last.end_verse)
+    }
+
+    fn no_blocks(&self) -> BARFileError {
+        BARFileError::InvalidFileFormat(format!(
+            "Chapter {} of {} has no blocks",
+            self.chapter_number,
+            super::book_name(self.book_number)
+        ))
     }
 }
 
 #[allow(dead_code)]
 pub struct BARChapterIterator<'a, T> {
     chapter: &'a BARChapter<T>,
-    block: Option<BARBlock<T>>,
-    text: Option<Rc<String>>,
-    newline_pos: usize,
+    block_index: usize,
+    text: Option<Rc<BlockText>>,
+    verse_index: usize,
 }
 impl<'a, T: io::Seek + io::Read> Iterator for BARChapterIterator<'a, T> {
     type Item = RcSubstring;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.block.is_none() {
-            // We don't have a block
-            // See if we can take the first block from the chapter
-            if self.chapter.current_block.borrow().is_some()
-                && self
-                    .chapter
-                    .current_block
-                    .borrow()
-                    .as_ref()
-                    .unwrap()
-                    .start_verse()
-                    == 0
-            {
-                self.block = self.chapter.current_block.borrow_mut().take();
-            } else {
-                // Chapter doesn't have the first block
-                // Fetch it
-                let block = self.chapter.first_block();
-                if block.is_err() {
-                    return None;
-                }
-                self.block = Some(block.unwrap());
+        loop {
+            if self.text.is_none() {
+                // Unpack the text of the next block in the directory
+                let directory = self.chapter.blocks().ok()?;
+                let entry = directory.get(self.block_index)?;
+                let text = self.chapter.current_text(entry).ok()?;
+                self.text = Some(text);
+                self.verse_index = 0;
             }
-        }
-        if self.text.is_none() {
-            let text = self.block.as_ref().unwrap().text();
-            if text.is_err() {
-                return None;
+            let verse = self.text.as_ref().unwrap().verse(self.verse_index);
+            if verse.is_some() {
+                self.verse_index += 1;
+                return verse;
             }
-            self.text = Some(text.unwrap());
-        }
-        let start = self.newline_pos;
-        let next_newline = &self.text.as_ref().unwrap()[start..].find("\n");
-        if next_newline.is_some() {
-            let next_newline = start + next_newline.unwrap();
-            self.newline_pos = next_newline + 1;
-            return Some(RcSubstring::new(
-                Rc::clone(self.text.as_ref().unwrap()),
-                start..next_newline,
-            ));
+            // Reached the end of this block
+            // Move on to the next one
+            self.text = None;
+            self.block_index += 1;
         }
-        // Reached the end of this block
-        // See if there is another one
-        let next = self.block.as_ref().unwrap().next_block();
-        match next {
-            Err(_) => return None,
-            Ok(None) => return None,
-            Ok(block @ Some(_)) => self.block = block,
-        }
-        // Unpack the new text
-        let text = self.block.as_ref().unwrap().text();
-        if text.is_err() {
+    }
+}
+
+#[allow(dead_code)]
+pub(crate) mod compress;
+
+pub struct TryBARChapterIterator<'a, T> {
+    chapter: &'a BARChapter<T>,
+    directory: Option<Rc<Vec<BlockEntry>>>,
+    block_index: usize,
+    text: Option<Rc<BlockText>>,
+    verse_index: usize,
+    finished: bool,
+}
+
+impl<'a, T: io::Seek + io::Read> Iterator for TryBARChapterIterator<'a, T> {
+    type Item = BARResult<RcSubstring>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.finished {
             return None;
         }
-        self.text = Some(text.unwrap());
-        let next_newline = &self.text.as_ref().unwrap().find("\n");
-        if next_newline.is_some() {
-            let next_newline = next_newline.unwrap();
-            let res = RcSubstring::new(Rc::clone(self.text.as_ref().unwrap()), 0..next_newline);
-            self.newline_pos = next_newline + 1;
-            return Some(res);
+        if self.directory.is_none() {
+            // Without the directory there is no way to carry on
+            match self.chapter.blocks() {
+                Ok(directory) => self.directory = Some(directory),
+                Err(err) => {
+                    self.finished = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+        let directory = Rc::clone(self.directory.as_ref().unwrap());
+        loop {
+            let entry = directory.get(self.block_index)?;
+            if self.text.is_none() {
+                match self.chapter.current_text(entry) {
+                    Ok(text) => {
+                        self.text = Some(text);
+                        self.verse_index = 0;
+                    }
+                    Err(err) => {
+                        // Skip the block that could not be read
+                        self.block_index += 1;
+                        return Some(Err(err));
+                    }
+                }
+            }
+            let text = self.text.as_ref().unwrap();
+            if let Some(verse) = text.verse(self.verse_index) {
+                self.verse_index += 1;
+                return Some(Ok(verse));
+            }
+            // Reached the end of this block. Check it held all the verses it should have.
+            let expected = usize::from(entry.end_verse.saturating_sub(entry.start_verse)) + 1;
+            let found = text.len();
+            self.text = None;
+            self.block_index += 1;
+            if found < expected {
+                return Some(Err(BARFileError::InvalidFileFormat(format!(
+                    "Block for verses {}-{} of chapter {} only holds {} verses",
+                    entry.start_verse, entry.end_verse, self.chapter.chapter_number, found
+                ))));
+            }
         }
-        None
     }
 }
 
-#[allow(dead_code)]
-mod compress;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // Chapter 3 split over three uncompressed blocks followed by the start of chapter 4
+    fn test_chapter() -> BARChapter<Cursor<Vec<u8>>> {
+        let mut buf: Vec<u8> = Vec::new();
+        for (start_verse, end_verse, text) in [
+            (1, 2, "One\nTwo\n"),
+            (3, 5, "Three\nFour\nFive\n"),
+            (6, 6, "Six\n"),
+        ] {
+            let header = BlockHeaderV2 {
+                chapter_number: 3,
+                start_verse,
+                end_verse,
+                compression_algorithm: CompressionAlgorithm::None,
+                block_size: text.len() as u32,
+            };
+            buf.append(&mut header.to_bytes());
+            buf.extend_from_slice(text.as_bytes());
+        }
+        let header = BlockHeaderV2 {
+            chapter_number: 4,
+            start_verse: 1,
+            end_verse: 1,
+            compression_algorithm: CompressionAlgorithm::None,
+            block_size: 4,
+        };
+        buf.append(&mut header.to_bytes());
+        buf.extend_from_slice(b"Next");
+        BARChapter::build(
+            Rc::new(RefCell::new(Cursor::new(buf))),
+            Rc::new(RefCell::new(BlockCache::default())),
+            1,
+            3,
+            0,
+            2,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_block_directory() {
+        let chapter = test_chapter();
+        let blocks = chapter.blocks().unwrap();
+        assert_eq!(blocks.len(), 3);
+        assert_eq!(blocks[0].file_offset, 0);
+        assert_eq!(blocks[1].file_offset, 16);
+        assert_eq!(blocks[2].file_offset, 40);
+        assert_eq!((blocks[1].start_verse, blocks[1].end_verse), (3, 5));
+        assert_eq!(blocks[2].block_size, 4);
+        assert!(matches!(
+            blocks[2].compression_algorithm,
+            CompressionAlgorithm::None
+        ));
+        assert_eq!(chapter.number_of_verses().unwrap(), 6);
+        assert_eq!(chapter.details().unwrap().number_of_blocks, 3);
+    }
+
+    #[test]
+    fn test_multi_block_verses() {
+        let chapter = test_chapter();
+        assert_eq!(&*chapter.verse_text(6).unwrap(), "Six");
+        assert_eq!(&*chapter.verse_text(1).unwrap(), "One");
+        assert_eq!(&*chapter.verse_text(4).unwrap(), "Four");
+        assert_eq!(&*chapter.verse_text(3).unwrap(), "Three");
+        assert!(chapter.verse_text(7).is_err());
+        let verses: Vec<String> = chapter.verses().map(|v| v.to_string()).collect();
+        assert_eq!(verses, vec!["One", "Two", "Three", "Four", "Five", "Six"]);
+        assert_eq!(
+            chapter.chapter_text().unwrap().as_str(),
+            "One\nTwo\nThree\nFour\nFive\nSix\n"
+        );
+    }
+
+    #[test]
+    fn test_verse_range() {
+        let chapter = test_chapter();
+        let verses: Vec<String> = chapter
+            .verse_range(2, 6)
+            .unwrap()
+            .iter()
+            .map(|v| v.to_string())
+            .collect();
+        assert_eq!(verses, vec!["Two", "Three", "Four", "Five", "Six"]);
+        assert_eq!(chapter.verse_range(4, 4).unwrap().len(), 1);
+        assert!(chapter.verse_range(0, 2).is_err());
+        assert!(chapter.verse_range(5, 7).is_err());
+        assert!(chapter.verse_range(3, 2).is_err());
+    }
+
+    #[test]
+    fn test_block_text() {
+        let text = BlockText::new("One\r\n\nThree".to_string());
+        assert_eq!(text.len(), 3);
+        assert_eq!(&*text.verse(0).unwrap(), "One");
+        assert_eq!(&*text.verse(1).unwrap(), "");
+        assert_eq!(&*text.verse(2).unwrap(), "Three");
+        assert!(text.verse(3).is_none());
+        assert!(BlockText::new(String::new()).is_empty());
+    }
+
+    #[test]
+    fn test_try_verses() {
+        let chapter = test_chapter();
+        let verses: Vec<String> = chapter
+            .try_verses()
+            .map(|verse| verse.unwrap().to_string())
+            .collect();
+        assert_eq!(verses, vec!["One", "Two", "Three", "Four", "Five", "Six"]);
+
+        // Second block is truncated and the third can not be decompressed
+        let mut buf: Vec<u8> = Vec::new();
+        for (start_verse, end_verse, compression_algorithm, text) in [
+            (1, 2, CompressionAlgorithm::None, "One\nTwo\n"),
+            (3, 5, CompressionAlgorithm::None, "Three\n"),
+            (6, 6, CompressionAlgorithm::Unknown, "Six\n"),
+            (7, 7, CompressionAlgorithm::None, "Seven\n"),
+        ] {
+            let header = BlockHeaderV2 {
+                chapter_number: 3,
+                start_verse,
+                end_verse,
+                compression_algorithm,
+                block_size: text.len() as u32,
+            };
+            buf.append(&mut header.to_bytes());
+            buf.extend_from_slice(text.as_bytes());
+        }
+        let chapter = BARChapter::build(
+            Rc::new(RefCell::new(Cursor::new(buf))),
+            Rc::new(RefCell::new(BlockCache::default())),
+            1,
+            3,
+            0,
+            2,
+        )
+        .unwrap();
+        let verses: Vec<BARResult<RcSubstring>> = chapter.try_verses().collect();
+        assert_eq!(verses.len(), 6);
+        assert_eq!(&**verses[2].as_ref().unwrap(), "Three");
+        assert!(matches!(verses[3], Err(BARFileError::InvalidFileFormat(_))));
+        assert!(matches!(verses[4], Err(BARFileError::CompressionError(_))));
+        assert_eq!(&**verses[5].as_ref().unwrap(), "Seven");
+        // The plain iterator just stops
+        assert_eq!(chapter.verses().count(), 3);
+
+        // A chapter index entry pointing at the wrong chapter
+        let chapter = BARChapter::build(
+            Rc::clone(&chapter.reader),
+            Rc::new(RefCell::new(BlockCache::default())),
+            1,
+            4,
+            0,
+            2,
+        )
+        .unwrap();
+        let verses: Vec<BARResult<RcSubstring>> = chapter.try_verses().collect();
+        assert_eq!(verses.len(), 1);
+        assert!(verses[0].is_err());
+    }
+
+    #[test]
+    fn test_verse_spans() {
+        let text = "In the beginning \x1BkGod\x1BK created\nAnd the earth \x1Biwas\x1BI void\n";
+        let mut buf = BlockHeaderV2 {
+            chapter_number: 1,
+            start_verse: 1,
+            end_verse: 2,
+            compression_algorithm: CompressionAlgorithm::None,
+            block_size: text.len() as u32,
+        }
+        .to_bytes();
+        buf.extend_from_slice(text.as_bytes());
+        let reader = Rc::new(RefCell::new(Cursor::new(buf)));
+        let chapter = |markup: bool| {
+            BARChapter::build(
+                Rc::clone(&reader),
+                Rc::new(RefCell::new(BlockCache::default())),
+                1,
+                1,
+                0,
+                2,
+            )
+            .unwrap()
+            .with_markup(markup)
+        };
+
+        // A book number from a damaged index is an error message, not a panic
+        let unknown = BARChapter::build(
+            Rc::clone(&reader),
+            Rc::new(RefCell::new(BlockCache::default())),
+            0,
+            1,
+            0,
+            2,
+        )
+        .unwrap();
+        assert!(unknown.verse_text(3).is_err());
+        assert!(unknown.verse_range(1, 3).is_err());
+
+        let marked = chapter(true);
+        assert_eq!(
+            &*marked.verse_text(1).unwrap(),
+            "In the beginning God created"
+        );
+        assert_eq!(
+            marked.chapter_text().unwrap().as_str(),
+            "In the beginning God created\nAnd the earth was void\n"
+        );
+        let spans = marked.verse_spans(2).unwrap();
+        assert_eq!(spans.len(), 3);
+        assert_eq!(spans[1].text, "was");
+        assert!(spans[1].style.italic);
+        assert!(marked.verse_spans(1).unwrap()[1].style.small_caps);
+
+        // A stray escape at the end of a verse does not join it to the next one
+        let text = BlockText::with_markup("In the beginning\x1B\nAnd the earth\n".to_string());
+        assert_eq!(text.len(), 2);
+        assert_eq!(&*text.verse(1).unwrap(), "And the earth");
+
+        // Archives from before markup was added are left alone
+        let plain = chapter(false);
+        assert!(plain.verse_text(1).unwrap().contains('\x1B'));
+        assert_eq!(plain.verse_spans(1).unwrap().len(), 1);
+    }
+}
@@ -42,7 +42,8 @@ pub mod lzo {
     use crate::barbook::barchapter::CompressionAlgorithm;
     use lzokay_native;
     const ALGORITHM: CompressionAlgorithm = CompressionAlgorithm::Lzo;
-    const MAX_SIZE: u32 = 100 * 1024;
+    /// Largest block of text that will be decompressed
+    pub(crate) const MAX_SIZE: u32 = 100 * 1024;
     const FIRST_BYTE: u8 = 241;
     use super::CompressionError;
 
@@ -68,14 +69,9 @@ pub mod lzo {
             ));
         }
 
-        let result = lzokay_native::decompress_all(&data[5..], Some(decompressed_size as usize));
-        if result.is_err() {
-            return Err(CompressionError(
-                ALGORITHM,
-                format!("{}", result.unwrap_err()),
-            ));
-        }
-        let decompressed = result.unwrap();
+        let decompressed =
+            lzokay_native::decompress_all(&data[5..], Some(decompressed_size as usize))
+                .map_err(|err| CompressionError(ALGORITHM, format!("{}", err)))?;
         if decompressed_size as usize != decompressed.len() {
             return Err(CompressionError(
                 ALGORITHM,
@@ -91,14 +87,8 @@ pub mod lzo {
 
     pub fn compress(data: &[u8]) -> Result<Vec<u8>> {
         let uncompressed_size: u32 = data.len() as u32;
-        let result = lzokay_native::compress(data);
-        if result.is_err() {
-            return Err(CompressionError(
-                ALGORITHM,
-                format!("{}", result.unwrap_err()),
-            ));
-        }
-        let mut compressed = result.unwrap();
+        let mut compressed = lzokay_native::compress(data)
+            .map_err(|err| CompressionError(ALGORITHM, format!("{}", err)))?;
         let mut lzo_data: Vec<u8> = Vec::new();
         lzo_data.push(FIRST_BYTE);
         lzo_data.append(&mut uncompressed_size.to_be_bytes().to_vec());
@@ -121,10 +111,10 @@ pub mod zlib {
         let mut decoder = ZlibDecoder::new(data);
         let mut decompressed = String::new();
         let result = decoder.read_to_string(&mut decompressed);
-        if result.is_err() {
+        if let Err(err) = result {
             return Err(CompressionError(
                 ALGORITHM,
-                format!("Decompression error: {}", result.unwrap_err()),
+                format!("Decompression error: {}", err),
             ));
         }
         Ok(decompressed)
@@ -133,10 +123,10 @@ pub mod zlib {
     pub fn compress(data: &[u8]) -> Result<Vec<u8>> {
         let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
         let result = encoder.write_all(data);
-        if result.is_err() {
+        if let Err(err) = result {
             return Err(CompressionError(
                 ALGORITHM,
-                format!("Compression error: {}", result.unwrap_err()),
+                format!("Compression error: {}", err),
             ));
         }
         match encoder.finish() {
@@ -153,32 +143,33 @@ pub mod gzip {
     use super::Result;
     use crate::barbook::barchapter::CompressionAlgorithm;
     use flate2::read::GzDecoder;
-    use flate2::write::GzEncoder;
     const ALGORITHM: CompressionAlgorithm = CompressionAlgorithm::GZip;
     use super::CompressionError;
-    use flate2::Compression;
+    use flate2::{Compression, GzBuilder};
     use std::io::{Read, Write};
 
     pub fn decompress(data: &[u8]) -> Result<String> {
         let mut decoder = GzDecoder::new(data);
         let mut decompressed = String::new();
         let result = decoder.read_to_string(&mut decompressed);
-        if result.is_err() {
-            return Err(CompressionError(
-                ALGORITHM,
-                format!("{}", result.unwrap_err()),
-            ));
+        if let Err(err) = result {
+            return Err(CompressionError(ALGORITHM, format!("{}", err)));
         }
         Ok(decompressed)
     }
 
     pub fn compress(data: &[u8]) -> Result<Vec<u8>> {
-        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        // No time stamp and an unknown operating system so the same text always compresses
+        // to the same bytes
+        let mut encoder = GzBuilder::new()
+            .mtime(0)
+            .operating_system(255)
+            .write(Vec::new(), Compression::default());
         let result = encoder.write_all(data);
-        if result.is_err() {
+        if let Err(err) = result {
             return Err(CompressionError(
                 ALGORITHM,
-                format!("Compression error: {}", result.unwrap_err()),
+                format!("Compression error: {}", err),
             ));
         }
         match encoder.finish() {
@@ -0,0 +1,232 @@
+//! Per-word lexical data for original-language archives (format version 2.7 on).
+//!
+//! The interlinear data of a book is kept as text in one compressed section after its last
+//! block, found through an offset stored after the notes offset. Each line of the text is one
+//! verse:
+//!
+//! ```text
+//! <chapter> <verse> <strongs>|<lemma>|<morph><TAB><strongs>|<lemma>|<morph>...
+//! ```
+//!
+//! There is one entry for each of the [`words`] of the plain verse text, in order. Fields that
+//! are not known are left empty.
+
+use crate::error::{BARFileError, BARResult};
+
+/// A word of a verse with its lexical data
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct InterlinearWord {
+    /// The word as it is in the verse text
+    pub word: String,
+    pub lemma: Option<String>,
+    /// Strong's number, eg. G3056 or H430
+    pub strongs: Option<String>,
+    /// Morphology code
+    pub morph: Option<String>,
+}
+
+impl InterlinearWord {
+    /// True when the word has the Strong's number. Numbers are compared ignoring case and
+    /// leading zeros, and a word may have several numbers separated by `/` or `,`.
+    pub fn has_strongs(&self, strongs: &str) -> bool {
+        let strongs = normalize_strongs(strongs);
+        self.strongs.as_deref().is_some_and(|numbers| {
+            numbers
+                .split(['/', ',', ' '])
+                .any(|number| normalize_strongs(number) == strongs)
+        })
+    }
+}
+
+// G0976 and g976 are the same number
+fn normalize_strongs(strongs: &str) -> String {
+    let strongs = strongs.trim().to_uppercase();
+    let digits = strongs
+        .find(|c: char| c.is_ascii_digit())
+        .unwrap_or(strongs.len());
+    let (prefix, number) = strongs.split_at(digits);
+    let number = number.trim_start_matches('0');
+    format!("{}{}", prefix, number)
+}
+
+// Punctuation kept next to a word in the verse text
+fn is_punctuation(c: char) -> bool {
+    c.is_ascii_punctuation()
+        || matches!(
+            c,
+            '\u{00B7}' // middle dot
+                | '\u{0387}' // Greek ano teleia
+                | '\u{037E}' // Greek question mark
+                | '\u{05C3}' // Hebrew sof pasuq
+                | '\u{00B6}' // pilcrow
+                | '\u{2013}'
+                | '\u{2014}'
+                | '\u{2018}'
+                | '\u{2019}'
+                | '\u{201C}'
+                | '\u{201D}'
+                | '\u{00AB}'
+                | '\u{00BB}'
+                | '\u{2026}'
+        )
+}
+
+/// The words of plain verse text that interlinear data lines up with. The text is split at
+/// white space and the Hebrew maqaf, and punctuation is trimmed from each end of a word. Runs
+/// of punctuation on their own are not words.
+pub fn words(text: &str) -> Vec<&str> {
+    text.split(|c: char| c.is_whitespace() || c == '\u{05BE}')
+        .map(|word| word.trim_matches(is_punctuation))
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+fn invalid(line: &str) -> BARFileError {
+    BARFileError::InvalidFileFormat(format!("Invalid interlinear line: {}", line))
+}
+
+fn field(text: &str) -> Option<String> {
+    match text {
+        "" => None,
+        text => Some(text.to_string()),
+    }
+}
+
+/// Parse a line of the interlinear section into (chapter, verse, words). The words do not have
+/// their text until they are [aligned](align) with the verse.
+pub(crate) fn parse(line: &str) -> BARResult<(u8, u8, Vec<InterlinearWord>)> {
+    let mut parts = line.splitn(3, ' ');
+    let mut next = || parts.next().ok_or_else(|| invalid(line));
+    let chapter: u8 = next()?.parse().map_err(|_| invalid(line))?;
+    let verse: u8 = next()?.parse().map_err(|_| invalid(line))?;
+    let mut words: Vec<InterlinearWord> = Vec::new();
+    for entry in next()?.split('\t') {
+        let mut fields = entry.split('|');
+        let (Some(strongs), Some(lemma), Some(morph), None) =
+            (fields.next(), fields.next(), fields.next(), fields.next())
+        else {
+            return Err(invalid(line));
+        };
+        words.push(InterlinearWord {
+            word: String::new(),
+            lemma: field(lemma),
+            strongs: field(strongs),
+            morph: field(morph),
+        });
+    }
+    Ok((chapter, verse, words))
+}
+
+/// Give each word its text from the plain verse text
+pub(crate) fn align(
+    verse_text: &str,
+    mut words: Vec<InterlinearWord>,
+) -> BARResult<Vec<InterlinearWord>> {
+    let text = self::words(verse_text);
+    if text.len() != words.len() {
+        return Err(BARFileError::InvalidFileFormat(format!(
+            "Verse has {} words but interlinear data for {}",
+            text.len(),
+            words.len()
+        )));
+    }
+    for (word, text) in words.iter_mut().zip(text) {
+        word.word = text.to_string();
+    }
+    Ok(words)
+}
+
+/// The line for the interlinear section. The words must line up with the plain verse text.
+pub(crate) fn encode(
+    chapter: u8,
+    verse: u8,
+    verse_text: &str,
+    words: &[InterlinearWord],
+) -> BARResult<String> {
+    let text = self::words(verse_text);
+    if text.len() != words.len()
+        || text
+            .iter()
+            .zip(words)
+            .any(|(text, word)| *text != word.word)
+    {
+        return Err(BARFileError::InvalidFileFormat(format!(
+            "Interlinear words of chapter {} verse {} do not match the verse text",
+            chapter, verse
+        )));
+    }
+    let mut entries: Vec<String> = Vec::new();
+    for word in words {
+        let fields = [&word.strongs, &word.lemma, &word.morph].map(|field| field.as_deref());
+        let fields = fields.map(|field| field.unwrap_or(""));
+        if fields
+            .iter()
+            .any(|field| field.contains(['|', '\t', '\n', '\r']))
+        {
+            return Err(BARFileError::InvalidFileFormat(format!(
+                "Interlinear data for {} in chapter {} verse {} contains a separator",
+                word.word, chapter, verse
+            )));
+        }
+        entries.push(fields.join("|"));
+    }
+    Ok(format!("{} {} {}", chapter, verse, entries.join("\t")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn word(word: &str, strongs: &str, lemma: &str, morph: &str) -> InterlinearWord {
+        InterlinearWord {
+            word: word.to_string(),
+            lemma: field(lemma),
+            strongs: field(strongs),
+            morph: field(morph),
+        }
+    }
+
+    #[test]
+    fn test_words() {
+        assert_eq!(
+            words("Ἐν ἀρχῇ ἦν ὁ λόγος, — καὶ"),
+            vec!["Ἐν", "ἀρχῇ", "ἦν", "ὁ", "λόγος", "καὶ"]
+        );
+        assert_eq!(words("אֶת־הָאָרֶץ׃"), vec!["אֶת", "הָאָרֶץ"]);
+    }
+
+    #[test]
+    fn test_lines() {
+        let verse = "Ἐν ἀρχῇ ἦν ὁ λόγος,";
+        let words = vec![
+            word("Ἐν", "G1722", "ἐν", "PREP"),
+            word("ἀρχῇ", "G746", "ἀρχή", "N-DSF"),
+            word("ἦν", "G2258", "εἰμί", "V-IAI-3S"),
+            word("ὁ", "G3588", "ὁ", "T-NSM"),
+            word("λόγος", "G3056", "", ""),
+        ];
+        let line = encode(1, 1, verse, &words).unwrap();
+        assert!(line.starts_with("1 1 G1722|ἐν|PREP\tG746|ἀρχή|N-DSF\t"));
+        assert!(line.ends_with("\tG3056||"));
+        let (chapter, verse_number, parsed) = parse(&line).unwrap();
+        assert_eq!((chapter, verse_number), (1, 1));
+        assert_eq!(parsed[4].lemma, None);
+        assert_eq!(align(verse, parsed).unwrap(), words);
+
+        assert!(encode(1, 1, verse, &words[..4]).is_err());
+        assert!(encode(1, 1, "Ἐν ἀρχῇ ἦν ὁ λόγοι", &words).is_err());
+        assert!(align("Ἐν ἀρχῇ", words.clone()).is_err());
+        assert!(parse("1 1 G1722|ἐν").is_err());
+        assert!(parse("1 x G1722|ἐν|PREP").is_err());
+    }
+
+    #[test]
+    fn test_has_strongs() {
+        let word = word("εἰς", "G0976/G1519", "", "");
+        assert!(word.has_strongs("g976"));
+        assert!(word.has_strongs("G1519"));
+        assert!(!word.has_strongs("G97"));
+        assert!(!InterlinearWord::default().has_strongs("G976"));
+    }
+}
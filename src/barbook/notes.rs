@@ -0,0 +1,223 @@
+//! Footnotes and cross references (format version 2.6 on).
+//!
+//! The notes of a book are kept as text in one compressed section after its last block, found
+//! through an offset stored after the annotations offset. Each line of the text is one note:
+//!
+//! ```text
+//! <chapter> <verse> <offset> f <footnote text>
+//! <chapter> <verse> <offset> x <reference>;<reference>...
+//! ```
+//!
+//! The offset is the character in the plain verse text the note is attached to, or `-` for the
+//! whole verse. References are `book.chapter.verse` or `book.chapter.verse-book.chapter.verse`
+//! with books numbered 1=Genesis 66=Revelation.
+
+use crate::error::{BARFileError, BARResult};
+use crate::reference::{Passage, Reference};
+
+/// A translator's footnote
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Note {
+    pub verse: u8,
+    /// Character in the plain verse text the note is attached to. None for the whole verse.
+    pub offset: Option<u32>,
+    pub text: String,
+}
+
+/// Passages referred to from a verse
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CrossReference {
+    pub verse: u8,
+    /// Character in the plain verse text the reference is attached to. None for the whole verse.
+    pub offset: Option<u32>,
+    pub targets: Vec<Passage>,
+}
+
+/// A line of the notes section
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum NoteLine {
+    Note(u8, Note), // (chapter, note)
+    CrossReference(u8, CrossReference),
+}
+
+fn invalid(line: &str) -> BARFileError {
+    BARFileError::InvalidFileFormat(format!("Invalid note: {}", line))
+}
+
+fn parse_reference(text: &str) -> Option<Reference> {
+    let mut parts = text.split('.').map(|part| part.parse::<u8>().ok());
+    let reference = Reference::new(parts.next()??, parts.next()??, parts.next()??);
+    match parts.next() {
+        None => Some(reference),
+        Some(_) => None,
+    }
+}
+
+fn format_reference(reference: &Reference) -> String {
+    format!(
+        "{}.{}.{}",
+        reference.book, reference.chapter, reference.verse
+    )
+}
+
+impl NoteLine {
+    pub(crate) fn parse(line: &str) -> BARResult<Self> {
+        let mut parts = line.splitn(5, ' ');
+        let mut next = || parts.next().ok_or_else(|| invalid(line));
+        let chapter: u8 = next()?.parse().map_err(|_| invalid(line))?;
+        let verse: u8 = next()?.parse().map_err(|_| invalid(line))?;
+        let offset = match next()? {
+            "-" => None,
+            offset => Some(offset.parse().map_err(|_| invalid(line))?),
+        };
+        let kind = next()?;
+        let content = next()?;
+        match kind {
+            "f" => Ok(NoteLine::Note(
+                chapter,
+                Note {
+                    verse,
+                    offset,
+                    text: content.to_string(),
+                },
+            )),
+            "x" => {
+                let mut targets: Vec<Passage> = Vec::new();
+                for target in content.split(';') {
+                    let passage = match target.split_once('-') {
+                        None => parse_reference(target).map(Passage::verse),
+                        Some((start, end)) => parse_reference(start)
+                            .zip(parse_reference(end))
+                            .map(|(start, end)| Passage::new(start, end)),
+                    };
+                    targets.push(passage.ok_or_else(|| invalid(line))?);
+                }
+                Ok(NoteLine::CrossReference(
+                    chapter,
+                    CrossReference {
+                        verse,
+                        offset,
+                        targets,
+                    },
+                ))
+            }
+            _ => Err(invalid(line)),
+        }
+    }
+
+    pub(crate) fn verse(&self) -> u8 {
+        match self {
+            NoteLine::Note(_, note) => note.verse,
+            NoteLine::CrossReference(_, cross_reference) => cross_reference.verse,
+        }
+    }
+
+    /// The line for the notes section, or an error if it can not be written as one line
+    pub(crate) fn encode(&self) -> BARResult<String> {
+        let (chapter, verse, offset, kind, content) = match self {
+            NoteLine::Note(chapter, note) => {
+                if note.text.contains(['\n', '\r']) {
+                    return Err(BARFileError::InvalidFileFormat(format!(
+                        "Footnote on chapter {} verse {} contains a line break",
+                        chapter, note.verse
+                    )));
+                }
+                (*chapter, note.verse, note.offset, 'f', note.text.clone())
+            }
+            NoteLine::CrossReference(chapter, cross_reference) => {
+                if cross_reference.targets.is_empty() {
+                    return Err(BARFileError::InvalidFileFormat(format!(
+                        "Cross reference on chapter {} verse {} has no targets",
+                        chapter, cross_reference.verse
+                    )));
+                }
+                let targets: Vec<String> = cross_reference
+                    .targets
+                    .iter()
+                    .map(|passage| match passage.start == passage.end {
+                        true => format_reference(&passage.start),
+                        false => format!(
+                            "{}-{}",
+                            format_reference(&passage.start),
+                            format_reference(&passage.end)
+                        ),
+                    })
+                    .collect();
+                (
+                    *chapter,
+                    cross_reference.verse,
+                    cross_reference.offset,
+                    'x',
+                    targets.join(";"),
+                )
+            }
+        };
+        let offset = match offset {
+            Some(offset) => offset.to_string(),
+            None => "-".to_string(),
+        };
+        Ok(format!(
+            "{} {} {} {} {}",
+            chapter, verse, offset, kind, content
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_note_lines() {
+        let note = NoteLine::Note(
+            1,
+            Note {
+                verse: 2,
+                offset: Some(41),
+                text: "Or empty".to_string(),
+            },
+        );
+        assert_eq!(note.encode().unwrap(), "1 2 41 f Or empty");
+        assert_eq!(NoteLine::parse("1 2 41 f Or empty").unwrap(), note);
+
+        let cross_reference = NoteLine::CrossReference(
+            1,
+            CrossReference {
+                verse: 1,
+                offset: None,
+                targets: vec![
+                    Passage::verse(Reference::new(43, 1, 1)),
+                    Passage::new(Reference::new(58, 11, 3), Reference::new(58, 11, 4)),
+                ],
+            },
+        );
+        let line = cross_reference.encode().unwrap();
+        assert_eq!(line, "1 1 - x 43.1.1;58.11.3-58.11.4");
+        assert_eq!(NoteLine::parse(&line).unwrap(), cross_reference);
+        assert_eq!(cross_reference.verse(), 1);
+
+        assert!(NoteLine::parse("1 1 - x 43.1").is_err());
+        assert!(NoteLine::parse("1 1 - q text").is_err());
+        assert!(NoteLine::parse("1 1").is_err());
+        let note = NoteLine::Note(
+            1,
+            Note {
+                verse: 1,
+                offset: None,
+                text: "Two\nlines".to_string(),
+            },
+        );
+        assert!(note.encode().is_err());
+        let cross_reference = NoteLine::CrossReference(
+            1,
+            CrossReference {
+                verse: 1,
+                offset: None,
+                targets: Vec::new(),
+            },
+        );
+        assert!(cross_reference.encode().is_err());
+    }
+}
@@ -0,0 +1,208 @@
+//! Archive-wide cache of decompressed block text.
+//!
+//! Every [`BARFile`](crate::BARFile) owns one [`BlockCache`] which is shared with all the books
+//! and chapters built from it. Blocks are keyed by their file offset so the same block fetched
+//! through two different [`BARChapter`](crate::barbook::barchapter::BARChapter) objects is only
+//! decompressed once. When the total size of the cached text exceeds the byte budget the least
+//! recently used blocks are evicted.
+//!
+//! The cache also keeps the block directory of each chapter it has seen, keyed by the file
+//! offset of the chapter. Directories are small so they are not counted against the budget.
+
+use crate::barbook::barchapter::{BlockEntry, BlockText};
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
+/// Default byte budget for the decompressed block cache (1 MiB)
+pub const DEFAULT_CACHE_BUDGET: usize = 1024 * 1024;
+
+/// Snapshot of the cache counters
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+    pub used_bytes: usize,
+    pub budget: usize,
+}
+
+struct CacheEntry {
+    text: Rc<BlockText>,
+    last_used: u64,
+}
+
+pub struct BlockCache {
+    budget: usize,
+    used_bytes: usize,
+    tick: u64,
+    entries: HashMap<u32, CacheEntry>,
+    recency: BTreeMap<u64, u32>, // last_used tick -> file offset
+    directories: HashMap<u32, Rc<Vec<BlockEntry>>>,
+    hits: u64,
+    misses: u64,
+}
+
+impl Default for BlockCache {
+    fn default() -> Self {
+        BlockCache::new(DEFAULT_CACHE_BUDGET)
+    }
+}
+
+impl BlockCache {
+    pub fn new(budget: usize) -> Self {
+        BlockCache {
+            budget,
+            used_bytes: 0,
+            tick: 0,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            directories: HashMap::new(),
+            hits: 0,
+            misses: 0,
+        }
+    }
+
+    /// Look up the text of the block at `file_offset`, counting a hit or a miss
+    pub fn get(&mut self, file_offset: u32) -> Option<Rc<BlockText>> {
+        self.tick += 1;
+        let tick = self.tick;
+        match self.entries.get_mut(&file_offset) {
+            Some(entry) => {
+                self.recency.remove(&entry.last_used);
+                self.recency.insert(tick, file_offset);
+                entry.last_used = tick;
+                self.hits += 1;
+                Some(Rc::clone(&entry.text))
+            }
+            None => {
+                self.misses += 1;
+                None
+            }
+        }
+    }
+
+    /// Add the decompressed text of the block at `file_offset`.
+    /// Text larger than the whole budget is not cached.
+    pub fn insert(&mut self, file_offset: u32, text: Rc<BlockText>) {
+        self.remove(file_offset);
+        let size = text.byte_size();
+        if size > self.budget {
+            return;
+        }
+        self.tick += 1;
+        self.used_bytes += size;
+        self.recency.insert(self.tick, file_offset);
+        self.entries.insert(
+            file_offset,
+            CacheEntry {
+                text,
+                last_used: self.tick,
+            },
+        );
+        self.evict();
+    }
+
+    /// Change the byte budget, evicting blocks if the cache is now over it
+    pub fn set_budget(&mut self, budget: usize) {
+        self.budget = budget;
+        self.evict();
+    }
+
+    /// The block directory of the chapter starting at `file_offset`
+    pub fn directory(&self, file_offset: u32) -> Option<Rc<Vec<BlockEntry>>> {
+        self.directories.get(&file_offset).map(Rc::clone)
+    }
+
+    pub fn insert_directory(&mut self, file_offset: u32, directory: Rc<Vec<BlockEntry>>) {
+        self.directories.insert(file_offset, directory);
+    }
+
+    /// Drop all cached blocks and directories. The hit and miss counters are kept.
+    pub fn clear(&mut self) {
+        self.directories.clear();
+        self.entries.clear();
+        self.recency.clear();
+        self.used_bytes = 0;
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits,
+            misses: self.misses,
+            entries: self.entries.len(),
+            used_bytes: self.used_bytes,
+            budget: self.budget,
+        }
+    }
+
+    fn remove(&mut self, file_offset: u32) {
+        if let Some(entry) = self.entries.remove(&file_offset) {
+            self.recency.remove(&entry.last_used);
+            self.used_bytes -= entry.text.byte_size();
+        }
+    }
+
+    fn evict(&mut self) {
+        while self.used_bytes > self.budget {
+            let oldest = match self.recency.first_key_value() {
+                Some((_, file_offset)) => *file_offset,
+                None => break,
+            };
+            self.remove(oldest);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Block with a single verse taking up len bytes in the cache
+    fn text(len: usize) -> Rc<BlockText> {
+        let verse_size = std::mem::size_of::<std::ops::Range<usize>>();
+        Rc::new(BlockText::new("x".repeat(len - verse_size)))
+    }
+
+    #[test]
+    fn test_hits_and_misses() {
+        let mut cache = BlockCache::new(100);
+        assert!(cache.get(10).is_none());
+        cache.insert(10, text(20));
+        assert_eq!(cache.get(10).unwrap().byte_size(), 20);
+        let stats = cache.stats();
+        assert_eq!(stats.hits, 1);
+        assert_eq!(stats.misses, 1);
+        assert_eq!(stats.entries, 1);
+        assert_eq!(stats.used_bytes, 20);
+    }
+
+    #[test]
+    fn test_evicts_least_recently_used() {
+        let mut cache = BlockCache::new(100);
+        cache.insert(1, text(40));
+        cache.insert(2, text(40));
+        // Touch block 1 so block 2 becomes the oldest
+        cache.get(1);
+        cache.insert(3, text(40));
+        assert!(cache.get(2).is_none());
+        assert!(cache.get(1).is_some());
+        assert!(cache.get(3).is_some());
+        assert_eq!(cache.stats().used_bytes, 80);
+    }
+
+    #[test]
+    fn test_budget() {
+        let mut cache = BlockCache::new(20);
+        cache.insert(1, text(30));
+        assert_eq!(cache.stats().entries, 0);
+        cache.set_budget(100);
+        cache.insert(1, text(50));
+        cache.insert(2, text(50));
+        cache.set_budget(60);
+        assert_eq!(cache.stats().entries, 1);
+        assert!(cache.get(2).is_some());
+        cache.set_budget(0);
+        assert_eq!(cache.stats().entries, 0);
+        assert_eq!(cache.stats().used_bytes, 0);
+    }
+}
@@ -0,0 +1,420 @@
+//! C API for reading BARFiles from other languages (requires the `ffi` feature).
+//!
+//! Archives are passed around as opaque [`BarArchive`] handles which must be released with
+//! [`bar_close`]. Every string or array handed back to the caller is owned by the caller and has
+//! a matching free function. Functions that can fail return a [`BarStatus`]; the message for the
+//! last failure on the calling thread is available from [`bar_last_error`].
+//!
+//! The C header is generated with `cbindgen --config cbindgen.toml --output include/biblearchive.h`.
+
+use crate::barbook::barchapter::BARChapter;
+use crate::{BARFile, BARFileError, BARResult, Reference};
+use std::cell::RefCell;
+use std::error::Error;
+use std::ffi::{CStr, CString, c_char};
+use std::fs::File;
+use std::io::{self, Cursor, Read, Seek};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+/// Status codes returned by the C API. These mirror the variants of `BARFileError`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BarStatus {
+    Ok = 0,
+    InvalidFileFormat = 1,
+    CompressionError = 2,
+    ReferenceError = 3,
+    IOError = 4,
+    /// A book, chapter or verse is not present in the archive
+    NotFound = 5,
+    /// A NULL pointer or invalid UTF-8 was passed in
+    InvalidArgument = 6,
+    /// Rust code panicked. The archive should not be used again.
+    Panic = 7,
+}
+
+impl From<&BARFileError> for BarStatus {
+    fn from(value: &BARFileError) -> Self {
+        match value {
+            BARFileError::InvalidFileFormat(_) => BarStatus::InvalidFileFormat,
+            BARFileError::CompressionError(_) => BarStatus::CompressionError,
+            BARFileError::ReferenceError(_) => BarStatus::ReferenceError,
+            BARFileError::IOError(_) => BarStatus::IOError,
+        }
+    }
+}
+
+trait ReadSeek: Read + Seek {}
+impl<T: Read + Seek> ReadSeek for T {}
+
+/// Opaque handle to an open archive
+pub struct BarArchive(BARFile<Box<dyn ReadSeek>>);
+
+/// A verse matching a search. `text` is a NUL terminated UTF-8 string.
+#[repr(C)]
+pub struct BarSearchHit {
+    pub book: u8,
+    pub chapter: u8,
+    pub verse: u8,
+    pub text: *mut c_char,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', "")).unwrap_or_default();
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some(message));
+}
+
+// Run the body of an API function, turning errors and panics into status codes
+fn guard(body: impl FnOnce() -> Result<(), (BarStatus, String)>) -> BarStatus {
+    match panic::catch_unwind(AssertUnwindSafe(body)) {
+        Ok(Ok(())) => BarStatus::Ok,
+        Ok(Err((status, message))) => {
+            set_last_error(message);
+            status
+        }
+        Err(_) => {
+            set_last_error("Panic in biblearchive".to_string());
+            BarStatus::Panic
+        }
+    }
+}
+
+fn open_error(err: Box<dyn Error>) -> (BarStatus, String) {
+    let status = if let Some(err) = err.downcast_ref::<BARFileError>() {
+        err.into()
+    } else if err.downcast_ref::<io::Error>().is_some() {
+        BarStatus::IOError
+    } else {
+        BarStatus::InvalidFileFormat
+    };
+    (status, err.to_string())
+}
+
+fn bar_error(err: BARFileError) -> (BarStatus, String) {
+    ((&err).into(), err.to_string())
+}
+
+fn not_found(message: String) -> (BarStatus, String) {
+    (BarStatus::NotFound, message)
+}
+
+fn null_argument() -> (BarStatus, String) {
+    (BarStatus::InvalidArgument, "NULL pointer argument".into())
+}
+
+unsafe fn handle<'a>(archive: *const BarArchive) -> Result<&'a BarArchive, (BarStatus, String)> {
+    unsafe { archive.as_ref() }.ok_or_else(null_argument)
+}
+
+unsafe fn input_str<'a>(value: *const c_char) -> Result<&'a str, (BarStatus, String)> {
+    if value.is_null() {
+        return Err(null_argument());
+    }
+    unsafe { CStr::from_ptr(value) }
+        .to_str()
+        .map_err(|_| (BarStatus::InvalidArgument, "String is not UTF-8".into()))
+}
+
+fn output_string(value: &str) -> *mut c_char {
+    CString::new(value.replace('\0', ""))
+        .unwrap_or_default()
+        .into_raw()
+}
+
+unsafe fn write_out<T>(out: *mut T, value: T) -> Result<(), (BarStatus, String)> {
+    if out.is_null() {
+        return Err(null_argument());
+    }
+    unsafe { out.write(value) };
+    Ok(())
+}
+
+/// Message describing the last failure on this thread, or NULL if there has not been one.
+/// The string is owned by the library and is valid until the next failing call on this thread.
+#[unsafe(no_mangle)]
+pub extern "C" fn bar_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| match last.borrow().as_ref() {
+        Some(message) => message.as_ptr(),
+        None => ptr::null(),
+    })
+}
+
+/// Open the archive at `path`.
+///
+/// # Safety
+/// `path` must be a NUL terminated string and `out` must be valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn bar_open(path: *const c_char, out: *mut *mut BarArchive) -> BarStatus {
+    guard(|| {
+        // Checked first so a NULL out does not leak the opened archive
+        if out.is_null() {
+            return Err(null_argument());
+        }
+        let path = unsafe { input_str(path)? };
+        let file = File::open(path).map_err(|err| (BarStatus::IOError, err.to_string()))?;
+        let reader: Box<dyn ReadSeek> = Box::new(file);
+        let bar = BARFile::read(reader).map_err(open_error)?;
+        unsafe { write_out(out, Box::into_raw(Box::new(BarArchive(bar)))) }
+    })
+}
+
+/// Open an archive held in memory. The data is copied so the buffer can be freed afterwards.
+///
+/// # Safety
+/// `data` must be valid for reads of `len` bytes and `out` must be valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn bar_open_memory(
+    data: *const u8,
+    len: usize,
+    out: *mut *mut BarArchive,
+) -> BarStatus {
+    guard(|| {
+        if data.is_null() || out.is_null() {
+            return Err(null_argument());
+        }
+        let bytes = unsafe { std::slice::from_raw_parts(data, len) }.to_vec();
+        let reader: Box<dyn ReadSeek> = Box::new(Cursor::new(bytes));
+        let bar = BARFile::read(reader).map_err(open_error)?;
+        unsafe { write_out(out, Box::into_raw(Box::new(BarArchive(bar)))) }
+    })
+}
+
+/// Close an archive opened with `bar_open` or `bar_open_memory`. NULL is ignored.
+///
+/// # Safety
+/// `archive` must have come from this library and must not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn bar_close(archive: *mut BarArchive) {
+    if !archive.is_null() {
+        drop(unsafe { Box::from_raw(archive) });
+    }
+}
+
+/// Number of books in the archive, or 0 if `archive` is NULL
+///
+/// # Safety
+/// `archive` must be NULL or a live handle.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn bar_book_count(archive: *const BarArchive) -> usize {
+    match unsafe { archive.as_ref() } {
+        Some(archive) => archive.0.number_of_books() as usize,
+        None => 0,
+    }
+}
+
+/// Write the numbers of the books in the archive (1=Genesis 66=Revelation) in canonical order
+/// into `books`, which has room for `capacity` entries. `count` is set to the number of books
+/// in the archive, which may be more than `capacity`.
+///
+/// # Safety
+/// `books` must be valid for writes of `capacity` bytes and `count` must be valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn bar_books(
+    archive: *const BarArchive,
+    books: *mut u8,
+    capacity: usize,
+    count: *mut usize,
+) -> BarStatus {
+    guard(|| {
+        let archive = unsafe { handle(archive)? };
+        let numbers: Vec<u8> = archive
+            .0
+            .books_in_order()
+            .map(|book| book.book_number())
+            .collect();
+        if capacity > 0 {
+            if books.is_null() {
+                return Err(null_argument());
+            }
+            let len = numbers.len().min(capacity);
+            unsafe { ptr::copy_nonoverlapping(numbers.as_ptr(), books, len) };
+        }
+        unsafe { write_out(count, numbers.len()) }
+    })
+}
+
+/// Full name of a book, eg. "Genesis". Free the result with `bar_string_free`.
+#[unsafe(no_mangle)]
+pub extern "C" fn bar_book_name(book: u8) -> *mut c_char {
+    output_string(crate::barbook::book_name(book))
+}
+
+/// Abbreviation of a book, eg. "Ge". Free the result with `bar_string_free`.
+#[unsafe(no_mangle)]
+pub extern "C" fn bar_book_abbrev(book: u8) -> *mut c_char {
+    output_string(crate::barbook::book_abbrev(book))
+}
+
+/// Number of chapters in a book
+///
+/// # Safety
+/// `archive` must be a live handle and `out` must be valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn bar_chapter_count(
+    archive: *const BarArchive,
+    book: u8,
+    out: *mut u8,
+) -> BarStatus {
+    guard(|| {
+        let archive = unsafe { handle(archive)? };
+        let book = archive
+            .0
+            .book(book)
+            .ok_or_else(|| not_found(format!("Book {} not in archive", book)))?;
+        unsafe { write_out(out, book.number_of_chapters()) }
+    })
+}
+
+/// Number of verses in a chapter
+///
+/// # Safety
+/// `archive` must be a live handle and `out` must be valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn bar_verse_count(
+    archive: *const BarArchive,
+    book: u8,
+    chapter: u8,
+    out: *mut u8,
+) -> BarStatus {
+    guard(|| {
+        let archive = unsafe { handle(archive)? };
+        let count = find_chapter(archive, book, chapter, |chapter| chapter.number_of_verses())?;
+        unsafe { write_out(out, count) }
+    })
+}
+
+/// Text of a single verse as UTF-8. Free the result with `bar_string_free`.
+///
+/// # Safety
+/// `archive` must be a live handle and `out` must be valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn bar_verse_text(
+    archive: *const BarArchive,
+    book: u8,
+    chapter: u8,
+    verse: u8,
+    out: *mut *mut c_char,
+) -> BarStatus {
+    guard(|| {
+        let archive = unsafe { handle(archive)? };
+        let text = find_chapter(archive, book, chapter, |chapter| {
+            chapter.verse_text(u32::from(verse))
+        })?;
+        unsafe { write_out(out, output_string(&text)) }
+    })
+}
+
+/// Text of a whole chapter as UTF-8, one verse per line. Free the result with `bar_string_free`.
+///
+/// # Safety
+/// `archive` must be a live handle and `out` must be valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn bar_chapter_text(
+    archive: *const BarArchive,
+    book: u8,
+    chapter: u8,
+    out: *mut *mut c_char,
+) -> BarStatus {
+    guard(|| {
+        let archive = unsafe { handle(archive)? };
+        let text = find_chapter(archive, book, chapter, |chapter| chapter.chapter_text())?;
+        unsafe { write_out(out, output_string(&text)) }
+    })
+}
+
+/// Free a string returned by this library. NULL is ignored.
+///
+/// # Safety
+/// `value` must have come from this library and must not be used afterwards.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn bar_string_free(value: *mut c_char) {
+    if !value.is_null() {
+        drop(unsafe { CString::from_raw(value) });
+    }
+}
+
+/// Find every verse containing `needle`. The hits are written to `hits` as an array of `count`
+/// entries which must be freed with `bar_search_free`.
+///
+/// # Safety
+/// `archive` must be a live handle, `needle` a NUL terminated string and `hits` and `count`
+/// must be valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn bar_search(
+    archive: *const BarArchive,
+    needle: *const c_char,
+    hits: *mut *mut BarSearchHit,
+    count: *mut usize,
+) -> BarStatus {
+    guard(|| {
+        let archive = unsafe { handle(archive)? };
+        let needle = unsafe { input_str(needle)? };
+        if hits.is_null() || count.is_null() {
+            return Err(null_argument());
+        }
+        let results: Box<[BarSearchHit]> = archive
+            .0
+            .search(needle)
+            .iter()
+            .map(|hit| {
+                let Reference {
+                    book,
+                    chapter,
+                    verse,
+                } = hit.reference;
+                BarSearchHit {
+                    book,
+                    chapter,
+                    verse,
+                    text: output_string(&hit.text),
+                }
+            })
+            .collect();
+        unsafe {
+            count.write(results.len());
+            hits.write(Box::into_raw(results) as *mut BarSearchHit);
+        }
+        Ok(())
+    })
+}
+
+/// Free the results of `bar_search`. NULL is ignored.
+///
+/// # Safety
+/// `hits` and `count` must be exactly as returned by `bar_search`.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn bar_search_free(hits: *mut BarSearchHit, count: usize) {
+    if hits.is_null() {
+        return;
+    }
+    let results = unsafe { Box::from_raw(ptr::slice_from_raw_parts_mut(hits, count)) };
+    for hit in results.iter() {
+        unsafe { bar_string_free(hit.text) };
+    }
+}
+
+fn find_chapter<R>(
+    archive: &BarArchive,
+    book: u8,
+    chapter: u8,
+    action: impl FnOnce(&BARChapter<Box<dyn ReadSeek>>) -> BARResult<R>,
+) -> Result<R, (BarStatus, String)> {
+    let book = archive
+        .0
+        .book(book)
+        .ok_or_else(|| not_found(format!("Book {} not in archive", book)))?;
+    let chapter_number = chapter;
+    let chapter = book.chapter(chapter).ok_or_else(|| {
+        not_found(format!(
+            "Chapter {} of {} not in archive",
+            chapter_number,
+            book.book_name()
+        ))
+    })?;
+    action(&chapter).map_err(bar_error)
+}
@@ -0,0 +1,62 @@
+//! A hash of the text of an archive.
+//!
+//! [`BARFile::fingerprint`] hashes the verse text of every book with SHA-256, so two archives
+//! built from the same text have the same fingerprint however their blocks are compressed or
+//! packed, and whatever their archive version or version abbreviation. Markup, annotations,
+//! notes and interlinear data are not part of it.
+
+use crate::BARFile;
+use crate::error::BARResult;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::io;
+
+/// SHA-256 of the verse text of an archive. Displayed as lower case hex.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Fingerprint([u8; 32]);
+
+impl Fingerprint {
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+}
+
+impl fmt::Display for Fingerprint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for byte in self.0 {
+            write!(f, "{:02x}", byte)?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: io::Read + io::Seek> BARFile<T> {
+    /// Hash the verse text of the archive. The books are taken in canonical order and each
+    /// verse is hashed as the line `<book> <chapter> <verse> <text>`.
+    pub fn fingerprint(&self) -> BARResult<Fingerprint> {
+        let mut books = self.try_books().collect::<BARResult<Vec<_>>>()?;
+        books.sort_by_key(|book| book.book_number());
+        let mut hasher = Sha256::new();
+        for book in books {
+            for chapter in book.try_chapters() {
+                let Some(chapter) = chapter? else {
+                    continue;
+                };
+                let start = chapter.blocks()?[0].start_verse;
+                for (verse, text) in (start..).zip(chapter.try_verses()) {
+                    hasher.update(
+                        format!(
+                            "{} {} {} {}\n",
+                            book.book_number(),
+                            chapter.chapter_number(),
+                            verse,
+                            &*text?
+                        )
+                        .as_bytes(),
+                    );
+                }
+            }
+        }
+        Ok(Fingerprint(hasher.finalize().into()))
+    }
+}
@@ -8,7 +8,7 @@
 //! let bar = BARFile::open("tests/data/KJV.ibar")?;
 //! if let Some(book) = bar.book_from_abbrev("Ge") {
 //!   if let Some(chapter) = book.chapter(1) {
-//!       verse = Some(chapter.verse_text(27)?);
+//!       verse = Some(chapter.verse_text(27)?.to_string());
 //!   }
 //! }
 //! assert_eq!(
@@ -19,20 +19,48 @@
 //! }
 //! ```
 
+use rcsubstring::RcSubstring;
 use std::cell::RefCell;
 use std::error::Error;
 use std::fmt;
 use std::fs::File;
-use std::io::{self, BufWriter, SeekFrom};
+use std::io::{self, SeekFrom};
 use std::path::Path;
 use std::rc::Rc;
 
 mod error;
+pub use error::{BARFileError, BARResult};
 
 pub mod barbook;
+pub mod cache;
 use barbook::BARBook;
+use cache::{BlockCache, CacheStats};
 
-const CURRENT_VERSION: (u8, u8) = (2, 2);
+#[cfg(feature = "async")]
+pub mod asyncbar;
+
+pub mod fingerprint;
+pub mod markup;
+pub mod navigator;
+pub mod random;
+pub mod readingplan;
+pub mod reference;
+pub use reference::{Passage, Reference};
+pub mod search;
+pub mod stats;
+pub mod verses;
+pub mod writer;
+
+#[cfg(feature = "serde")]
+mod serialize;
+
+#[cfg(feature = "ffi")]
+pub mod ffi;
+
+const CURRENT_VERSION: (u8, u8) = (2, 7);
+
+// Bytes available for the version abbreviation in the file header
+const MAX_VERSION_ABBREV: usize = 10;
 
 pub mod binarystruct;
 use binarystruct::BinaryStruct;
@@ -67,6 +95,7 @@ pub struct BARFile<T> {
     file: Rc<RefCell<T>>,
     header: BARFileHeader,
     book_index: Vec<BARBookIndexEntry>,
+    cache: Rc<RefCell<BlockCache>>,
 }
 
 impl BinaryStruct for BARFileHeader {
@@ -108,6 +137,116 @@ impl BinaryStruct for BARFileHeader {
     }
 }
 
+impl BARFileHeader {
+    /// A builder for the header of a new archive with the current version and room for 66 books
+    pub fn builder(version_abbrev: &str) -> BARFileHeaderBuilder {
+        BARFileHeaderBuilder {
+            header: BARFileHeader {
+                version_abbrev: version_abbrev.to_string(),
+                ..Default::default()
+            },
+        }
+    }
+
+    /// Check a header read from a file is one we can handle
+    fn check(&self) -> Result<(), String> {
+        if self.leader != [b'B', b'A', b'R'] {
+            return Err(format!(
+                "Invalid BAR file. Unexpected leader: {}",
+                String::from_utf8(self.leader.to_vec()).unwrap_or("???".to_string())
+            ));
+        }
+        if self.major_version > CURRENT_VERSION.0 {
+            return Err(format!(
+                "Unsupported future BARFile version: {}.{}",
+                self.major_version, self.minor_version
+            ));
+        }
+        self.validate()
+            .map_err(|msg| format!("Invalid BAR file. {}", msg))
+    }
+
+    /// Check the header is one that could be written. Later minor versions are allowed as
+    /// they only add to the format.
+    fn validate(&self) -> Result<(), String> {
+        if self.version_abbrev.is_empty() {
+            return Err("Version abbrev in header not specified or corrupt.".to_string());
+        }
+        if self.version_abbrev.len() > MAX_VERSION_ABBREV {
+            return Err(format!(
+                "Version abbrev {} is longer than {} bytes.",
+                self.version_abbrev, MAX_VERSION_ABBREV
+            ));
+        }
+        if self.version_abbrev.contains('\0') {
+            return Err("Version abbrev contains a NUL character.".to_string());
+        }
+        if self.major_version == 0 || self.major_version > CURRENT_VERSION.0 {
+            return Err(format!(
+                "Invalid BARFile version: {}.{}",
+                self.major_version, self.minor_version
+            ));
+        }
+        if self.number_of_books == 0 || self.number_of_books > 66 {
+            return Err(format!(
+                "Book capacity must be from 1 to 66. Got: {}",
+                self.number_of_books
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Builds the header of a new archive, checking each value
+///
+/// # Example
+/// ```rust
+/// use biblearchive::BARFileHeader;
+/// let header = BARFileHeader::builder("KJV").version(2, 3).book_capacity(27).build();
+/// assert!(header.is_ok());
+/// assert!(BARFileHeader::builder("Authorized Version").build().is_err());
+/// ```
+pub struct BARFileHeaderBuilder {
+    header: BARFileHeader,
+}
+
+impl BARFileHeaderBuilder {
+    /// The archive format version. It can not be later than the current version.
+    pub fn version(self, major_version: u8, minor_version: u8) -> Self {
+        BARFileHeaderBuilder {
+            header: BARFileHeader {
+                major_version,
+                minor_version,
+                ..self.header
+            },
+        }
+    }
+
+    /// The number of books the archive has room for, from 1 to 66
+    pub fn book_capacity(self, number_of_books: u8) -> Self {
+        BARFileHeaderBuilder {
+            header: BARFileHeader {
+                number_of_books,
+                ..self.header
+            },
+        }
+    }
+
+    /// The header, or an error if any value can not be written. The version abbreviation must
+    /// be 1 to 10 bytes of UTF-8 without NUL characters.
+    pub fn build(self) -> BARResult<BARFileHeader> {
+        let header = self.header;
+        header.validate().map_err(BARFileError::InvalidFileFormat)?;
+        if (header.major_version, header.minor_version) > CURRENT_VERSION {
+            return Err(BARFileError::InvalidFileFormat(format!(
+                "Can not write future BARFile version: {}.{}",
+                header.major_version, header.minor_version
+            )));
+        }
+        Ok(header)
+    }
+}
+
 impl Default for BARFileHeader {
     fn default() -> Self {
         let mut leader: [u8; 3] = [0; 3];
@@ -168,6 +307,7 @@ impl BARBookIndexEntry {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
 pub struct BARVersion(pub u8, pub u8);
 impl std::fmt::Display for BARVersion {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -175,6 +315,25 @@ impl std::fmt::Display for BARVersion {
     }
 }
 
+/// Summary of an archive and the books in it
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ArchiveMetadata {
+    pub archive_version: BARVersion,
+    pub bible_version: String,
+    pub book_capacity: u8,
+    pub books: Vec<BookMetadata>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BookMetadata {
+    pub book: u8, // (1=Gen 66=Rev)
+    pub abbrev: String,
+    pub name: String,
+    pub chapters: u8,
+}
+
 pub struct BARFileIterator<'a, T> {
     barfile: &'a BARFile<T>,
     index: u8,
@@ -190,6 +349,21 @@ impl<'a, T: io::Seek + io::Read> Iterator for BARFileIterator<'a, T> {
     }
 }
 
+pub struct TryBARFileIterator<'a, T> {
+    barfile: &'a BARFile<T>,
+    index: u8,
+}
+
+impl<'a, T: io::Seek + io::Read> Iterator for TryBARFileIterator<'a, T> {
+    type Item = BARResult<BARBook<T>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let current_index = self.index;
+        self.index += 1;
+        self.barfile.try_book_from_index(current_index).transpose()
+    }
+}
+
 pub struct SortedBARFileIterator<'a, T> {
     barfile: &'a BARFile<T>,
     sorted_indexes: Vec<u8>,
@@ -216,7 +390,7 @@ impl<'a, T> SortedBARFileIterator<'a, T> {
                 BARBookIndexEntry::Empty => break,
             }
         }
-        book_indexes.sort_by(|a, b| a.1.cmp(&b.1));
+        book_indexes.sort_by_key(|m| m.1);
         let sorted_indexes = book_indexes.iter().map(|m| m.0).collect();
         SortedBARFileIterator {
             barfile,
@@ -262,11 +436,7 @@ impl BARFile<File> {
         file_path: impl AsRef<Path>,
         version_abbrev: String,
     ) -> Result<Self, Box<dyn Error>> {
-        let default = BARFileHeader::default();
-        let header = BARFileHeader {
-            version_abbrev,
-            ..default
-        };
+        let header = BARFileHeader::builder(&version_abbrev).build()?;
         Self::create_with_options(file_path, header)
     }
 
@@ -274,16 +444,32 @@ impl BARFile<File> {
         file_path: impl AsRef<Path>,
         header: BARFileHeader,
     ) -> Result<Self, Box<dyn Error>> {
+        header.validate().map_err(BARFileError::InvalidFileFormat)?;
         let file = File::create_new(file_path)?;
-        let mut writer = BufWriter::new(file);
-        header.write_to(&mut writer)?;
+        Ok(Self::create_in(file, header)?)
+    }
+}
+
+impl<T: io::Write + io::Seek> BARFile<T> {
+    /// Start an empty archive in anything that can be written and seeked, such as a
+    /// `Cursor<Vec<u8>>`. The archive is written from the start of the target. Books are added
+    /// with [`writer::BARWriter::write_to`] or, if the target can also be read, with the
+    /// editing methods.
+    pub fn create_in(mut target: T, header: BARFileHeader) -> BARResult<Self> {
+        header.validate().map_err(BARFileError::InvalidFileFormat)?;
         let book_index = Self::new_book_index(header.number_of_books);
-        BARBookIndexEntry::write_array(&book_index, &mut writer)?;
-        let file = writer.into_inner().unwrap();
+        let mut bytes = header.to_bytes();
+        for entry in &book_index {
+            bytes.append(&mut entry.to_bytes());
+        }
+        target.seek(SeekFrom::Start(0))?;
+        target.write_all(&bytes)?;
+        target.flush()?;
         Ok(Self {
-            file: Rc::new(RefCell::new(file)),
+            file: Rc::new(RefCell::new(target)),
             header,
             book_index,
+            cache: Rc::new(RefCell::new(BlockCache::default())),
         })
     }
 }
@@ -316,6 +502,36 @@ impl<T> BARFile<T> {
         self.header.number_of_books as i32
     }
 
+    /// Statistics for the decompressed block cache shared by all books and chapters
+    pub fn cache_stats(&self) -> CacheStats {
+        self.cache.borrow().stats()
+    }
+
+    /// Set the maximum number of bytes of decompressed text to keep cached.
+    /// A budget of zero disables the cache.
+    pub fn set_cache_budget(&self, bytes: usize) {
+        self.cache.borrow_mut().set_budget(bytes);
+    }
+
+    /// Discard all cached block text
+    pub fn clear_cache(&self) {
+        self.cache.borrow_mut().clear();
+    }
+
+    /// The reader or writer the archive is in. Books and chapters got from the archive share
+    /// it, so while any of them are still alive the archive is given back as the error.
+    pub fn into_inner(self) -> Result<T, Self> {
+        match Rc::try_unwrap(self.file) {
+            Ok(file) => Ok(file.into_inner()),
+            Err(file) => Err(Self {
+                file,
+                header: self.header,
+                book_index: self.book_index,
+                cache: self.cache,
+            }),
+        }
+    }
+
     fn new_book_index(number_of_books: u8) -> Vec<BARBookIndexEntry> {
         let mut book_index: Vec<BARBookIndexEntry> = Vec::new();
         book_index.resize_with(usize::from(number_of_books), || {
@@ -325,69 +541,77 @@ impl<T> BARFile<T> {
     }
 }
 
+/// File offset of a book from the book index
+fn book_offset(book_index: &[BARBookIndexEntry], book_number: u8) -> Option<u32> {
+    for entry in book_index {
+        match entry {
+            BARBookIndexEntry::Live {
+                book_number: entry_book_number,
+                file_offset,
+            } => {
+                if *entry_book_number == book_number {
+                    return Some(*file_offset);
+                }
+            }
+            BARBookIndexEntry::Empty => break,
+        }
+    }
+    None
+}
+
 impl<T: io::Read + io::Seek> BARFile<T> {
+    /// Read an archive from the start of the reader, wherever it is positioned
     pub fn read(mut reader: T) -> Result<Self, Box<dyn Error>> {
+        reader.seek(SeekFrom::Start(0))?;
         let header = BARFileHeader::read_from(&mut reader)?;
-        if header.leader != [b'B', b'A', b'R'] {
-            return Err(format!(
-                "Invalid BAR file. Unexpected leader: {}",
-                String::from_utf8(header.leader.to_vec()).unwrap_or("???".to_string())
-            )
-            .into());
-        }
-        if header.version_abbrev.is_empty() {
-            return Err(
-                "Invalid BAR file. Version abbrev in header not specified or corrupt.".into(),
-            );
-        }
-        if header.major_version > CURRENT_VERSION.0 {
-            return Err(format!(
-                "Unsupported future BARFile version: {}.{}",
-                header.major_version, header.minor_version
-            )
-            .into());
-        }
+        header.check()?;
         let book_index: Vec<BARBookIndexEntry> =
             BARBookIndexEntry::read_array(usize::from(header.number_of_books), &mut reader)?;
         Ok(Self {
             file: Rc::new(RefCell::new(reader)),
             header,
             book_index,
+            cache: Rc::new(RefCell::new(BlockCache::default())),
         })
     }
 
+    // The size of the file, which is never empty as it always has a header
+    #[allow(clippy::len_without_is_empty)]
     pub fn len(&self) -> u64 {
         self.file.borrow_mut().seek(SeekFrom::End(0)).unwrap_or(0)
     }
 
     pub fn book(&self, book_number: u8) -> Option<BARBook<T>> {
-        let mut file_offset: u32 = 0;
-        for entry in &self.book_index {
-            match entry {
-                BARBookIndexEntry::Live {
-                    book_number: entry_book_number,
-                    file_offset: entry_file_offset,
-                } => {
-                    if *entry_book_number == book_number {
-                        file_offset = *entry_file_offset;
-                        break;
-                    }
-                }
-                BARBookIndexEntry::Empty => break,
-            }
-        }
-        if file_offset == 0 {
-            return None;
-        }
+        let file_offset = book_offset(&self.book_index, book_number)?;
         BARBook::build(
             Rc::clone(&self.file),
+            Rc::clone(&self.cache),
             book_number,
             file_offset,
             self.header.major_version,
+            self.header.minor_version,
         )
         .ok()
     }
 
+    /// Versions and the books present, in canonical order
+    pub fn metadata(&self) -> ArchiveMetadata {
+        ArchiveMetadata {
+            archive_version: self.archive_version(),
+            bible_version: self.bible_version().clone(),
+            book_capacity: self.header.number_of_books,
+            books: self
+                .books_in_order()
+                .map(|book| BookMetadata {
+                    book: book.book_number(),
+                    abbrev: book.book_abbrev().to_string(),
+                    name: book.book_name().to_string(),
+                    chapters: book.number_of_chapters(),
+                })
+                .collect(),
+        }
+    }
+
     pub fn books<'a>(&'a self) -> BARFileIterator<'a, T> {
         BARFileIterator {
             barfile: self,
@@ -395,27 +619,56 @@ impl<T: io::Read + io::Seek> BARFile<T> {
         }
     }
 
+    /// Like [`BARFile::books`] but a book that can not be read is returned as an error rather
+    /// than ending the iteration
+    pub fn try_books<'a>(&'a self) -> TryBARFileIterator<'a, T> {
+        TryBARFileIterator {
+            barfile: self,
+            index: 0,
+        }
+    }
+
     pub fn books_in_order<'a>(&'a self) -> SortedBARFileIterator<'a, T> {
         SortedBARFileIterator::new(self)
     }
 
     pub fn book_from_index(&self, book_index: u8) -> Option<BARBook<T>> {
-        let entry = self.book_index.get(book_index as usize)?;
+        self.try_book_from_index(book_index).ok().flatten()
+    }
+
+    /// The book at a position in the book index. `Ok(None)` past the last book.
+    pub fn try_book_from_index(&self, book_index: u8) -> BARResult<Option<BARBook<T>>> {
+        let Some(entry) = self.book_index.get(book_index as usize) else {
+            return Ok(None);
+        };
         match entry {
-            BARBookIndexEntry::Empty => None,
+            BARBookIndexEntry::Empty => Ok(None),
             BARBookIndexEntry::Live {
                 book_number,
                 file_offset,
             } => BARBook::build(
                 Rc::clone(&self.file),
+                Rc::clone(&self.cache),
                 *book_number,
                 *file_offset,
                 self.header.major_version,
+                self.header.minor_version,
             )
-            .ok(),
+            .map(Some),
         }
     }
 
+    /// Text of a single verse
+    pub fn verse_text(&self, reference: &Reference) -> BARResult<RcSubstring> {
+        let chapter = self
+            .book(reference.book)
+            .and_then(|book| book.chapter(reference.chapter))
+            .ok_or_else(|| {
+                BARFileError::ReferenceError(format!("Could not retrieve {}", reference))
+            })?;
+        chapter.verse_text(u32::from(reference.verse))
+    }
+
     pub fn book_from_abbrev(&self, abbrev: &str) -> Option<BARBook<T>> {
         let book_number = bible_data::parse_book_abbrev(abbrev);
         match book_number {
@@ -451,17 +704,7 @@ mod tests {
         }
 
         fn create_in_memory_with_options(buf: &'a mut Vec<u8>, header: BARFileHeader) -> Self {
-            let mut file = Cursor::new(buf);
-            header.write_to(&mut file).unwrap();
-            let book_index = Self::new_book_index(header.number_of_books);
-            for entry in &book_index {
-                entry.write_to(&mut file).unwrap();
-            }
-            Self {
-                file: Rc::new(RefCell::new(file)),
-                header,
-                book_index,
-            }
+            BARFile::create_in(Cursor::new(buf), header).unwrap()
         }
     }
 
@@ -533,16 +776,68 @@ mod tests {
         assert_eq!(NIV_HEADER, hex_output.as_str());
     }
 
+    #[test]
+    fn test_header_builder() {
+        let header = BARFileHeader::builder("ΚΑΙΝΗ")
+            .version(2, 3)
+            .book_capacity(27)
+            .build()
+            .unwrap();
+        let bytes = header.to_bytes();
+        assert_eq!(bytes.len(), 16);
+        assert_eq!(bytes[3..6], [2, 3, 27]);
+        assert_eq!(BARFileHeader::from_bytes(&bytes).version_abbrev, "ΚΑΙΝΗ");
+
+        // 12 bytes of UTF-8 does not fit
+        assert!(BARFileHeader::builder("ΚΑΙΝΗΔ").build().is_err());
+        assert!(BARFileHeader::builder("").build().is_err());
+        assert!(BARFileHeader::builder("A\0B").build().is_err());
+        assert!(
+            BARFileHeader::builder("KJV")
+                .book_capacity(0)
+                .build()
+                .is_err()
+        );
+        assert!(
+            BARFileHeader::builder("KJV")
+                .book_capacity(67)
+                .build()
+                .is_err()
+        );
+        assert!(BARFileHeader::builder("KJV").version(0, 1).build().is_err());
+        assert!(BARFileHeader::builder("KJV").version(3, 0).build().is_err());
+        assert!(BARFileHeader::builder("KJV").version(2, 8).build().is_err());
+        assert!(BARFileHeader::builder("KJV").version(1, 0).build().is_ok());
+    }
+
+    #[test]
+    fn test_header_check() {
+        let check = |hex_header: &str| {
+            let bytes = hex::decode(hex_header).expect("Covert to bytes failed.");
+            BARFileHeader::from_bytes(&bytes).check()
+        };
+        assert!(check(GREEK_HEADER).is_ok());
+        // Later minor versions can still be read
+        assert!(check("424152020942677265656B0000000000").is_ok());
+        // Version 0, no books, more than 66 books, NUL in the abbrev and a cut character
+        assert!(check("424152000042677265656B0000000000").is_err());
+        assert!(check("424152020100677265656B0000000000").is_err());
+        assert!(check("424152020143677265656B0000000000").is_err());
+        assert!(check("42415202014267720065656B00000000").is_err());
+        assert!(check("42415202014267CE0000000000000000").is_err());
+        assert!(check("424152030042677265656B0000000000").is_err());
+    }
+
     #[test]
     fn test_create_in_memory() {
         let mut buf: Vec<u8> = Vec::new();
         let version_abbrev = String::from("NIV");
         let bar = BARFile::create_in_memory(&mut buf, version_abbrev);
         assert_eq!(bar.header.major_version, 2);
-        assert_eq!(bar.header.minor_version, 2);
+        assert_eq!(bar.header.minor_version, 7);
         assert_eq!(bar.header.number_of_books, 66);
         assert_eq!(bar.header.version_abbrev.as_str(), "NIV");
-        assert_eq!(bar.archive_version().to_string().as_str(), "2.2");
+        assert_eq!(bar.archive_version().to_string().as_str(), "2.7");
         assert_eq!(bar.bible_version().as_str(), "NIV");
         assert_eq!(bar.book_index.len(), 66);
         assert_eq!(bar.number_of_books(), 0);
@@ -561,10 +856,10 @@ mod tests {
         }
         let bar = BARFile::open_from_memory(&mut buf);
         assert_eq!(bar.header.major_version, 2);
-        assert_eq!(bar.header.minor_version, 2);
+        assert_eq!(bar.header.minor_version, 7);
         assert_eq!(bar.header.number_of_books, 66);
         assert_eq!(bar.header.version_abbrev.as_str(), "NIV");
-        assert_eq!(bar.archive_version().to_string().as_str(), "2.2");
+        assert_eq!(bar.archive_version().to_string().as_str(), "2.7");
         assert_eq!(bar.bible_version().as_str(), "NIV");
         assert_eq!(bar.book_index.len(), 66);
         assert_eq!(bar.number_of_books(), 0);
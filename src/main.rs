@@ -1,8 +1,110 @@
 use biblearchive::BARFile;
+use biblearchive::search::{Matcher, SearchOptions};
+use biblearchive::writer::merge::ConflictPolicy;
+use std::fs::File;
 use std::{env, io};
 
+// bar stats <file> [--json]
+fn stats(args: &[String]) -> io::Result<()> {
+    let json = args.iter().any(|arg| arg == "--json");
+    let Some(file_path) = args.iter().find(|arg| !arg.starts_with("--")) else {
+        eprintln!("Usage: bar stats <file> [--json]");
+        std::process::exit(2);
+    };
+    let bar = BARFile::open(file_path).expect("Failed to open");
+    let stats = bar.stats().map_err(io::Error::other)?;
+    if json {
+        #[cfg(feature = "json")]
+        println!("{}", serde_json::to_string_pretty(&stats)?);
+        #[cfg(not(feature = "json"))]
+        {
+            eprintln!("JSON output needs the json feature");
+            std::process::exit(2);
+        }
+    } else {
+        print!("{stats}");
+    }
+    Ok(())
+}
+
+// Book numbers from a list such as "Mt-Re" or "1-5,Ps"
+fn parse_books(list: &str) -> Option<Vec<u8>> {
+    let book = |text: &str| match text.parse::<u8>() {
+        Ok(number) => Some(number).filter(|number| (1..=66).contains(number)),
+        Err(_) => bible_data::parse_book_abbrev(text).map(|number| number as u8 + 1),
+    };
+    let mut books: Vec<u8> = Vec::new();
+    for part in list.split(',') {
+        match part.split_once('-') {
+            Some((first, last)) => books.extend(book(first)?..=book(last)?),
+            None => books.push(book(part)?),
+        }
+    }
+    Some(books)
+}
+
+// bar extract <file> <output> <books>
+fn extract(args: &[String]) -> io::Result<()> {
+    let [file_path, output, list] = args else {
+        eprintln!("Usage: bar extract <file> <output> <books>");
+        std::process::exit(2);
+    };
+    let Some(books) = parse_books(list) else {
+        eprintln!("Invalid list of books: {list}");
+        std::process::exit(2);
+    };
+    let bar = BARFile::open(file_path).expect("Failed to open");
+    let written = bar
+        .extract(output, books)
+        .map_err(|err| io::Error::other(err.to_string()))?;
+    println!("Wrote {} books to {output}", written.number_of_books());
+    Ok(())
+}
+
+// bar merge <output> <file>... [--keep-first | --keep-last]
+fn merge(args: &[String]) -> io::Result<()> {
+    let policy = match (
+        args.iter().any(|arg| arg == "--keep-first"),
+        args.iter().any(|arg| arg == "--keep-last"),
+    ) {
+        (false, false) => ConflictPolicy::Error,
+        (true, false) => ConflictPolicy::KeepFirst,
+        (false, true) => ConflictPolicy::KeepLast,
+        (true, true) => {
+            eprintln!("Use only one of --keep-first and --keep-last");
+            std::process::exit(2);
+        }
+    };
+    let paths: Vec<&String> = args.iter().filter(|arg| !arg.starts_with("--")).collect();
+    let (output, file_paths) = match paths.as_slice() {
+        [output, file_paths @ ..] if !file_paths.is_empty() => (output, file_paths),
+        _ => {
+            eprintln!("Usage: bar merge <output> <file>... [--keep-first | --keep-last]");
+            std::process::exit(2);
+        }
+    };
+    let archives: Vec<BARFile<File>> = file_paths
+        .iter()
+        .map(|file_path| BARFile::open(file_path).expect("Failed to open"))
+        .collect();
+    let archives: Vec<&BARFile<File>> = archives.iter().collect();
+    let written = biblearchive::writer::merge::merge(output, &archives, policy)
+        .map_err(|err| io::Error::other(err.to_string()))?;
+    println!("Wrote {} books to {output}", written.number_of_books());
+    Ok(())
+}
+
 fn main() -> io::Result<()> {
     let args: Vec<String> = env::args().collect();
+    if args.len() > 1 && args[1] == "stats" {
+        return stats(&args[2..]);
+    }
+    if args.len() > 1 && args[1] == "extract" {
+        return extract(&args[2..]);
+    }
+    if args.len() > 1 && args[1] == "merge" {
+        return merge(&args[2..]);
+    }
     if args.len() > 1 {
         let file_path = &args[1];
         let bar = BARFile::open(file_path).expect("Failed to open");
@@ -64,12 +166,19 @@ fn main() -> io::Result<()> {
         }
     }
 
+    let seven = Matcher::new(
+        "seven",
+        SearchOptions {
+            ignore_case: true,
+            ..Default::default()
+        },
+    );
     for book in bar.books_in_order() {
         //println!("{}", book.book_name());
         for chapter in book.chapters().flatten() {
             let mut count = 0;
             for verse in chapter.verses() {
-                if verse.contains("seven") || verse.contains("Seven") {
+                if seven.is_match(&verse) {
                     count += 1;
                 }
             }
@@ -0,0 +1,207 @@
+//! Inline markup in block text (format version 2.4 on).
+//!
+//! Styled text is marked with two byte tags: an escape character (0x1B) followed by one ASCII
+//! character. The escape character never appears in plain verse text, so text without markup
+//! is unchanged and archives from before 2.4 are never interpreted as having markup.
+//!
+//! | Tag | Meaning |
+//! | --- | --- |
+//! | `ESC i` / `ESC I` | start / end italic (words supplied by the translators) |
+//! | `ESC r` / `ESC R` | start / end red letter (words of Jesus) |
+//! | `ESC k` / `ESC K` | start / end small caps (LORD) |
+//! | `ESC 0`..`ESC 9` | start a new line of poetry at that indent level (0 for prose) |
+//!
+//! Styles run on to the end of the verse if they are not closed. Tags take no space in the
+//! plain text, so the text keeps its own spaces around them. Unknown tags are dropped when
+//! reading and refused when writing. A tag never takes a line break as its second character.
+
+/// Starts every tag
+pub const ESCAPE: char = '\x1B';
+
+/// How a span of verse text is shown
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Style {
+    pub italic: bool,
+    pub red_letter: bool,
+    pub small_caps: bool,
+    /// Poetry indent level, 0 for prose
+    pub indent: u8,
+}
+
+/// A run of verse text in one style
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Span {
+    pub text: String,
+    pub style: Style,
+    /// The span starts a new line of poetry
+    pub new_line: bool,
+}
+
+/// The text with all the tags removed. An escape character at the end of a line is dropped
+/// on its own, so the lines of the text stay the same.
+pub fn strip(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != ESCAPE {
+            result.push(c);
+        } else if chars.peek().is_some_and(|&tag| tag != '\n' && tag != '\r') {
+            chars.next();
+        }
+    }
+    result
+}
+
+/// Whether every escape character in the text starts one of the tags above
+pub fn is_valid(text: &str) -> bool {
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c == ESCAPE
+            && !chars
+                .next()
+                .is_some_and(|tag| matches!(tag, 'i' | 'I' | 'r' | 'R' | 'k' | 'K' | '0'..='9'))
+        {
+            return false;
+        }
+    }
+    true
+}
+
+/// Split marked up verse text into styled spans
+pub fn spans(text: &str) -> Vec<Span> {
+    let mut result: Vec<Span> = Vec::new();
+    let mut current = Span {
+        text: String::new(),
+        style: Style::default(),
+        new_line: false,
+    };
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != ESCAPE {
+            current.text.push(c);
+            continue;
+        }
+        let Some(tag) = chars.next() else {
+            break;
+        };
+        let mut style = current.style;
+        let mut new_line = false;
+        match tag {
+            'i' | 'I' => style.italic = tag == 'i',
+            'r' | 'R' => style.red_letter = tag == 'r',
+            'k' | 'K' => style.small_caps = tag == 'k',
+            '0'..='9' => {
+                style.indent = tag as u8 - b'0';
+                new_line = true;
+            }
+            _ => continue,
+        }
+        if style == current.style && !new_line {
+            continue;
+        }
+        let next = Span {
+            text: String::new(),
+            style,
+            new_line,
+        };
+        let finished = std::mem::replace(&mut current, next);
+        if !finished.text.is_empty() {
+            result.push(finished);
+        } else if finished.new_line {
+            // A line with no text of its own still starts where it was marked
+            current.new_line = true;
+        }
+    }
+    if !current.text.is_empty() || result.is_empty() {
+        result.push(current);
+    }
+    result
+}
+
+/// Mark up spans as verse text. The inverse of [`spans`].
+pub fn encode(spans: &[Span]) -> String {
+    let mut result = String::new();
+    let mut style = Style::default();
+    for span in spans {
+        if span.new_line {
+            result.push(ESCAPE);
+            result.push(char::from(b'0' + span.style.indent.min(9)));
+            style.indent = span.style.indent;
+        }
+        for (on, was, start, end) in [
+            (span.style.italic, style.italic, 'i', 'I'),
+            (span.style.red_letter, style.red_letter, 'r', 'R'),
+            (span.style.small_caps, style.small_caps, 'k', 'K'),
+        ] {
+            if on != was {
+                result.push(ESCAPE);
+                result.push(if on { start } else { end });
+            }
+        }
+        style = span.style;
+        result.push_str(&span.text);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spans() {
+        let text = "And God said, Let there be light: and there \x1Biwas\x1BI light.";
+        assert_eq!(
+            strip(text),
+            "And God said, Let there be light: and there was light."
+        );
+        let spans = spans(text);
+        assert_eq!(spans.len(), 3);
+        assert_eq!(spans[1].text, "was");
+        assert!(spans[1].style.italic);
+        assert!(!spans[2].style.italic);
+        assert_eq!(encode(&spans), text);
+    }
+
+    #[test]
+    fn test_poetry() {
+        let text = "\x1B1The \x1BkLord\x1BK is my shepherd; \x1B2I shall not want.";
+        assert_eq!(strip(text), "The Lord is my shepherd; I shall not want.");
+        let spans = spans(text);
+        let texts: Vec<&str> = spans.iter().map(|span| span.text.as_str()).collect();
+        assert_eq!(
+            texts,
+            vec!["The ", "Lord", " is my shepherd; ", "I shall not want."]
+        );
+        assert!(spans[0].new_line);
+        assert_eq!(spans[0].style.indent, 1);
+        assert!(spans[1].style.small_caps && !spans[1].new_line);
+        assert_eq!(spans[1].style.indent, 1);
+        assert!(spans[3].new_line);
+        assert_eq!(spans[3].style.indent, 2);
+        assert_eq!(encode(&spans), text);
+    }
+
+    #[test]
+    fn test_plain_and_unknown_tags() {
+        assert_eq!(
+            spans("In the beginning"),
+            vec![Span {
+                text: "In the beginning".to_string(),
+                style: Style::default(),
+                new_line: false,
+            }]
+        );
+        assert_eq!(spans("").len(), 1);
+        assert_eq!(strip("a\x1Bzb\x1B"), "ab");
+        assert_eq!(strip("a\x1B\nb\x1B\r\n"), "a\nb\r\n");
+        assert!(is_valid("\x1B1The \x1BkLord\x1BK"));
+        assert!(!is_valid("a\x1Bzb"));
+        assert!(!is_valid("a\x1B"));
+        assert_eq!(spans("a\x1Bzb")[0].text, "ab");
+        // Unclosed styles run to the end of the verse
+        assert!(spans("\x1Brall red")[0].style.red_letter);
+    }
+}
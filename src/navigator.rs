@@ -0,0 +1,261 @@
+//! Step through an archive by verse, chapter or book.
+//!
+//! A [`Navigator`] is built from the verse counts of every chapter in the archive, so moving it
+//! never reads the file. Chapters and books missing from the archive are skipped. Verses are
+//! also numbered from 1 to [`Navigator::len`] across the whole archive in canonical order, which
+//! gives every verse an absolute ordinal.
+
+use crate::BARFile;
+use crate::error::{BARFileError, BARResult};
+use crate::reference::Reference;
+use rcsubstring::RcSubstring;
+use std::io;
+
+struct OutlineChapter {
+    book: u8,
+    chapter: u8,
+    verses: u8,
+    first_ordinal: u32,
+}
+
+/// Verse counts of the chapters present in an archive, in canonical order
+pub(crate) struct Outline {
+    chapters: Vec<OutlineChapter>,
+    len: u32,
+}
+
+impl Outline {
+    pub(crate) fn build<T: io::Read + io::Seek>(barfile: &BARFile<T>) -> BARResult<Self> {
+        let mut books = barfile.try_books().collect::<BARResult<Vec<_>>>()?;
+        books.sort_by_key(|book| book.book_number());
+        let mut chapters: Vec<OutlineChapter> = Vec::new();
+        let mut len: u32 = 0;
+        for book in books {
+            for (i, verses) in book.verse_counts()?.into_iter().enumerate() {
+                if verses == 0 {
+                    continue;
+                }
+                chapters.push(OutlineChapter {
+                    book: book.book_number(),
+                    chapter: i as u8 + 1,
+                    verses,
+                    first_ordinal: len + 1,
+                });
+                len += u32::from(verses);
+            }
+        }
+        Ok(Outline { chapters, len })
+    }
+
+    pub(crate) fn len(&self) -> u32 {
+        self.len
+    }
+
+    /// (book, chapter, number of verses) of each chapter
+    pub(crate) fn chapters(&self) -> impl Iterator<Item = (u8, u8, u8)> + '_ {
+        self.chapters
+            .iter()
+            .map(|chapter| (chapter.book, chapter.chapter, chapter.verses))
+    }
+
+    pub(crate) fn ordinal_of(&self, reference: &Reference) -> Option<u32> {
+        let index = self
+            .chapters
+            .binary_search_by(|entry| {
+                (entry.book, entry.chapter).cmp(&(reference.book, reference.chapter))
+            })
+            .ok()?;
+        let chapter = &self.chapters[index];
+        if reference.verse == 0 || reference.verse > chapter.verses {
+            return None;
+        }
+        Some(chapter.first_ordinal + u32::from(reference.verse) - 1)
+    }
+
+    pub(crate) fn reference_at(&self, ordinal: u32) -> Option<Reference> {
+        if ordinal == 0 || ordinal > self.len {
+            return None;
+        }
+        let chapter = &self.chapters[self.chapter_index(ordinal)];
+        let verse = ordinal - chapter.first_ordinal + 1;
+        Some(Reference::new(chapter.book, chapter.chapter, verse as u8))
+    }
+
+    /// Ordinal of the first verse at or after reference. One past the end if there is none.
+    pub(crate) fn first_at_or_after(&self, reference: &Reference) -> u32 {
+        let index = self.chapters.partition_point(|entry| {
+            (entry.book, entry.chapter) < (reference.book, reference.chapter)
+        });
+        match self.chapters.get(index) {
+            None => self.len + 1,
+            Some(chapter)
+                if (chapter.book, chapter.chapter) != (reference.book, reference.chapter) =>
+            {
+                chapter.first_ordinal
+            }
+            Some(chapter) if reference.verse > chapter.verses => {
+                chapter.first_ordinal + u32::from(chapter.verses)
+            }
+            Some(chapter) => chapter.first_ordinal + u32::from(reference.verse.max(1)) - 1,
+        }
+    }
+
+    /// Ordinal of the last verse at or before reference. Zero if there is none.
+    pub(crate) fn last_at_or_before(&self, reference: &Reference) -> u32 {
+        let index = self.chapters.partition_point(|entry| {
+            (entry.book, entry.chapter) <= (reference.book, reference.chapter)
+        });
+        let Some(chapter) = index.checked_sub(1).map(|index| &self.chapters[index]) else {
+            return 0;
+        };
+        if (chapter.book, chapter.chapter) != (reference.book, reference.chapter) {
+            return chapter.first_ordinal + u32::from(chapter.verses) - 1;
+        }
+        chapter.first_ordinal + u32::from(reference.verse.min(chapter.verses)) - 1
+    }
+
+    /// Index of the chapter containing a verse, which must be in range
+    pub(crate) fn chapter_index(&self, ordinal: u32) -> usize {
+        self.chapters
+            .partition_point(|chapter| chapter.first_ordinal <= ordinal)
+            - 1
+    }
+
+    fn chapter_book(&self, index: usize) -> u8 {
+        self.chapters[index].book
+    }
+
+    fn first_ordinal(&self, index: usize) -> u32 {
+        self.chapters[index].first_ordinal
+    }
+
+    fn number_of_chapters(&self) -> usize {
+        self.chapters.len()
+    }
+}
+
+/// Cursor over the verses of an archive.
+///
+/// The methods that move the navigator return the new position. At the start or end of the
+/// archive they return None and leave the position unchanged.
+pub struct Navigator<'a, T> {
+    barfile: &'a BARFile<T>,
+    outline: Outline,
+    position: u32, // ordinal of the current verse
+}
+
+impl<T: io::Read + io::Seek> BARFile<T> {
+    /// A navigator positioned at the first verse in the archive
+    pub fn navigator(&self) -> BARResult<Navigator<'_, T>> {
+        let outline = Outline::build(self)?;
+        if outline.len() == 0 {
+            return Err(BARFileError::ReferenceError(
+                "Archive contains no verses".to_string(),
+            ));
+        }
+        Ok(Navigator {
+            barfile: self,
+            outline,
+            position: 1,
+        })
+    }
+}
+
+impl<'a, T: io::Read + io::Seek> Navigator<'a, T> {
+    /// Total number of verses in the archive
+    pub fn len(&self) -> u32 {
+        self.outline.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.outline.len() == 0
+    }
+
+    pub fn position(&self) -> Reference {
+        self.reference_at(self.position).unwrap()
+    }
+
+    /// Absolute ordinal of the current verse. The first verse in the archive is 1.
+    pub fn ordinal(&self) -> u32 {
+        self.position
+    }
+
+    /// Text of the current verse
+    pub fn text(&self) -> BARResult<RcSubstring> {
+        self.barfile.verse_text(&self.position())
+    }
+
+    /// Absolute ordinal of a verse, or None if it is not in the archive
+    pub fn ordinal_of(&self, reference: &Reference) -> Option<u32> {
+        self.outline.ordinal_of(reference)
+    }
+
+    /// The verse with an absolute ordinal, or None if the ordinal is out of range
+    pub fn reference_at(&self, ordinal: u32) -> Option<Reference> {
+        self.outline.reference_at(ordinal)
+    }
+
+    /// Move to a verse. If it is not in the archive the position is left unchanged.
+    pub fn go_to(&mut self, reference: &Reference) -> Option<Reference> {
+        let ordinal = self.ordinal_of(reference)?;
+        self.go_to_ordinal(ordinal)
+    }
+
+    pub fn go_to_ordinal(&mut self, ordinal: u32) -> Option<Reference> {
+        let reference = self.reference_at(ordinal)?;
+        self.position = ordinal;
+        Some(reference)
+    }
+
+    /// Move to the next verse, going on to the next chapter or book at the end of this one
+    pub fn next_verse(&mut self) -> Option<Reference> {
+        self.go_to_ordinal(self.position + 1)
+    }
+
+    pub fn previous_verse(&mut self) -> Option<Reference> {
+        self.go_to_ordinal(self.position - 1)
+    }
+
+    /// Move to the first verse of the next chapter
+    pub fn next_chapter(&mut self) -> Option<Reference> {
+        let index = self.outline.chapter_index(self.position) + 1;
+        if index >= self.outline.number_of_chapters() {
+            return None;
+        }
+        self.go_to_ordinal(self.outline.first_ordinal(index))
+    }
+
+    /// Move to the first verse of the previous chapter
+    pub fn previous_chapter(&mut self) -> Option<Reference> {
+        let index = self.outline.chapter_index(self.position).checked_sub(1)?;
+        self.go_to_ordinal(self.outline.first_ordinal(index))
+    }
+
+    /// Move to the first verse of the next book
+    pub fn next_book(&mut self) -> Option<Reference> {
+        let mut index = self.outline.chapter_index(self.position);
+        let book = self.outline.chapter_book(index);
+        while index < self.outline.number_of_chapters() && self.outline.chapter_book(index) == book
+        {
+            index += 1;
+        }
+        if index >= self.outline.number_of_chapters() {
+            return None;
+        }
+        self.go_to_ordinal(self.outline.first_ordinal(index))
+    }
+
+    /// Move to the first verse of the previous book
+    pub fn previous_book(&mut self) -> Option<Reference> {
+        let mut index = self.outline.chapter_index(self.position);
+        let book = self.outline.chapter_book(index);
+        while self.outline.chapter_book(index) == book {
+            index = index.checked_sub(1)?;
+        }
+        let previous = self.outline.chapter_book(index);
+        while index > 0 && self.outline.chapter_book(index - 1) == previous {
+            index -= 1;
+        }
+        self.go_to_ordinal(self.outline.first_ordinal(index))
+    }
+}
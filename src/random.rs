@@ -0,0 +1,307 @@
+//! Random verse and passage selection, and a verse of the day.
+//!
+//! All the choices come from a small seeded generator (SplitMix64) so the same seed always
+//! gives the same verses on every platform. Only verses that are in the archive are chosen, so
+//! a chosen reference can always be passed to [`BARFile::verse_text`].
+
+use crate::BARFile;
+use crate::error::{BARFileError, BARResult};
+use crate::navigator::Outline;
+use crate::reference::{Passage, Reference};
+use std::io;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A calendar date (proleptic Gregorian)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Date {
+    pub year: i32,
+    pub month: u8,
+    pub day: u8,
+}
+
+impl Date {
+    /// None if the day is not in the month
+    pub fn new(year: i32, month: u8, day: u8) -> Option<Self> {
+        let leap = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+        let days_in_month = match month {
+            1 | 3 | 5 | 7 | 8 | 10 | 12 => 31,
+            4 | 6 | 9 | 11 => 30,
+            2 if leap => 29,
+            2 => 28,
+            _ => return None,
+        };
+        if day == 0 || day > days_in_month {
+            return None;
+        }
+        Some(Date { year, month, day })
+    }
+
+    /// Today's date in UTC
+    pub fn today() -> Self {
+        let seconds = match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(duration) => duration.as_secs() as i64,
+            Err(err) => -(err.duration().as_secs() as i64),
+        };
+        Date::from_days(seconds.div_euclid(86400))
+    }
+
+    /// Days since 1970-01-01
+    pub fn days(&self) -> i64 {
+        // Days from civil, counting years from March so the leap day is at the end
+        let year = i64::from(self.year) - i64::from(self.month <= 2);
+        let era = year.div_euclid(400);
+        let year_of_era = year - era * 400;
+        let month = i64::from(self.month);
+        let day_of_year =
+            (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + i64::from(self.day) - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        era * 146097 + day_of_era - 719468
+    }
+
+    /// The date a number of days after 1970-01-01
+    pub fn from_days(days: i64) -> Self {
+        let days = days + 719468;
+        let era = days.div_euclid(146097);
+        let day_of_era = days - era * 146097;
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month + 2) / 5 + 1;
+        let month = if month < 10 { month + 3 } else { month - 9 };
+        let year = year_of_era + era * 400 + i64::from(month <= 2);
+        Date {
+            year: year as i32,
+            month: month as u8,
+            day: day as u8,
+        }
+    }
+}
+
+/// SplitMix64 (Steele, Lea and Flood). Small, fast and fully determined by its seed.
+#[derive(Debug, Clone)]
+struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    fn new(seed: u64) -> Self {
+        SplitMix64 { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E3779B97F4A7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58476D1CE4E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D049BB133111EB);
+        z ^ (z >> 31)
+    }
+
+    /// Uniform in 0..bound. Bound must not be zero.
+    fn below(&mut self, bound: u64) -> u64 {
+        // Reject the top of the range that would make the lower values more likely
+        let limit = u64::MAX - u64::MAX % bound;
+        loop {
+            let value = self.next_u64();
+            if value < limit {
+                return value % bound;
+            }
+        }
+    }
+}
+
+/// How verses are chosen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Weighting {
+    /// Every verse is equally likely
+    Verse,
+    /// Every chapter is equally likely, so verses in short chapters come up more often
+    Chapter,
+}
+
+/// Chooses random verses and passages from an archive
+pub struct VersePicker {
+    rng: SplitMix64,
+    outline: Outline,
+    ranges: Vec<(u32, u32)>, // ordinals first..=last, each within one chapter
+    total: u32,
+    weighting: Weighting,
+}
+
+impl<T: io::Read + io::Seek> BARFile<T> {
+    /// A picker over the whole archive
+    pub fn verse_picker(&self, seed: u64) -> BARResult<VersePicker> {
+        let outline = Outline::build(self)?;
+        // Start with every chapter
+        let ranges: Vec<(u32, u32)> = outline
+            .chapters()
+            .filter_map(|(book, chapter, verses)| {
+                let first = outline.ordinal_of(&Reference::new(book, chapter, 1))?;
+                Some((first, first + u32::from(verses) - 1))
+            })
+            .collect();
+        Ok(VersePicker {
+            rng: SplitMix64::new(seed),
+            total: outline.len(),
+            outline,
+            ranges,
+            weighting: Weighting::Verse,
+        })
+    }
+
+    /// The same verse for everyone using the same seed on the same date
+    pub fn verse_of_the_day(&self, date: Date, seed: u64) -> BARResult<Reference> {
+        let day_seed = seed ^ (date.days() as u64).wrapping_mul(0x9E3779B97F4A7C15);
+        self.verse_picker(day_seed)?
+            .verse()
+            .ok_or_else(|| BARFileError::ReferenceError("Archive contains no verses".to_string()))
+    }
+}
+
+impl VersePicker {
+    /// Only choose verses from these books (1=Gen 66=Rev)
+    pub fn books(mut self, books: &[u8]) -> Self {
+        self.restrict(|book| books.contains(&book), None);
+        self
+    }
+
+    /// Only choose verses from these passages. Verses in more than one passage are more likely.
+    pub fn passages(mut self, passages: &[Passage]) -> Self {
+        self.restrict(|_| true, Some(passages));
+        self
+    }
+
+    pub fn weighting(mut self, weighting: Weighting) -> Self {
+        self.weighting = weighting;
+        self
+    }
+
+    /// Number of verses that can be chosen
+    pub fn len(&self) -> u32 {
+        self.total
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.total == 0
+    }
+
+    /// A random verse, or None if there are no verses to choose from
+    pub fn verse(&mut self) -> Option<Reference> {
+        let (index, offset) = self.choose()?;
+        self.outline.reference_at(self.ranges[index].0 + offset)
+    }
+
+    /// A random run of up to `length` verses that does not cross the end of a chapter
+    pub fn passage(&mut self, length: u8) -> Option<Passage> {
+        let (index, offset) = self.choose()?;
+        let (first, last) = self.ranges[index];
+        let start = first + offset;
+        let end = last.min(start + u32::from(length.max(1)) - 1);
+        Some(Passage::new(
+            self.outline.reference_at(start)?,
+            self.outline.reference_at(end)?,
+        ))
+    }
+
+    // (index into ranges, offset into the range)
+    fn choose(&mut self) -> Option<(usize, u32)> {
+        if self.ranges.is_empty() {
+            return None;
+        }
+        match self.weighting {
+            Weighting::Verse => {
+                let mut n = self.rng.below(u64::from(self.total)) as u32;
+                for (index, (first, last)) in self.ranges.iter().enumerate() {
+                    let size = last - first + 1;
+                    if n < size {
+                        return Some((index, n));
+                    }
+                    n -= size;
+                }
+                None
+            }
+            Weighting::Chapter => {
+                // Ranges can share a chapter when passages were given, so pick a chapter first
+                let mut chapters: Vec<usize> = Vec::new();
+                for (first, _) in self.ranges.iter() {
+                    let chapter = self.outline.chapter_index(*first);
+                    if chapters.last() != Some(&chapter) {
+                        chapters.push(chapter);
+                    }
+                }
+                let chapter = chapters[self.rng.below(chapters.len() as u64) as usize];
+                let in_chapter: Vec<usize> = (0..self.ranges.len())
+                    .filter(|index| self.outline.chapter_index(self.ranges[*index].0) == chapter)
+                    .collect();
+                let size: u32 = in_chapter
+                    .iter()
+                    .map(|index| self.ranges[*index].1 - self.ranges[*index].0 + 1)
+                    .sum();
+                let mut n = self.rng.below(u64::from(size)) as u32;
+                for index in in_chapter {
+                    let (first, last) = self.ranges[index];
+                    if n <= last - first {
+                        return Some((index, n));
+                    }
+                    n -= last - first + 1;
+                }
+                None
+            }
+        }
+    }
+
+    // Keep the verses of the current ranges in the given books and passages
+    fn restrict(&mut self, keep_book: impl Fn(u8) -> bool, passages: Option<&[Passage]>) {
+        let mut ranges: Vec<(u32, u32)> = Vec::new();
+        for (first, last) in std::mem::take(&mut self.ranges) {
+            let book = self.outline.reference_at(first).unwrap().book;
+            if !keep_book(book) {
+                continue;
+            }
+            match passages {
+                None => ranges.push((first, last)),
+                Some(passages) => {
+                    for passage in passages {
+                        let start = self.outline.first_at_or_after(&passage.start).max(first);
+                        let end = self.outline.last_at_or_before(&passage.end).min(last);
+                        if start <= end {
+                            ranges.push((start, end));
+                        }
+                    }
+                }
+            }
+        }
+        self.total = ranges.iter().map(|(first, last)| last - first + 1).sum();
+        self.ranges = ranges;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_splitmix() {
+        // Reference values for seed 1234567
+        let mut rng = SplitMix64::new(1234567);
+        assert_eq!(rng.next_u64(), 6457827717110365317);
+        assert_eq!(rng.next_u64(), 3203168211198807973);
+        let mut rng = SplitMix64::new(0);
+        for _ in 0..1000 {
+            assert!(rng.below(7) < 7);
+        }
+    }
+
+    #[test]
+    fn test_date() {
+        assert_eq!(Date::new(1970, 1, 1).unwrap().days(), 0);
+        assert_eq!(Date::new(2000, 3, 1).unwrap().days(), 11017);
+        assert_eq!(Date::new(1969, 12, 31).unwrap().days(), -1);
+        for days in [-800000, -1, 0, 59, 60, 11016, 11017, 20000, 800000] {
+            assert_eq!(Date::from_days(days).days(), days);
+        }
+        assert_eq!(Date::from_days(11016), Date::new(2000, 2, 29).unwrap());
+        assert!(Date::new(1900, 2, 29).is_none());
+        assert!(Date::new(2024, 13, 1).is_none());
+    }
+}
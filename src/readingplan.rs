@@ -0,0 +1,251 @@
+//! Reading plans: the passages to read on each day.
+//!
+//! A plan can be worked out from an archive with [`BARFile::balanced_plan`], which splits the
+//! whole archive into a number of days of roughly equal length without splitting chapters, or
+//! loaded from a plan definition.
+//!
+//! The text format has one line per day with the passages separated by `;`. Blank lines and
+//! lines starting with `#` are ignored.
+//!
+//! ```text
+//! # Genesis and Matthew together
+//! Ge 1-2; Mt 1
+//! Ge 3-4; Mt 2:1-12
+//! ```
+//!
+//! With the `json` feature the same plan can be read from `{"days": [["Ge 1-2", "Mt 1"], ...]}`.
+//! Passages are parsed as for [`Passage`]'s `FromStr`.
+
+use crate::BARFile;
+use crate::error::{BARFileError, BARResult};
+use crate::navigator::Outline;
+use crate::reference::{Passage, Reference};
+use rcsubstring::RcSubstring;
+use std::fmt;
+use std::io;
+use std::str::FromStr;
+
+/// What to even out between the days of a balanced plan
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Balance {
+    Verses,
+    Words,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReadingPlan {
+    pub days: Vec<Vec<Passage>>,
+}
+
+/// A passage of a plan as found in an archive
+#[derive(Debug, Clone)]
+pub struct PassageText {
+    /// The passage clipped to the verses in the archive
+    pub passage: Passage,
+    pub verses: Vec<(Reference, RcSubstring)>,
+}
+
+impl ReadingPlan {
+    /// Number of days in the plan
+    pub fn len(&self) -> usize {
+        self.days.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.days.is_empty()
+    }
+
+    /// The passages for a day. The first day is 1.
+    pub fn day(&self, day: usize) -> Option<&[Passage]> {
+        self.days
+            .get(day.checked_sub(1)?)
+            .map(|passages| &passages[..])
+    }
+
+    #[cfg(feature = "json")]
+    pub fn from_json(json: &str) -> BARResult<Self> {
+        let plan: PlanFile = serde_json::from_str(json).map_err(|err| {
+            BARFileError::ReferenceError(format!("Invalid reading plan: {}", err))
+        })?;
+        let mut days: Vec<Vec<Passage>> = Vec::new();
+        for passages in plan.days {
+            let day: BARResult<Vec<Passage>> = passages.iter().map(|p| p.parse()).collect();
+            days.push(day?);
+        }
+        Ok(ReadingPlan { days })
+    }
+
+    #[cfg(feature = "json")]
+    pub fn to_json(&self) -> String {
+        let plan = PlanFile {
+            days: self
+                .days
+                .iter()
+                .map(|passages| passages.iter().map(|p| p.to_string()).collect())
+                .collect(),
+        };
+        serde_json::to_string(&plan).unwrap()
+    }
+}
+
+#[cfg(feature = "json")]
+#[derive(serde::Serialize, serde::Deserialize)]
+struct PlanFile {
+    days: Vec<Vec<String>>,
+}
+
+/// Parse the text format described in the module documentation
+impl FromStr for ReadingPlan {
+    type Err = BARFileError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut days: Vec<Vec<Passage>> = Vec::new();
+        for line in s.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let day: BARResult<Vec<Passage>> = line
+                .split(';')
+                .filter(|passage| !passage.trim().is_empty())
+                .map(|passage| passage.trim().parse())
+                .collect();
+            days.push(day?);
+        }
+        Ok(ReadingPlan { days })
+    }
+}
+
+/// Write the plan in the text format
+impl fmt::Display for ReadingPlan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for passages in &self.days {
+            let passages: Vec<String> = passages.iter().map(|p| p.to_string()).collect();
+            writeln!(f, "{}", passages.join("; "))?;
+        }
+        Ok(())
+    }
+}
+
+impl<T: io::Read + io::Seek> BARFile<T> {
+    /// Split the chapters in the archive into `days` days, each holding about the same number of
+    /// verses or words. Chapters are never split between days.
+    pub fn balanced_plan(&self, days: usize, balance: Balance) -> BARResult<ReadingPlan> {
+        let outline = Outline::build(self)?;
+        let chapters: Vec<(u8, u8, u8)> = outline.chapters().collect();
+        if days == 0 || days > chapters.len() {
+            return Err(BARFileError::ReferenceError(format!(
+                "Can not split {} chapters into {} days",
+                chapters.len(),
+                days
+            )));
+        }
+        let mut weights: Vec<u64> = Vec::new();
+        for (book, chapter, verses) in &chapters {
+            let weight = match balance {
+                Balance::Verses => u64::from(*verses),
+                Balance::Words => {
+                    let chapter = self
+                        .book(*book)
+                        .and_then(|book| book.chapter(*chapter))
+                        .ok_or_else(|| {
+                            BARFileError::ReferenceError(format!(
+                                "Could not retrieve {} {}",
+                                crate::barbook::book_abbrev(*book),
+                                chapter
+                            ))
+                        })?;
+                    chapter.chapter_text()?.split_whitespace().count() as u64
+                }
+            };
+            weights.push(weight);
+        }
+
+        let mut plan = ReadingPlan::default();
+        let mut start = 0;
+        for end in split_points(&weights, days) {
+            let (first_book, first_chapter, _) = chapters[start];
+            let (last_book, last_chapter, last_verses) = chapters[end - 1];
+            plan.days.push(vec![Passage::new(
+                Reference::new(first_book, first_chapter, 1),
+                Reference::new(last_book, last_chapter, last_verses),
+            )]);
+            start = end;
+        }
+        Ok(plan)
+    }
+
+    /// The passages and text for a day of a plan. The first day is 1.
+    pub fn plan_day(&self, plan: &ReadingPlan, day: usize) -> BARResult<Vec<PassageText>> {
+        let passages = plan.day(day).ok_or_else(|| {
+            BARFileError::ReferenceError(format!(
+                "Day {} is not in a plan of {} days",
+                day,
+                plan.len()
+            ))
+        })?;
+        let mut result: Vec<PassageText> = Vec::new();
+        for passage in passages {
+            let verses: BARResult<Vec<(Reference, RcSubstring)>> =
+                self.try_passage_verses(passage)?.collect();
+            let verses = verses?;
+            let passage = match (verses.first(), verses.last()) {
+                (Some((start, _)), Some((end, _))) => Passage::new(*start, *end),
+                _ => *passage,
+            };
+            result.push(PassageText { passage, verses });
+        }
+        Ok(result)
+    }
+}
+
+// Where each day ends, as an index one past its last chapter. Each cut is put at the chapter
+// boundary closest to an even share of the total, leaving at least one chapter for every day.
+fn split_points(weights: &[u64], days: usize) -> Vec<usize> {
+    let mut cumulative: Vec<u64> = vec![0];
+    for weight in weights {
+        cumulative.push(cumulative.last().unwrap() + weight);
+    }
+    let total = *cumulative.last().unwrap();
+    let mut ends: Vec<usize> = Vec::new();
+    let mut previous = 0;
+    for day in 1..days {
+        let target = total * day as u64 / days as u64;
+        let mut end = cumulative.partition_point(|sum| *sum < target);
+        if end > 0 && target - cumulative[end - 1] < cumulative[end] - target {
+            end -= 1;
+        }
+        let end = end.clamp(previous + 1, weights.len() - (days - day));
+        ends.push(end);
+        previous = end;
+    }
+    ends.push(weights.len());
+    ends
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_points() {
+        assert_eq!(split_points(&[10, 10, 10, 10], 2), vec![2, 4]);
+        assert_eq!(split_points(&[30, 10, 10, 10], 2), vec![1, 4]);
+        assert_eq!(split_points(&[1, 1, 1, 100], 3), vec![2, 3, 4]);
+        assert_eq!(split_points(&[100, 1, 1, 1], 4), vec![1, 2, 3, 4]);
+        assert_eq!(split_points(&[5, 5, 5], 1), vec![3]);
+    }
+
+    #[test]
+    fn test_plan_text() {
+        let plan: ReadingPlan = "# Test plan\nGe 1-2; Mt 1\n\nGe 3:1-5\n".parse().unwrap();
+        assert_eq!(plan.len(), 2);
+        assert_eq!(plan.day(1).unwrap().len(), 2);
+        assert_eq!(plan.day(2).unwrap()[0].to_string(), "Ge 3:1-5");
+        assert!(plan.day(0).is_none());
+        assert!(plan.day(3).is_none());
+        let plan: ReadingPlan = plan.to_string().parse().unwrap();
+        assert_eq!(plan.len(), 2);
+        assert!("Ge 1; Xx 2".parse::<ReadingPlan>().is_err());
+    }
+}
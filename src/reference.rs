@@ -0,0 +1,199 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::barbook;
+use crate::error::BARFileError;
+
+/// A single verse reference, eg. Ge 1:1
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Reference {
+    pub book: u8, // (1=Gen 66=Rev)
+    pub chapter: u8,
+    pub verse: u8,
+}
+
+impl Reference {
+    pub fn new(book: u8, chapter: u8, verse: u8) -> Self {
+        Reference {
+            book,
+            chapter,
+            verse,
+        }
+    }
+
+    pub fn book_abbrev(&self) -> &'static str {
+        barbook::book_abbrev(self.book)
+    }
+
+    pub fn book_name(&self) -> &'static str {
+        barbook::book_name(self.book)
+    }
+}
+
+impl fmt::Display for Reference {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}:{}", self.book_abbrev(), self.chapter, self.verse)
+    }
+}
+
+/// Parse "Ge 1:1". A reference to a whole chapter, "Ge 1", is verse 1 of it.
+impl FromStr for Reference {
+    type Err = BARFileError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (book, chapter, verse) = parse_reference(s)?;
+        Ok(Reference::new(book, chapter, verse.unwrap_or(1)))
+    }
+}
+
+// (book, chapter, verse) where the verse is optional
+fn parse_reference(s: &str) -> Result<(u8, u8, Option<u8>), BARFileError> {
+    let invalid = || BARFileError::ReferenceError(format!("Invalid reference: {}", s));
+    let (book, place) = s.trim().rsplit_once(' ').ok_or_else(invalid)?;
+    let book = bible_data::parse_book_abbrev(book.trim()).ok_or_else(invalid)? as u8 + 1;
+    let (chapter, verse) = parse_place(place).ok_or_else(invalid)?;
+    Ok((book, chapter, verse))
+}
+
+// "1:2" or "1"
+fn parse_place(s: &str) -> Option<(u8, Option<u8>)> {
+    match s.split_once(':') {
+        Some((chapter, verse)) => Some((chapter.parse().ok()?, Some(verse.parse().ok()?))),
+        None => Some((s.parse().ok()?, None)),
+    }
+}
+
+/// An inclusive range of verses, eg. Ge 1:1-2:3
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Passage {
+    pub start: Reference,
+    pub end: Reference,
+}
+
+impl Passage {
+    pub fn new(start: Reference, end: Reference) -> Self {
+        Passage { start, end }
+    }
+
+    /// A passage of just one verse
+    pub fn verse(reference: Reference) -> Self {
+        Passage {
+            start: reference,
+            end: reference,
+        }
+    }
+
+    pub fn contains(&self, reference: &Reference) -> bool {
+        self.start <= *reference && *reference <= self.end
+    }
+}
+
+impl fmt::Display for Passage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (start, end) = (&self.start, &self.end);
+        if start == end {
+            write!(f, "{}", start)
+        } else if start.book != end.book {
+            write!(f, "{}-{}", start, end)
+        } else if start.chapter != end.chapter {
+            write!(f, "{}-{}:{}", start, end.chapter, end.verse)
+        } else {
+            write!(f, "{}-{}", start, end.verse)
+        }
+    }
+}
+
+/// Verse number standing for the end of a chapter when a passage only gives the chapter.
+/// It is past the last verse of every chapter so it is clipped to the real last verse when the
+/// passage is looked up in an archive.
+pub const END_OF_CHAPTER: u8 = u8::MAX;
+
+/// Parse the forms written by `Display`, eg. "Ge 1:1-5", "Ge 1:1-2:3" or "Ge 1:1-Ex 1:3", and
+/// whole chapters, eg. "Ge 1" or "Ge 1-3". The end of a whole chapter is [`END_OF_CHAPTER`].
+impl FromStr for Passage {
+    type Err = BARFileError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || BARFileError::ReferenceError(format!("Invalid passage: {}", s));
+        let (start, end) = match s.split_once('-') {
+            Some((start, end)) => (start, Some(end.trim())),
+            None => (s, None),
+        };
+        let (book, chapter, verse) = parse_reference(start).map_err(|_| invalid())?;
+        let start = Reference::new(book, chapter, verse.unwrap_or(1));
+        let end = match end {
+            None => Reference::new(book, chapter, verse.unwrap_or(END_OF_CHAPTER)),
+            Some(end) if end.contains(' ') => {
+                let (book, chapter, verse) = parse_reference(end).map_err(|_| invalid())?;
+                Reference::new(book, chapter, verse.unwrap_or(END_OF_CHAPTER))
+            }
+            Some(end) => match (parse_place(end).ok_or_else(invalid)?, verse) {
+                // Ge 1:1-2:3
+                ((end_chapter, Some(end_verse)), _) => Reference::new(book, end_chapter, end_verse),
+                // Ge 1:1-5
+                ((end_verse, None), Some(_)) => Reference::new(book, chapter, end_verse),
+                // Ge 1-3
+                ((end_chapter, None), None) => Reference::new(book, end_chapter, END_OF_CHAPTER),
+            },
+        };
+        if end < start {
+            return Err(invalid());
+        }
+        Ok(Passage { start, end })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let ge1_1 = Reference::new(1, 1, 1);
+        assert_eq!(ge1_1.to_string(), "Ge 1:1");
+        assert_eq!(Passage::verse(ge1_1).to_string(), "Ge 1:1");
+        assert_eq!(
+            Passage::new(ge1_1, Reference::new(1, 1, 5)).to_string(),
+            "Ge 1:1-5"
+        );
+        assert_eq!(
+            Passage::new(ge1_1, Reference::new(1, 2, 3)).to_string(),
+            "Ge 1:1-2:3"
+        );
+        assert_eq!(
+            Passage::new(ge1_1, Reference::new(2, 1, 3)).to_string(),
+            "Ge 1:1-Ex 1:3"
+        );
+    }
+
+    #[test]
+    fn test_passage_contains() {
+        let passage = Passage::new(Reference::new(1, 1, 5), Reference::new(1, 2, 3));
+        assert!(passage.contains(&Reference::new(1, 1, 31)));
+        assert!(passage.contains(&Reference::new(1, 2, 3)));
+        assert!(!passage.contains(&Reference::new(1, 1, 4)));
+        assert!(!passage.contains(&Reference::new(1, 2, 4)));
+    }
+
+    #[test]
+    fn test_parse() {
+        let ge1_1 = Reference::new(1, 1, 1);
+        assert_eq!("Ge 1:1".parse::<Reference>().unwrap(), ge1_1);
+        assert_eq!("Ge 1".parse::<Reference>().unwrap(), ge1_1);
+        assert!("Ge".parse::<Reference>().is_err());
+        assert!("Xx 1:1".parse::<Reference>().is_err());
+        for text in ["Ge 1:1", "Ge 1:1-5", "Ge 1:1-2:3", "Ge 1:1-Ex 1:3"] {
+            assert_eq!(text.parse::<Passage>().unwrap().to_string(), text);
+        }
+        let passage: Passage = "Ge 1-3".parse().unwrap();
+        assert_eq!(passage.start, ge1_1);
+        assert_eq!(passage.end, Reference::new(1, 3, END_OF_CHAPTER));
+        let passage: Passage = "Ge 50-Ex 2".parse().unwrap();
+        assert_eq!(passage.start, Reference::new(1, 50, 1));
+        assert_eq!(passage.end, Reference::new(2, 2, END_OF_CHAPTER));
+        assert!("Ge 2-1".parse::<Passage>().is_err());
+        assert!("Ge 1:x".parse::<Passage>().is_err());
+    }
+}
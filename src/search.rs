@@ -0,0 +1,306 @@
+use crate::BARFile;
+use crate::barbook::interlinear::InterlinearWord;
+use crate::error::BARResult;
+use crate::markup::ESCAPE;
+use crate::reference::Reference;
+use rcsubstring::RcSubstring;
+use std::io;
+use std::ops::Range;
+use unicode_normalization::char::{
+    canonical_combining_class, decompose_canonical, is_combining_mark,
+};
+
+/// A verse matching a search
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SearchHit {
+    pub reference: Reference,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialize::rcsubstring"))]
+    pub text: RcSubstring,
+}
+
+/// A verse matching [`BARFile::search_with`] and where in the verse the matches are
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct SearchMatch {
+    pub reference: Reference,
+    #[cfg_attr(feature = "serde", serde(with = "crate::serialize::rcsubstring"))]
+    pub text: RcSubstring,
+    /// Byte ranges of the matches in `text`, for highlighting
+    pub spans: Vec<Range<usize>>,
+}
+
+/// How text is compared by a [`Matcher`].
+///
+/// Text is always compared in canonical decomposed form (NFD), so a precomposed letter such as
+/// U+1F04 matches the same letter written with combining marks whichever form is in the archive
+/// or the search.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SearchOptions {
+    /// Fold upper and lower case together, including the Greek final sigma
+    pub ignore_case: bool,
+    /// Ignore combining marks: accents, breathings and iota subscript in Greek, points and
+    /// cantillation marks in Hebrew
+    pub ignore_diacritics: bool,
+}
+
+// Text prepared for comparison, with the range of the original text each byte came from
+struct Folded {
+    text: String,
+    origins: Vec<Range<usize>>,
+}
+
+fn fold_with_origins(text: &str, options: &SearchOptions) -> Folded {
+    let mut chars: Vec<(char, Range<usize>)> = Vec::new();
+    let mut tag = false;
+    for (start, c) in text.char_indices() {
+        // Markup tags are not part of the text
+        if tag {
+            tag = false;
+            continue;
+        }
+        if c == ESCAPE {
+            tag = true;
+            continue;
+        }
+        let origin = start..start + c.len_utf8();
+        decompose_canonical(c, |part| chars.push((part, origin.clone())));
+    }
+    // Put each run of combining marks in canonical order
+    let mut start = 0;
+    while start < chars.len() {
+        let end = start
+            + chars[start..]
+                .iter()
+                .take_while(|(c, _)| canonical_combining_class(*c) != 0)
+                .count();
+        chars[start..end].sort_by_key(|(c, _)| canonical_combining_class(*c));
+        start = end + 1;
+    }
+
+    let mut folded = Folded {
+        text: String::new(),
+        origins: Vec::new(),
+    };
+    let mut push = |c: char, origin: &Range<usize>| {
+        if options.ignore_diacritics && is_combining_mark(c) {
+            return;
+        }
+        folded.text.push(c);
+        for _ in 0..c.len_utf8() {
+            folded.origins.push(origin.clone());
+        }
+    };
+    for (c, origin) in chars {
+        if options.ignore_case {
+            for lower in c.to_lowercase() {
+                push(if lower == 'ς' { 'σ' } else { lower }, &origin);
+            }
+        } else {
+            push(c, &origin);
+        }
+    }
+    folded
+}
+
+/// Text in the form it is compared in by a [`Matcher`] with these options
+pub fn fold(text: &str, options: &SearchOptions) -> String {
+    fold_with_origins(text, options).text
+}
+
+/// Finds text in verses using [`SearchOptions`]
+#[derive(Debug, Clone)]
+pub struct Matcher {
+    needle: String,
+    options: SearchOptions,
+}
+
+impl Matcher {
+    pub fn new(needle: &str, options: SearchOptions) -> Self {
+        Matcher {
+            needle: fold(needle, &options),
+            options,
+        }
+    }
+
+    /// Byte ranges of the matches in `text`. A match never ends part way through a letter and
+    /// its marks, so without `ignore_diacritics` α does not match ἄ. [Markup](crate::markup)
+    /// tags in the text are skipped over.
+    pub fn find(&self, text: &str) -> Vec<Range<usize>> {
+        let mut spans: Vec<Range<usize>> = Vec::new();
+        if self.needle.is_empty() {
+            return spans;
+        }
+        let folded = fold_with_origins(text, &self.options);
+        let mut position = 0;
+        while let Some(found) = folded.text[position..].find(&self.needle) {
+            let start = position + found;
+            let end = start + self.needle.len();
+            if folded.text[end..]
+                .chars()
+                .next()
+                .is_some_and(is_combining_mark)
+            {
+                position = start
+                    + folded.text[start..]
+                        .chars()
+                        .next()
+                        .map_or(1, char::len_utf8);
+                continue;
+            }
+            spans.push(folded.origins[start].start..folded.origins[end - 1].end);
+            position = end;
+        }
+        spans
+    }
+
+    pub fn is_match(&self, text: &str) -> bool {
+        !self.find(text).is_empty()
+    }
+}
+
+/// A word with the Strong's number searched for
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct WordHit {
+    pub reference: Reference,
+    /// Index of the word in the verse, 0 for the first word
+    pub position: usize,
+    pub word: InterlinearWord,
+}
+
+impl<T: io::Read + io::Seek> BARFile<T> {
+    /// Find every verse containing `needle`, in canonical book order
+    pub fn search(&self, needle: &str) -> Vec<SearchHit> {
+        let mut hits: Vec<SearchHit> = Vec::new();
+        for book in self.books_in_order() {
+            for chapter in book.chapters().flatten() {
+                for (verse, text) in chapter.enumerated_verses() {
+                    if text.contains(needle) {
+                        hits.push(SearchHit {
+                            reference: Reference::new(
+                                book.book_number(),
+                                chapter.chapter_number(),
+                                verse,
+                            ),
+                            text,
+                        });
+                    }
+                }
+            }
+        }
+        hits
+    }
+
+    /// Find every verse containing `needle` compared using `options`, in canonical book order
+    pub fn search_with(&self, needle: &str, options: SearchOptions) -> Vec<SearchMatch> {
+        let matcher = Matcher::new(needle, options);
+        let mut hits: Vec<SearchMatch> = Vec::new();
+        for book in self.books_in_order() {
+            for chapter in book.chapters().flatten() {
+                for (verse, text) in chapter.enumerated_verses() {
+                    let spans = matcher.find(&text);
+                    if !spans.is_empty() {
+                        hits.push(SearchMatch {
+                            reference: Reference::new(
+                                book.book_number(),
+                                chapter.chapter_number(),
+                                verse,
+                            ),
+                            text,
+                            spans,
+                        });
+                    }
+                }
+            }
+        }
+        hits
+    }
+
+    /// Find every word with a Strong's number, such as `G3056`, in canonical book order. Only
+    /// archives with interlinear data (version 2.7 on) have any.
+    pub fn search_strongs(&self, strongs: &str) -> BARResult<Vec<WordHit>> {
+        let mut hits: Vec<WordHit> = Vec::new();
+        for book in self.books_in_order() {
+            for chapter in book.chapters().flatten() {
+                for (verse, words) in chapter.interlinear_verses()? {
+                    for (position, word) in words.into_iter().enumerate() {
+                        if word.has_strongs(strongs) {
+                            hits.push(WordHit {
+                                reference: Reference::new(
+                                    book.book_number(),
+                                    chapter.chapter_number(),
+                                    verse,
+                                ),
+                                position,
+                                word,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+        Ok(hits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOTH: SearchOptions = SearchOptions {
+        ignore_case: true,
+        ignore_diacritics: true,
+    };
+
+    #[test]
+    fn test_fold() {
+        // Precomposed and decomposed forms compare the same
+        let options = SearchOptions::default();
+        assert_eq!(
+            fold("\u{1F04}", &options),
+            fold("\u{3B1}\u{313}\u{301}", &options)
+        );
+        // Marks are put in canonical order
+        assert_eq!(
+            fold("\u{3B1}\u{345}\u{313}", &options),
+            fold("\u{3B1}\u{313}\u{345}", &options)
+        );
+        assert_eq!(fold("Λόγος", &BOTH), "λογοσ");
+        assert_eq!(fold("ΛΌΓΟΣ", &BOTH), "λογοσ");
+        // Hebrew points and cantillation
+        assert_eq!(fold("בְּרֵאשִׁ֖ית", &BOTH), "בראשית");
+        assert_eq!(fold("In \x1Bithe\x1BI Lord", &BOTH), "in the lord");
+    }
+
+    #[test]
+    fn test_matcher() {
+        let text = "Ἐν ἀρχῇ ἦν ὁ λόγος, καὶ ὁ λόγος ἦν πρὸς τὸν θεόν";
+        let matcher = Matcher::new("ΛΟΓΟΣ", BOTH);
+        let spans = matcher.find(text);
+        assert_eq!(spans.len(), 2);
+        assert_eq!(&text[spans[0].clone()], "λόγος");
+        assert_eq!(&text[spans[1].clone()], "λόγος");
+        assert_eq!(
+            &text[Matcher::new("αρχη", BOTH).find(text)[0].clone()],
+            "ἀρχῇ"
+        );
+
+        // Without folding the marks have to match
+        let options = SearchOptions::default();
+        assert!(!Matcher::new("λογος", options).is_match(text));
+        assert!(Matcher::new("λόγος", options).is_match(text));
+        assert!(!Matcher::new("ἀρχη", options).is_match(text));
+        assert!(!Matcher::new("Λόγος", options).is_match(text));
+        let options = SearchOptions {
+            ignore_case: true,
+            ..Default::default()
+        };
+        assert!(Matcher::new("ΛΌΓΟΣ", options).is_match(text));
+
+        let text = "there \x1Biwas\x1BI light";
+        let spans = Matcher::new("was light", BOTH).find(text);
+        assert_eq!(spans, vec![8..19]);
+        assert!(Matcher::new("", BOTH).find(text).is_empty());
+    }
+}
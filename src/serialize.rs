@@ -0,0 +1,58 @@
+//! Serde support for the public data types (requires the `serde` feature).
+//!
+//! The JSON shapes are documented in the README and are covered by tests/serde_test.rs so
+//! changes to them are deliberate.
+
+use crate::BARVersion;
+use serde::de::{self, Deserializer};
+use serde::{Deserialize, Serialize, Serializer};
+
+/// Archive versions are written as "major.minor", eg. "2.1"
+impl Serialize for BARVersion {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for BARVersion {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        let parsed = value
+            .split_once('.')
+            .and_then(|(major, minor)| Some(BARVersion(major.parse().ok()?, minor.parse().ok()?)));
+        parsed.ok_or_else(|| de::Error::custom(format!("Invalid BARFile version: {}", value)))
+    }
+}
+
+/// Verse text is written as a plain string
+pub(crate) mod rcsubstring {
+    use rcsubstring::RcSubstring;
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::rc::Rc;
+
+    pub fn serialize<S: Serializer>(value: &RcSubstring, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(value)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<RcSubstring, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        let len = value.len();
+        Ok(RcSubstring::new(Rc::new(value), 0..len))
+    }
+}
+
+/// Durations are written as a whole number of microseconds
+pub(crate) mod duration_micros {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(value: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_u64(value.as_micros() as u64)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        Ok(Duration::from_micros(u64::deserialize(deserializer)?))
+    }
+}
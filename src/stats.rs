@@ -0,0 +1,357 @@
+//! Size and content statistics for a whole archive.
+//!
+//! [`BARFile::stats`] decompresses every block once, bypassing the block cache so the timings
+//! are comparable between runs, and rolls the figures up per book and for the archive.
+
+use crate::barbook;
+use crate::barbook::barchapter::{BlockHeader, CompressionAlgorithm};
+use crate::binarystruct::BinaryStruct;
+use crate::error::{BARFileError, BARResult};
+use crate::reference::Reference;
+use crate::{BARBookIndexEntry, BARFile, BARFileHeader, BARVersion, markup};
+use std::fmt;
+use std::io;
+use std::time::{Duration, Instant};
+
+/// Number of blocks listed in [`ArchiveStats::largest_blocks`]
+pub const LARGEST_BLOCKS: usize = 10;
+
+/// Counts and sizes for a book or for the whole archive
+#[derive(Debug, Clone, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TextStats {
+    pub chapters: u32,
+    pub verses: u32,
+    pub words: u64,
+    pub characters: u64,
+    pub blocks: u32,
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+    #[cfg_attr(
+        feature = "serde",
+        serde(
+            rename = "decompress_time_us",
+            with = "crate::serialize::duration_micros"
+        )
+    )]
+    pub decompress_time: Duration,
+}
+
+impl TextStats {
+    /// Uncompressed size divided by compressed size
+    pub fn compression_ratio(&self) -> f64 {
+        ratio(self.uncompressed_size, self.compressed_size)
+    }
+
+    fn add(&mut self, other: &TextStats) {
+        self.chapters += other.chapters;
+        self.verses += other.verses;
+        self.words += other.words;
+        self.characters += other.characters;
+        self.blocks += other.blocks;
+        self.compressed_size += other.compressed_size;
+        self.uncompressed_size += other.uncompressed_size;
+        self.decompress_time += other.decompress_time;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BookStats {
+    pub book: u8, // (1=Gen 66=Rev)
+    pub abbrev: String,
+    pub totals: TextStats,
+}
+
+/// Totals for all the blocks packed with one compression algorithm
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CompressionStats {
+    pub algorithm: CompressionAlgorithm,
+    pub blocks: u32,
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+}
+
+impl CompressionStats {
+    /// Uncompressed size divided by compressed size
+    pub fn compression_ratio(&self) -> f64 {
+        ratio(self.uncompressed_size, self.compressed_size)
+    }
+}
+
+/// One block of the archive. The reference is to the first verse in the block.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BlockStats {
+    pub reference: Reference,
+    pub end_verse: u8,
+    pub file_offset: u32,
+    pub compression_algorithm: CompressionAlgorithm,
+    pub compressed_size: u32,
+    pub uncompressed_size: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct ArchiveStats {
+    pub archive_version: BARVersion,
+    pub bible_version: String,
+    pub file_size: u64,
+    /// Bytes not taken by the header, the indexes, the blocks or the end of book markers
+    pub unused_bytes: u64,
+    pub totals: TextStats,
+    /// Books in canonical order
+    pub books: Vec<BookStats>,
+    /// One entry per compression algorithm used, in algorithm number order
+    pub compression: Vec<CompressionStats>,
+    /// The largest blocks by compressed size, largest first
+    pub largest_blocks: Vec<BlockStats>,
+}
+
+fn ratio(uncompressed_size: u64, compressed_size: u64) -> f64 {
+    if compressed_size == 0 {
+        return 0.0;
+    }
+    uncompressed_size as f64 / compressed_size as f64
+}
+
+impl<T: io::Read + io::Seek> BARFile<T> {
+    /// Work out the statistics for the whole archive. Every block is decompressed.
+    pub fn stats(&self) -> BARResult<ArchiveStats> {
+        let file_size = self.len();
+        let block_header_size = BlockHeader::size_for_version(self.header.major_version)? as u64;
+        let markup = barbook::has_markup(self.header.major_version, self.header.minor_version);
+        // File ranges that are in use, merged at the end to find the unused space
+        let mut used: Vec<(u64, u64)> = vec![(
+            0,
+            (BARFileHeader::byte_size() + self.book_index.len() * BARBookIndexEntry::byte_size())
+                as u64,
+        )];
+        let mut totals = TextStats::default();
+        let mut books: Vec<BookStats> = Vec::new();
+        let mut compression: Vec<CompressionStats> = Vec::new();
+        let mut blocks: Vec<BlockStats> = Vec::new();
+
+        let mut ordered = self.try_books().collect::<BARResult<Vec<_>>>()?;
+        ordered.sort_by_key(|book| book.book_number());
+        for book in ordered {
+            let mut book_totals = TextStats::default();
+            let book_start = u64::from(book.file_offset());
+            let mut book_end = book_start + u64::from(book.header_size());
+            used.push((book_start, book_end));
+            for chapter in book.try_chapters() {
+                let Some(chapter) = chapter? else {
+                    continue;
+                };
+                book_totals.chapters += 1;
+                for entry in chapter.blocks()?.iter() {
+                    let now = Instant::now();
+                    let text = chapter.decompress_uncached(entry)?;
+                    book_totals.decompress_time += now.elapsed();
+                    book_totals.blocks += 1;
+                    let verses =
+                        entry
+                            .end_verse
+                            .checked_sub(entry.start_verse)
+                            .ok_or_else(|| {
+                                BARFileError::InvalidFileFormat(format!(
+                                    "Block of {} {} ends at verse {} before its start verse {}",
+                                    book.book_name(),
+                                    chapter.chapter_number(),
+                                    entry.end_verse,
+                                    entry.start_verse
+                                ))
+                            })?;
+                    book_totals.verses += u32::from(verses) + 1;
+                    // Words and characters of the text as read, without markup tags
+                    let stripped: String;
+                    let plain = match markup {
+                        true => {
+                            stripped = markup::strip(&text);
+                            &stripped
+                        }
+                        false => &text,
+                    };
+                    book_totals.words += plain.split_whitespace().count() as u64;
+                    book_totals.characters += plain.chars().count() as u64;
+                    book_totals.compressed_size += u64::from(entry.block_size);
+                    book_totals.uncompressed_size += text.len() as u64;
+
+                    let start = u64::from(entry.file_offset);
+                    let end = start + block_header_size + u64::from(entry.block_size);
+                    used.push((start, end));
+                    book_end = book_end.max(end);
+
+                    let index = compression
+                        .iter()
+                        .position(|c| c.algorithm == entry.compression_algorithm);
+                    let algorithm = match index {
+                        Some(index) => &mut compression[index],
+                        None => {
+                            compression.push(CompressionStats {
+                                algorithm: entry.compression_algorithm.clone(),
+                                blocks: 0,
+                                compressed_size: 0,
+                                uncompressed_size: 0,
+                            });
+                            compression.last_mut().unwrap()
+                        }
+                    };
+                    algorithm.blocks += 1;
+                    algorithm.compressed_size += u64::from(entry.block_size);
+                    algorithm.uncompressed_size += text.len() as u64;
+
+                    blocks.push(BlockStats {
+                        reference: Reference::new(
+                            book.book_number(),
+                            chapter.chapter_number(),
+                            entry.start_verse,
+                        ),
+                        end_verse: entry.end_verse,
+                        file_offset: entry.file_offset,
+                        compression_algorithm: entry.compression_algorithm.clone(),
+                        compressed_size: entry.block_size,
+                        uncompressed_size: text.len() as u32,
+                    });
+                }
+            }
+            // The book is terminated by a single zero byte after its last block
+            if book_end < file_size {
+                used.push((book_end, book_end + 1));
+            }
+            if let Some(range) = book.annotations_range()? {
+                used.push(range);
+            }
+            if let Some(range) = book.notes_range()? {
+                used.push(range);
+            }
+            if let Some(range) = book.interlinear_range()? {
+                used.push(range);
+            }
+            totals.add(&book_totals);
+            books.push(BookStats {
+                book: book.book_number(),
+                abbrev: book.book_abbrev().to_string(),
+                totals: book_totals,
+            });
+        }
+
+        compression.sort_by_key(|c| u8::from(&c.algorithm));
+        blocks.sort_by(|a, b| {
+            b.compressed_size
+                .cmp(&a.compressed_size)
+                .then(a.file_offset.cmp(&b.file_offset))
+        });
+        blocks.truncate(LARGEST_BLOCKS);
+
+        Ok(ArchiveStats {
+            archive_version: self.archive_version(),
+            bible_version: self.bible_version().clone(),
+            file_size,
+            unused_bytes: file_size - covered(used, file_size),
+            totals,
+            books,
+            compression,
+            largest_blocks: blocks,
+        })
+    }
+}
+
+// Total length of the ranges once overlaps are merged, ignoring anything past the end of the file
+fn covered(mut ranges: Vec<(u64, u64)>, file_size: u64) -> u64 {
+    ranges.sort();
+    let mut total = 0;
+    let mut reached = 0;
+    for (start, end) in ranges {
+        let start = start.max(reached);
+        let end = end.min(file_size);
+        if end > start {
+            total += end - start;
+            reached = end;
+        }
+    }
+    total
+}
+
+/// Plain text table, as shown by `bar stats`
+impl fmt::Display for ArchiveStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "{} (archive version {}). {} bytes, {} unused.",
+            self.bible_version, self.archive_version, self.file_size, self.unused_bytes
+        )?;
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:<6} {:>8} {:>7} {:>9} {:>11} {:>6} {:>10} {:>12} {:>6} {:>10}",
+            "Book",
+            "Chapters",
+            "Verses",
+            "Words",
+            "Characters",
+            "Blocks",
+            "Compressed",
+            "Uncompressed",
+            "Ratio",
+            "Time (us)"
+        )?;
+        for book in &self.books {
+            write_totals(f, &book.abbrev, &book.totals)?;
+        }
+        write_totals(f, "Total", &self.totals)?;
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:<9} {:>6} {:>10} {:>12} {:>6}",
+            "Algorithm", "Blocks", "Compressed", "Uncompressed", "Ratio"
+        )?;
+        for algorithm in &self.compression {
+            writeln!(
+                f,
+                "{:<9} {:>6} {:>10} {:>12} {:>6.2}",
+                algorithm.algorithm.to_string(),
+                algorithm.blocks,
+                algorithm.compressed_size,
+                algorithm.uncompressed_size,
+                algorithm.compression_ratio()
+            )?;
+        }
+        writeln!(f)?;
+        writeln!(
+            f,
+            "{:<16} {:>10} {:>10} {:>12} Algorithm",
+            "Largest blocks", "Offset", "Compressed", "Uncompressed"
+        )?;
+        for block in &self.largest_blocks {
+            writeln!(
+                f,
+                "{:<16} {:>10} {:>10} {:>12} {}",
+                format!("{}-{}", block.reference, block.end_verse),
+                block.file_offset,
+                block.compressed_size,
+                block.uncompressed_size,
+                block.compression_algorithm
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn write_totals(f: &mut fmt::Formatter<'_>, name: &str, totals: &TextStats) -> fmt::Result {
+    writeln!(
+        f,
+        "{:<6} {:>8} {:>7} {:>9} {:>11} {:>6} {:>10} {:>12} {:>6.2} {:>10}",
+        name,
+        totals.chapters,
+        totals.verses,
+        totals.words,
+        totals.characters,
+        totals.blocks,
+        totals.compressed_size,
+        totals.uncompressed_size,
+        totals.compression_ratio(),
+        totals.decompress_time.as_micros()
+    )
+}
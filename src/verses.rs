@@ -0,0 +1,193 @@
+//! Iterate over every verse of an archive, or a passage of it, in canonical order.
+
+use crate::BARFile;
+use crate::barbook::barchapter::BARChapter;
+use crate::error::{BARFileError, BARResult};
+use crate::navigator::Outline;
+use crate::reference::{Passage, Reference};
+use rcsubstring::RcSubstring;
+use std::io;
+
+// Shared by the plain and fallible iterators. Yields the verses with ordinals front..=back.
+struct VerseRange<'a, T> {
+    barfile: &'a BARFile<T>,
+    outline: Outline,
+    front: u32,
+    back: u32,
+    front_chapter: Option<(usize, BARChapter<T>)>, // (outline index, chapter)
+    back_chapter: Option<(usize, BARChapter<T>)>,
+}
+
+impl<'a, T: io::Read + io::Seek> VerseRange<'a, T> {
+    fn new(barfile: &'a BARFile<T>, outline: Outline, passage: Option<&Passage>) -> Self {
+        let (front, back) = match passage {
+            None => (1, outline.len()),
+            Some(passage) => (
+                outline.first_at_or_after(&passage.start),
+                outline.last_at_or_before(&passage.end),
+            ),
+        };
+        VerseRange {
+            barfile,
+            outline,
+            front,
+            back,
+            front_chapter: None,
+            back_chapter: None,
+        }
+    }
+
+    fn len(&self) -> usize {
+        if self.front > self.back {
+            return 0;
+        }
+        (self.back - self.front + 1) as usize
+    }
+
+    fn finish(&mut self) {
+        self.front = 1;
+        self.back = 0;
+    }
+
+    fn next(&mut self) -> Option<BARResult<(Reference, RcSubstring)>> {
+        if self.front > self.back {
+            return None;
+        }
+        let ordinal = self.front;
+        self.front += 1;
+        Some(self.verse(ordinal, false))
+    }
+
+    fn next_back(&mut self) -> Option<BARResult<(Reference, RcSubstring)>> {
+        if self.front > self.back {
+            return None;
+        }
+        let ordinal = self.back;
+        self.back -= 1;
+        Some(self.verse(ordinal, true))
+    }
+
+    fn verse(&mut self, ordinal: u32, from_back: bool) -> BARResult<(Reference, RcSubstring)> {
+        let reference = self.outline.reference_at(ordinal).unwrap();
+        let index = self.outline.chapter_index(ordinal);
+        // Keep the chapter at each end so its current block is reused for the next verse
+        let slot = match from_back {
+            false => &mut self.front_chapter,
+            true => &mut self.back_chapter,
+        };
+        if !matches!(slot, Some((current, _)) if *current == index) {
+            let chapter = self
+                .barfile
+                .book(reference.book)
+                .and_then(|book| book.chapter(reference.chapter))
+                .ok_or_else(|| {
+                    BARFileError::ReferenceError(format!("Could not retrieve {}", reference))
+                })?;
+            *slot = Some((index, chapter));
+        }
+        let (_, chapter) = slot.as_ref().unwrap();
+        let text = chapter.verse_text(u32::from(reference.verse))?;
+        Ok((reference, text))
+    }
+}
+
+/// Verses of an archive with their references. Ends early if a verse cannot be read, so unlike
+/// [`TryBARVerseIterator`] it does not have an exact size.
+pub struct BARVerseIterator<'a, T> {
+    range: VerseRange<'a, T>,
+}
+
+/// Verses of an archive with their references, reporting verses that cannot be read
+pub struct TryBARVerseIterator<'a, T> {
+    range: VerseRange<'a, T>,
+}
+
+impl<T: io::Read + io::Seek> BARFile<T> {
+    /// Every verse in the archive in canonical order. An error if the verse counts of the books
+    /// can not be read.
+    ///
+    /// The iteration ends at the first verse that can not be read. Use [`BARFile::try_verses`]
+    /// to see the error instead.
+    pub fn verses<'a>(&'a self) -> BARResult<BARVerseIterator<'a, T>> {
+        Ok(BARVerseIterator {
+            range: VerseRange::new(self, Outline::build(self)?, None),
+        })
+    }
+
+    /// The verses of a passage that are in the archive. Read errors end the iteration as for
+    /// [`BARFile::verses`]; use [`BARFile::try_passage_verses`] to see them.
+    pub fn passage_verses<'a>(&'a self, passage: &Passage) -> BARResult<BARVerseIterator<'a, T>> {
+        Ok(BARVerseIterator {
+            range: VerseRange::new(self, Outline::build(self)?, Some(passage)),
+        })
+    }
+
+    /// Like [`BARFile::verses`] but each verse is a `Result` so a damaged block is reported
+    /// rather than ending the iteration
+    pub fn try_verses<'a>(&'a self) -> BARResult<TryBARVerseIterator<'a, T>> {
+        Ok(TryBARVerseIterator {
+            range: VerseRange::new(self, Outline::build(self)?, None),
+        })
+    }
+
+    pub fn try_passage_verses<'a>(
+        &'a self,
+        passage: &Passage,
+    ) -> BARResult<TryBARVerseIterator<'a, T>> {
+        Ok(TryBARVerseIterator {
+            range: VerseRange::new(self, Outline::build(self)?, Some(passage)),
+        })
+    }
+}
+
+impl<'a, T: io::Read + io::Seek> Iterator for BARVerseIterator<'a, T> {
+    type Item = (Reference, RcSubstring);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.range.next()? {
+            Ok(verse) => Some(verse),
+            Err(_) => {
+                self.range.finish();
+                None
+            }
+        }
+    }
+
+    // A verse that can not be read ends the iteration early, so only the most is known
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(self.range.len()))
+    }
+}
+
+impl<'a, T: io::Read + io::Seek> DoubleEndedIterator for BARVerseIterator<'a, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        match self.range.next_back()? {
+            Ok(verse) => Some(verse),
+            Err(_) => {
+                self.range.finish();
+                None
+            }
+        }
+    }
+}
+
+impl<'a, T: io::Read + io::Seek> Iterator for TryBARVerseIterator<'a, T> {
+    type Item = BARResult<(Reference, RcSubstring)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.range.next()
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.range.len();
+        (len, Some(len))
+    }
+}
+
+impl<'a, T: io::Read + io::Seek> DoubleEndedIterator for TryBARVerseIterator<'a, T> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.range.next_back()
+    }
+}
+
+impl<'a, T: io::Read + io::Seek> ExactSizeIterator for TryBARVerseIterator<'a, T> {}
@@ -0,0 +1,682 @@
+//! Write BAR archives.
+//!
+//! A [`BARWriter`] collects the text of each book and writes the whole archive at once. Books
+//! can be built up by hand as [`BookSource`]s or imported with [`usfm::parse`].
+//!
+//! An existing archive opened with [`BARFile::open_for_edit`] can have verses, chapters and
+//! books replaced. The new text is written at the end of the file and the chapter or book
+//! index pointed at it, so the archive stays readable by every reader at each step. The old
+//! text is left in the file, unused, until [`BARFile::compact`] is called.
+//!
+//! Archives can be combined with [`merge::merge`] and some of the books of an archive written
+//! to a new one with [`BARFile::extract`].
+//!
+//! Writing is reproducible. Books are written in canonical order and compressed with the same
+//! settings each time, so the same books and options always give the same bytes.
+//!
+//! # Example
+//! ```rust
+//! # fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! use biblearchive::writer::{BARWriter, BookSource, ChapterSource};
+//! let path = std::env::temp_dir().join("writer-doc-example.ibar");
+//! # let _ = std::fs::remove_file(&path);
+//! let mut book = BookSource::new(1);
+//! book.chapters.push(ChapterSource::new(1, vec![
+//!     "In the beginning God created the heaven and the earth.".to_string(),
+//! ]));
+//! let mut writer = BARWriter::new("KJV");
+//! writer.add_book(book)?;
+//! let bar = writer.write(&path)?;
+//! let chapter = bar.book(1).unwrap().chapter(1).unwrap();
+//! assert_eq!(&*chapter.verse_text(1)?, "In the beginning God created the heaven and the earth.");
+//! # std::fs::remove_file(&path)?;
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::barbook::annotations::{self, ChapterAnnotations, Heading};
+use crate::barbook::barchapter::compress::lzo;
+use crate::barbook::barchapter::{BlockHeader, CompressionAlgorithm, compress_block};
+use crate::barbook::interlinear::{self, InterlinearWord};
+use crate::barbook::notes::{CrossReference, Note, NoteLine};
+use crate::barbook::{self, BARBookHeader, BARChapterIndexEntry};
+use crate::error::{BARFileError, BARResult};
+use crate::{BARBookIndexEntry, BARFile, BARFileHeader, BinaryStruct, markup};
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, SeekFrom};
+use std::path::Path;
+
+mod edit;
+pub mod merge;
+pub mod usfm;
+
+/// Bytes of verse text to put in a block before starting a new one
+pub const DEFAULT_BLOCK_SIZE: usize = 8192;
+
+/// The text of one chapter to be written
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChapterSource {
+    pub chapter_number: u8,
+    /// Text of each verse starting with verse 1. It may contain [markup](crate::markup) tags but
+    /// not unknown tags or line breaks.
+    pub verses: Vec<String>,
+    /// Written from archive version 2.5
+    pub headings: Vec<Heading>,
+    /// The verses that start a paragraph. Written from archive version 2.5.
+    pub paragraphs: Vec<u8>,
+    /// Written from archive version 2.6
+    pub notes: Vec<Note>,
+    /// Written from archive version 2.6
+    pub cross_references: Vec<CrossReference>,
+    /// The [words](interlinear::words) of each verse with their lexical data, starting with
+    /// verse 1. Verses without data are left empty. Written from archive version 2.7.
+    pub interlinear: Vec<Vec<InterlinearWord>>,
+}
+
+/// The text of one book to be written
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BookSource {
+    pub book_number: u8, // (1=Gen 66=Rev)
+    pub chapters: Vec<ChapterSource>,
+}
+
+impl ChapterSource {
+    pub fn new(chapter_number: u8, verses: Vec<String>) -> Self {
+        ChapterSource {
+            chapter_number,
+            verses,
+            ..Default::default()
+        }
+    }
+}
+
+impl BookSource {
+    pub fn new(book_number: u8) -> Self {
+        BookSource {
+            book_number,
+            chapters: Vec::new(),
+        }
+    }
+}
+
+/// Builds an archive from the text of its books
+pub struct BARWriter {
+    header: BARFileHeader,
+    compression: CompressionAlgorithm,
+    block_size: usize,
+    books: Vec<BookSource>,
+}
+
+impl BARWriter {
+    /// A writer for the current archive version. The abbreviation is checked when the archive
+    /// is written.
+    pub fn new(version_abbrev: &str) -> Self {
+        Self::with_header(BARFileHeader {
+            version_abbrev: version_abbrev.to_string(),
+            ..Default::default()
+        })
+    }
+
+    /// A writer for a header made with [`BARFileHeader::builder`]. Version 1 archives are
+    /// written with LZO compression and no markup, annotations, notes or interlinear data.
+    pub fn with_header(header: BARFileHeader) -> Self {
+        let compression = match header.major_version {
+            1 => CompressionAlgorithm::Lzo,
+            _ => CompressionAlgorithm::ZLib,
+        };
+        BARWriter {
+            header,
+            compression,
+            block_size: DEFAULT_BLOCK_SIZE,
+            books: Vec::new(),
+        }
+    }
+
+    /// How to compress the blocks. The default is ZLib, or LZO for version 1 archives which can
+    /// use nothing else.
+    pub fn compression(self, compression: CompressionAlgorithm) -> Self {
+        BARWriter {
+            compression,
+            ..self
+        }
+    }
+
+    /// Bytes of verse text to put in a block before starting a new one. Chapters always start
+    /// a new block and a block always holds at least one verse.
+    pub fn block_size(self, block_size: usize) -> Self {
+        BARWriter { block_size, ..self }
+    }
+
+    pub fn add_book(&mut self, book: BookSource) -> BARResult<()> {
+        if self
+            .books
+            .iter()
+            .any(|added| added.book_number == book.book_number)
+        {
+            return Err(BARFileError::ReferenceError(format!(
+                "{} has already been added",
+                barbook::book_name(book.book_number)
+            )));
+        }
+        if self.books.len() >= usize::from(self.header.number_of_books) {
+            return Err(BARFileError::ReferenceError(format!(
+                "The archive only has room for {} books",
+                self.header.number_of_books
+            )));
+        }
+        // Books are written in canonical order whatever order they are added in
+        let index = self
+            .books
+            .partition_point(|added| added.book_number < book.book_number);
+        self.books.insert(index, book);
+        Ok(())
+    }
+
+    /// Write the archive to a new file. Every book is checked and compressed before the file is
+    /// created.
+    pub fn write(self, file_path: impl AsRef<Path>) -> Result<BARFile<File>, Box<dyn Error>> {
+        let encoded = self.encode()?;
+        let barfile = BARFile::create_with_options(file_path, self.header)?;
+        Ok(Self::write_books(barfile, &self.books, encoded)?)
+    }
+
+    /// Write the archive to anything that can be written and seeked, such as a
+    /// `Cursor<Vec<u8>>`, starting at the beginning. Every book is checked and compressed before
+    /// anything is written. Get the target back with [`BARFile::into_inner`].
+    ///
+    /// ```rust
+    /// # fn main() -> Result<(), Box<dyn std::error::Error>> {
+    /// use biblearchive::BARFile;
+    /// use biblearchive::writer::{BARWriter, BookSource, ChapterSource};
+    /// use std::io::Cursor;
+    /// let mut book = BookSource::new(43);
+    /// book.chapters.push(ChapterSource::new(11, vec![String::new(); 35]));
+    /// book.chapters[0].verses[34] = "Jesus wept.".to_string();
+    /// let mut writer = BARWriter::new("KJV");
+    /// writer.add_book(book)?;
+    /// let bytes = writer.write_to(Cursor::new(Vec::new()))?.into_inner().ok().unwrap();
+    /// let bar = BARFile::read(bytes)?;
+    /// let chapter = bar.book(43).unwrap().chapter(11).unwrap();
+    /// assert_eq!(&*chapter.verse_text(35)?, "Jesus wept.");
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub fn write_to<T: io::Write + io::Seek>(self, target: T) -> BARResult<BARFile<T>> {
+        let encoded = self.encode()?;
+        let barfile = BARFile::create_in(target, self.header)?;
+        Self::write_books(barfile, &self.books, encoded)
+    }
+
+    // Check and compress every book
+    fn encode(&self) -> BARResult<Vec<Vec<u8>>> {
+        let mut encoded: Vec<Vec<u8>> = Vec::new();
+        for book in &self.books {
+            encoded.push(encode_book(
+                book,
+                &self.header,
+                &self.compression,
+                self.block_size,
+            )?);
+        }
+        Ok(encoded)
+    }
+
+    fn write_books<T: io::Write + io::Seek>(
+        mut barfile: BARFile<T>,
+        books: &[BookSource],
+        encoded: Vec<Vec<u8>>,
+    ) -> BARResult<BARFile<T>> {
+        for (book, bytes) in books.iter().zip(encoded) {
+            barfile.write_book(book.book_number, &bytes)?;
+        }
+        Ok(barfile)
+    }
+}
+
+impl<T: io::Write + io::Seek> BARFile<T> {
+    /// Add an encoded book at the end of the file and in the next empty slot of the book index
+    pub(crate) fn write_book(&mut self, book_number: u8, bytes: &[u8]) -> BARResult<()> {
+        let index = self
+            .book_index
+            .iter()
+            .position(|entry| matches!(entry, BARBookIndexEntry::Empty))
+            .ok_or_else(|| BARFileError::ReferenceError("The book index is full".to_string()))?;
+        self.write_book_at(index, book_number, bytes)
+    }
+
+    /// Add an encoded book at the end of the file and point a slot of the book index at it
+    pub(crate) fn write_book_at(
+        &mut self,
+        index: usize,
+        book_number: u8,
+        bytes: &[u8],
+    ) -> BARResult<()> {
+        let file = &mut *self.file.borrow_mut();
+        let file_offset = u32::try_from(file.seek(SeekFrom::End(0))?).map_err(|_| {
+            BARFileError::InvalidFileFormat("Archive is too big for a 32 bit offset".to_string())
+        })?;
+        file.write_all(bytes)?;
+        let entry = BARBookIndexEntry::Live {
+            book_number,
+            file_offset,
+        };
+        let entry_offset = BARFileHeader::byte_size() + index * BARBookIndexEntry::byte_size();
+        file.seek(SeekFrom::Start(entry_offset as u64))?;
+        entry.write_to(file)?;
+        file.flush()?;
+        self.book_index[index] = entry;
+        Ok(())
+    }
+}
+
+/// The bytes of a book entry, with offsets relative to the start of the entry
+pub(crate) fn encode_book(
+    book: &BookSource,
+    header: &BARFileHeader,
+    compression: &CompressionAlgorithm,
+    block_size: usize,
+) -> BARResult<Vec<u8>> {
+    check_version(header)?;
+    if book.book_number == 0 || book.book_number > 66 {
+        return Err(BARFileError::ReferenceError(format!(
+            "Invalid book number {}",
+            book.book_number
+        )));
+    }
+    let book_name = barbook::book_name(book.book_number);
+    let mut chapters: Vec<&ChapterSource> = book.chapters.iter().collect();
+    chapters.sort_by_key(|chapter| chapter.chapter_number);
+    for (i, chapter) in chapters.iter().enumerate() {
+        if chapter.chapter_number == 0
+            || (i > 0 && chapters[i - 1].chapter_number == chapter.chapter_number)
+        {
+            return Err(BARFileError::ReferenceError(format!(
+                "Invalid or repeated chapter {} in {}",
+                chapter.chapter_number, book_name
+            )));
+        }
+    }
+    let number_of_chapters = chapters.last().map_or(0, |chapter| chapter.chapter_number);
+    let layout = BookLayout::new(header, number_of_chapters);
+
+    let slots = usize::from(number_of_chapters);
+    let header_size = layout.header_size;
+    let mut offsets: Vec<u32> = vec![0; slots];
+    let mut counts: Vec<u8> = vec![0; slots];
+    let mut section: Vec<u8> = Vec::new();
+    let mut note_lines: Vec<String> = Vec::new();
+    let mut word_lines: Vec<String> = Vec::new();
+    let mut data: Vec<u8> = Vec::new();
+    for chapter in chapters {
+        let mut encoded =
+            encode_chapter(chapter, book.book_number, header, compression, block_size)?;
+        let index = usize::from(chapter.chapter_number - 1);
+        offsets[index] = (header_size + data.len()) as u32;
+        counts[index] = encoded.verse_count;
+        data.append(&mut encoded.blocks);
+        note_lines.append(&mut encoded.notes);
+        word_lines.append(&mut encoded.interlinear);
+        if let Some(mut record) = encoded.annotations {
+            section.append(&mut record);
+        }
+    }
+    // End of book
+    data.push(0);
+
+    let mut annotations_offset: u32 = 0;
+    if !section.is_empty() {
+        annotations_offset = (header_size + data.len()) as u32;
+        data.extend_from_slice(&(section.len() as u32).to_le_bytes());
+        data.append(&mut section);
+    }
+    let section_offset = |lines: &[String], data: &mut Vec<u8>| -> BARResult<u32> {
+        let mut section = text_section(lines, compression)?;
+        if section.is_empty() {
+            return Ok(0);
+        }
+        let offset = (header_size + data.len()) as u32;
+        data.append(&mut section);
+        Ok(offset)
+    };
+    let notes_offset = section_offset(&note_lines, &mut data)?;
+    let interlinear_offset = section_offset(&word_lines, &mut data)?;
+
+    let mut result = BARBookHeader {
+        book_number: book.book_number,
+        number_of_chapters,
+    }
+    .to_bytes();
+    for offset in offsets {
+        let entry = match offset {
+            0 => BARChapterIndexEntry::Empty,
+            additional_offset => BARChapterIndexEntry::Live { additional_offset },
+        };
+        result.append(&mut entry.to_bytes());
+    }
+    if layout.verse_counts.is_some() {
+        result.append(&mut counts);
+    }
+    let section_offsets = [annotations_offset, notes_offset, interlinear_offset];
+    for offset in &section_offsets[..layout.sections] {
+        result.extend_from_slice(&offset.to_le_bytes());
+    }
+    result.append(&mut data);
+    Ok(result)
+}
+
+/// Version 1 and 2 archives can be written
+pub(crate) fn check_version(header: &BARFileHeader) -> BARResult<()> {
+    if !(1..=2).contains(&header.major_version) {
+        return Err(BARFileError::InvalidFileFormat(format!(
+            "Can not write version {}.{} archives",
+            header.major_version, header.minor_version
+        )));
+    }
+    Ok(())
+}
+
+/// Where the fields of a book header are, from the start of the book entry
+pub(crate) struct BookLayout {
+    /// Start of the verse count table, if the version has one
+    pub(crate) verse_counts: Option<usize>,
+    /// Start of the section offsets
+    pub(crate) section_offsets: usize,
+    /// Number of section offsets: annotations, notes then interlinear
+    pub(crate) sections: usize,
+    pub(crate) header_size: usize,
+}
+
+impl BookLayout {
+    pub(crate) fn new(header: &BARFileHeader, number_of_chapters: u8) -> Self {
+        let (major_version, minor_version) = (header.major_version, header.minor_version);
+        let slots = usize::from(number_of_chapters);
+        let index_end = BARBookHeader::byte_size() + slots * BARChapterIndexEntry::byte_size();
+        let (verse_counts, section_offsets) =
+            match barbook::has_verse_counts(major_version, minor_version) {
+                true => (Some(index_end), index_end + slots),
+                false => (None, index_end),
+            };
+        let sections = [
+            barbook::has_annotations(major_version, minor_version),
+            barbook::has_notes(major_version, minor_version),
+            barbook::has_interlinear(major_version, minor_version),
+        ]
+        .iter()
+        .filter(|present| **present)
+        .count();
+        BookLayout {
+            verse_counts,
+            section_offsets,
+            sections,
+            header_size: section_offsets + sections * 4,
+        }
+    }
+
+    /// Position of the chapter's entry in the chapter index
+    pub(crate) fn chapter_entry(&self, chapter_number: u8) -> usize {
+        BARBookHeader::byte_size()
+            + usize::from(chapter_number - 1) * BARChapterIndexEntry::byte_size()
+    }
+}
+
+/// The parts of one chapter for the book entry
+pub(crate) struct EncodedChapter {
+    /// The blocks, without the end of book marker
+    pub(crate) blocks: Vec<u8>,
+    pub(crate) verse_count: u8,
+    /// The record for the annotations section, if there are any headings or paragraphs
+    pub(crate) annotations: Option<Vec<u8>>,
+    /// Lines for the notes section in verse order
+    pub(crate) notes: Vec<String>,
+    /// Lines for the interlinear section in verse order
+    pub(crate) interlinear: Vec<String>,
+}
+
+/// Check and encode a chapter. Data the archive version can not hold is an error.
+pub(crate) fn encode_chapter(
+    chapter: &ChapterSource,
+    book_number: u8,
+    header: &BARFileHeader,
+    compression: &CompressionAlgorithm,
+    block_size: usize,
+) -> BARResult<EncodedChapter> {
+    let (major_version, minor_version) = (header.major_version, header.minor_version);
+    let book_name = barbook::book_name(book_number);
+    let number = chapter.chapter_number;
+    if chapter.verses.is_empty() || chapter.verses.len() > usize::from(u8::MAX) {
+        return Err(BARFileError::ReferenceError(format!(
+            "{} {} has {} verses",
+            book_name,
+            number,
+            chapter.verses.len()
+        )));
+    }
+    if chapter
+        .verses
+        .iter()
+        .any(|verse| verse.contains(['\n', '\r']))
+    {
+        return Err(BARFileError::InvalidFileFormat(format!(
+            "Verse text in {} {} contains a line break",
+            book_name, number
+        )));
+    }
+    if let Some(index) = chapter
+        .verses
+        .iter()
+        .position(|verse| !markup::is_valid(verse))
+    {
+        return Err(BARFileError::InvalidFileFormat(format!(
+            "Verse {} of {} {} has an unknown or unfinished markup tag",
+            index + 1,
+            book_name,
+            number
+        )));
+    }
+    // Version 1 block headers have no compression algorithm, so every block is LZO
+    if major_version == 1 && *compression != CompressionAlgorithm::Lzo {
+        return Err(BARFileError::InvalidFileFormat(format!(
+            "Version 1 archives can only use LZO compression, not {}",
+            compression
+        )));
+    }
+    let mut blocks: Vec<u8> = Vec::new();
+    let markup = barbook::has_markup(major_version, minor_version);
+    for (start_verse, end_verse, text) in pack(&chapter.verses, block_size, markup) {
+        if *compression == CompressionAlgorithm::Lzo && text.len() > lzo::MAX_SIZE as usize {
+            return Err(BARFileError::InvalidFileFormat(format!(
+                "A block of {} {} is {} bytes but LZO blocks can only hold {}",
+                book_name,
+                number,
+                text.len(),
+                lzo::MAX_SIZE
+            )));
+        }
+        let compressed = compress_block(compression, &text)?;
+        let block_header = BlockHeader::new(
+            major_version,
+            number,
+            start_verse,
+            end_verse,
+            compression.clone(),
+            compressed.len() as u32,
+        );
+        blocks.append(&mut block_header.to_bytes());
+        blocks.extend_from_slice(&compressed);
+    }
+
+    let mut lines: Vec<NoteLine> = chapter
+        .notes
+        .iter()
+        .map(|note| NoteLine::Note(number, note.clone()))
+        .chain(
+            chapter
+                .cross_references
+                .iter()
+                .map(|cross_reference| NoteLine::CrossReference(number, cross_reference.clone())),
+        )
+        .collect();
+    if !lines.is_empty() && !barbook::has_notes(major_version, minor_version) {
+        return Err(BARFileError::InvalidFileFormat(format!(
+            "Notes and cross references in {} {} need archive version 2.6",
+            book_name, number
+        )));
+    }
+    lines.sort_by_key(|line| line.verse());
+    let mut notes: Vec<String> = Vec::new();
+    for line in &lines {
+        notes.push(line.encode()?);
+    }
+
+    if chapter.interlinear.len() > chapter.verses.len() {
+        return Err(BARFileError::ReferenceError(format!(
+            "{} {} has interlinear data for verses it does not have",
+            book_name, number
+        )));
+    }
+    let mut interlinear: Vec<String> = Vec::new();
+    for (i, words) in chapter.interlinear.iter().enumerate() {
+        if words.is_empty() {
+            continue;
+        }
+        if !barbook::has_interlinear(major_version, minor_version) {
+            return Err(BARFileError::InvalidFileFormat(format!(
+                "Interlinear data in {} {} needs archive version 2.7",
+                book_name, number
+            )));
+        }
+        let verse = markup::strip(&chapter.verses[i]);
+        interlinear.push(interlinear::encode(number, i as u8 + 1, &verse, words)?);
+    }
+
+    let mut chapter_annotations = ChapterAnnotations {
+        paragraphs: chapter.paragraphs.clone(),
+        headings: chapter.headings.clone(),
+    };
+    chapter_annotations.paragraphs.sort();
+    chapter_annotations.paragraphs.dedup();
+    chapter_annotations
+        .headings
+        .sort_by_key(|heading| heading.verse);
+    let annotations = match chapter_annotations.is_empty() {
+        true => None,
+        false if !barbook::has_annotations(major_version, minor_version) => {
+            return Err(BARFileError::InvalidFileFormat(format!(
+                "Headings and paragraphs in {} {} need archive version 2.5",
+                book_name, number
+            )));
+        }
+        false => Some(annotations::encode(number, &chapter_annotations)?),
+    };
+
+    Ok(EncodedChapter {
+        blocks,
+        verse_count: chapter.verses.len() as u8,
+        annotations,
+        notes,
+        interlinear,
+    })
+}
+
+/// A compressed text section holding the lines: compression, size then the data. Empty when
+/// there are no lines.
+pub(crate) fn text_section(
+    lines: &[String],
+    compression: &CompressionAlgorithm,
+) -> BARResult<Vec<u8>> {
+    if lines.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut text = String::new();
+    for line in lines {
+        text.push_str(line);
+        text.push('\n');
+    }
+    let compressed = compress_block(compression, &text)?;
+    let mut section: Vec<u8> = vec![compression.into()];
+    section.extend_from_slice(&(compressed.len() as u32).to_le_bytes());
+    section.extend_from_slice(&compressed);
+    Ok(section)
+}
+
+// (start verse, end verse, text) of each block of a chapter. Markup is removed for archive
+// versions that do not have it.
+fn pack(verses: &[String], block_size: usize, markup: bool) -> Vec<(u8, u8, String)> {
+    let mut blocks: Vec<(u8, u8, String)> = Vec::new();
+    let mut start_verse: u8 = 1;
+    let mut text = String::new();
+    for (i, verse) in verses.iter().enumerate() {
+        let verse_number = i as u8 + 1;
+        let verse = match markup {
+            true => verse.clone(),
+            false => markup::strip(verse),
+        };
+        if !text.is_empty() && text.len() + verse.len() + 1 > block_size {
+            blocks.push((start_verse, verse_number - 1, std::mem::take(&mut text)));
+            start_verse = verse_number;
+        }
+        text.push_str(&verse);
+        text.push('\n');
+    }
+    blocks.push((start_verse, verses.len() as u8, text));
+    blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn verses(texts: &[&str]) -> Vec<String> {
+        texts.iter().map(|text| text.to_string()).collect()
+    }
+
+    #[test]
+    fn test_pack() {
+        let blocks = pack(&verses(&["One", "Two", "Three"]), 8, true);
+        assert_eq!(
+            blocks,
+            vec![
+                (1, 2, "One\nTwo\n".to_string()),
+                (3, 3, "Three\n".to_string())
+            ]
+        );
+        // A verse longer than a block still gets a block
+        let blocks = pack(&verses(&["A long verse", "B"]), 4, true);
+        assert_eq!(blocks[0], (1, 1, "A long verse\n".to_string()));
+        assert_eq!(blocks[1], (2, 2, "B\n".to_string()));
+        let blocks = pack(&verses(&["\x1BiA\x1BI"]), 100, false);
+        assert_eq!(blocks[0].2, "A\n");
+    }
+
+    #[test]
+    fn test_encode_checks() {
+        let header = BARFileHeader::default();
+        let encode = |book: &BookSource| {
+            encode_book(
+                book,
+                &header,
+                &CompressionAlgorithm::None,
+                DEFAULT_BLOCK_SIZE,
+            )
+        };
+        let mut book = BookSource::new(1);
+        book.chapters.push(ChapterSource::new(2, verses(&["A"])));
+        let bytes = encode(&book).unwrap();
+        // Header, two chapter slots, two verse counts, annotations, notes and interlinear offsets
+        assert_eq!(bytes[0..2], [1, 2]);
+        assert_eq!(bytes[2..6], [0; 4]);
+        assert_eq!(bytes[6..10], 24u32.to_le_bytes());
+        assert_eq!(bytes[10..12], [0, 1]);
+        assert_eq!(bytes[12..24], [0; 12]);
+        assert_eq!(bytes.len(), 24 + 8 + 2 + 1);
+
+        book.chapters.push(ChapterSource::new(2, verses(&["B"])));
+        assert!(encode(&book).is_err());
+        book.chapters[1] = ChapterSource::new(3, verses(&["B\nC"]));
+        assert!(encode(&book).is_err());
+        book.chapters[1] = ChapterSource::new(3, vec![]);
+        assert!(encode(&book).is_err());
+        assert!(encode(&BookSource::new(67)).is_err());
+    }
+}
@@ -0,0 +1,541 @@
+//! Edit an archive in place.
+//!
+//! New blocks and sections are always written at the end of the file first, and only then are
+//! the offsets in the chapter index, book header or book index changed to point at them. Each
+//! offset is a single small write, so at every step the file is an archive that any reader can
+//! read. An edit that changes several offsets, such as a chapter with its notes, is only complete
+//! once the last of them is written; the chapter offset goes first and a verse count last. What
+//! was replaced is left unused in the file until [`BARFile::compact`].
+
+use super::{
+    BookLayout, BookSource, ChapterSource, DEFAULT_BLOCK_SIZE, check_version, encode_book,
+    encode_chapter, text_section,
+};
+use crate::barbook::barchapter::{BARChapter, BlockHeader, CompressionAlgorithm, decompress_block};
+use crate::barbook::{BARBook, BARBookHeader, annotations, interlinear};
+use crate::error::{BARFileError, BARResult};
+use crate::reference::Reference;
+use crate::{BARBookIndexEntry, BARFile, BARFileHeader, BinaryStruct, markup};
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::Path;
+
+impl BARFile<File> {
+    /// Open an archive to be read and edited
+    pub fn open_for_edit(file_path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let file = OpenOptions::new().read(true).write(true).open(file_path)?;
+        BARFile::read(file)
+    }
+
+    /// Rewrite the archive without the space left unused by edits.
+    ///
+    /// Each book is laid out again straight after the book index, in index order, with its
+    /// blocks and sections copied as they are. The new layout is built in memory and then
+    /// written over the file, so unlike the edits the file can not be read while this runs.
+    pub fn compact(&mut self) -> BARResult<()> {
+        let image = self.compacted()?;
+        {
+            let file = &mut *self.file.borrow_mut();
+            file.seek(SeekFrom::Start(0))?;
+            file.write_all(&image)?;
+            file.set_len(image.len() as u64)?;
+            file.flush()?;
+        }
+        let mut index = &image[BARFileHeader::byte_size()..];
+        self.book_index = BARBookIndexEntry::read_array(self.book_index.len(), &mut index)?;
+        self.clear_cache();
+        Ok(())
+    }
+}
+
+impl<T: io::Read + io::Write + io::Seek> BARFile<T> {
+    /// Replace the text of a verse, which may contain [markup](crate::markup). The notes and
+    /// annotations of the verse are kept. Interlinear data for the verse is dropped if it no
+    /// longer lines up with the words.
+    ///
+    /// Books and chapters got from the archive before an edit still have the old text.
+    pub fn replace_verse(&mut self, reference: &Reference, text: &str) -> BARResult<()> {
+        let chapter = self
+            .book(reference.book)
+            .and_then(|book| book.chapter(reference.chapter))
+            .ok_or_else(|| not_in_archive(&reference.to_string()))?;
+        let mut source = chapter.to_source()?;
+        let index = usize::from(reference.verse)
+            .checked_sub(1)
+            .filter(|index| *index < source.verses.len())
+            .ok_or_else(|| not_in_archive(&reference.to_string()))?;
+        source.verses[index] = text.to_string();
+        if let Some(words) = source.interlinear.get_mut(index) {
+            let plain = markup::strip(text);
+            let verse_words = interlinear::words(&plain);
+            if verse_words.len() != words.len()
+                || verse_words
+                    .iter()
+                    .zip(words.iter())
+                    .any(|(verse_word, word)| *verse_word != word.word)
+            {
+                words.clear();
+            }
+        }
+        self.replace_chapter(reference.book, source)
+    }
+
+    /// Replace a chapter of a book in the archive, or add a chapter to it.
+    ///
+    /// The new blocks are linked in through the chapter index. A chapter after the last one in
+    /// the book has no slot in the chapter index, so then the whole book is written again.
+    pub fn replace_chapter(&mut self, book_number: u8, chapter: ChapterSource) -> BARResult<()> {
+        let book = self
+            .book(book_number)
+            .ok_or_else(|| not_in_archive(crate::barbook::book_name(book_number)))?;
+        let number = chapter.chapter_number;
+        if number == 0 || number > book.number_of_chapters() {
+            let mut source = book.to_source()?;
+            source
+                .chapters
+                .retain(|existing| existing.chapter_number != number);
+            source.chapters.push(chapter);
+            return self.replace_book(source);
+        }
+        check_version(&self.header)?;
+        let compression = edit_compression(&book)?;
+        let encoded = encode_chapter(
+            &chapter,
+            book_number,
+            &self.header,
+            &compression,
+            DEFAULT_BLOCK_SIZE,
+        )?;
+        let layout = BookLayout::new(&self.header, book.number_of_chapters());
+
+        // The sections of the book with the part for this chapter replaced. None where the
+        // section does not change.
+        let mut sections: Vec<Option<Vec<u8>>> = Vec::new();
+        if layout.sections > 0 {
+            let old = self.read_range(book.annotations_range()?)?;
+            let (mut records, found) = annotations::remove(old.get(4..).unwrap_or(&[]), number)?;
+            sections.push(match (found, encoded.annotations) {
+                (false, None) => None,
+                (_, record) => {
+                    records.extend(record.unwrap_or_default());
+                    let mut section: Vec<u8> = Vec::new();
+                    if !records.is_empty() {
+                        section.extend_from_slice(&(records.len() as u32).to_le_bytes());
+                        section.append(&mut records);
+                    }
+                    Some(section)
+                }
+            });
+        }
+        if layout.sections > 1 {
+            let old = self.read_text_section(book.notes_range()?)?;
+            let lines = merge_lines(old, number, encoded.notes);
+            sections.push(
+                lines
+                    .map(|lines| text_section(&lines, &compression))
+                    .transpose()?,
+            );
+        }
+        if layout.sections > 2 {
+            let old = self.read_text_section(book.interlinear_range()?)?;
+            let lines = merge_lines(old, number, encoded.interlinear);
+            sections.push(
+                lines
+                    .map(|lines| text_section(&lines, &compression))
+                    .transpose()?,
+            );
+        }
+
+        let book_offset = u64::from(book.file_offset());
+        let file = &mut *self.file.borrow_mut();
+        let end = file.seek(SeekFrom::End(0))?;
+        let relative = |position: u64| {
+            u32::try_from(position - book_offset).map_err(|_| {
+                BARFileError::InvalidFileFormat("Book is too big for a 32 bit offset".to_string())
+            })
+        };
+        let mut offsets: Vec<(usize, u32)> = vec![(layout.chapter_entry(number), relative(end)?)];
+        let mut data = encoded.blocks;
+        // End of book
+        data.push(0);
+        for (i, section) in sections.into_iter().enumerate() {
+            let Some(mut section) = section else {
+                continue;
+            };
+            let offset = match section.is_empty() {
+                true => 0,
+                false => relative(end + data.len() as u64)?,
+            };
+            offsets.push((layout.section_offsets + i * 4, offset));
+            data.append(&mut section);
+        }
+        file.write_all(&data)?;
+        // The chapter offset first, then the sections and the verse count last, so the count
+        // never describes blocks that are not linked in yet
+        for (position, offset) in offsets {
+            file.seek(SeekFrom::Start(book_offset + position as u64))?;
+            file.write_all(&offset.to_le_bytes())?;
+        }
+        if let Some(verse_counts) = layout.verse_counts {
+            let position = verse_counts + usize::from(number - 1);
+            file.seek(SeekFrom::Start(book_offset + position as u64))?;
+            file.write_all(&[encoded.verse_count])?;
+        }
+        file.flush()?;
+        self.cache.borrow_mut().clear();
+        Ok(())
+    }
+
+    /// Replace a book in the archive. The new book is linked in through the book index.
+    pub fn replace_book(&mut self, book: BookSource) -> BARResult<()> {
+        let index = self
+            .book_index
+            .iter()
+            .position(|entry| {
+                matches!(entry, BARBookIndexEntry::Live { book_number, .. }
+                    if *book_number == book.book_number)
+            })
+            .ok_or_else(|| not_in_archive(crate::barbook::book_name(book.book_number)))?;
+        let compression = match self.book(book.book_number) {
+            Some(existing) => edit_compression(&existing)?,
+            None => CompressionAlgorithm::ZLib,
+        };
+        let bytes = encode_book(&book, &self.header, &compression, DEFAULT_BLOCK_SIZE)?;
+        self.write_book_at(index, book.book_number, &bytes)?;
+        self.cache.borrow_mut().clear();
+        Ok(())
+    }
+
+    /// Add a book to the archive in the next empty slot of the book index. A full book index is
+    /// first [grown](BARFile::grow_book_index) to twice the size, up to room for all 66 books.
+    pub fn append_book(&mut self, book: BookSource) -> BARResult<()> {
+        let present = self.book_index.iter().any(|entry| {
+            matches!(entry, BARBookIndexEntry::Live { book_number, .. }
+                if *book_number == book.book_number)
+        });
+        if present {
+            return Err(BARFileError::ReferenceError(format!(
+                "{} is already in the archive",
+                crate::barbook::book_name(book.book_number)
+            )));
+        }
+        let compression = match self.books().next() {
+            Some(existing) => edit_compression(&existing)?,
+            None => CompressionAlgorithm::ZLib,
+        };
+        // Encode first so that a book that can not be written leaves the file as it was
+        let bytes = encode_book(&book, &self.header, &compression, DEFAULT_BLOCK_SIZE)?;
+        let full = !self
+            .book_index
+            .iter()
+            .any(|entry| matches!(entry, BARBookIndexEntry::Empty));
+        if full {
+            let capacity = (self.book_index.len() * 2).clamp(1, 66);
+            self.grow_book_index(capacity as u8)?;
+        }
+        self.write_book(book.book_number, &bytes)
+    }
+
+    /// Make room in the book index for `capacity` books.
+    ///
+    /// Everything after the book index is moved along to make space for the new entries and the
+    /// book offsets changed to match. This rewrites the file from the end of the old book index,
+    /// so unlike the other edits the file can not be read while it runs.
+    pub fn grow_book_index(&mut self, capacity: u8) -> BARResult<()> {
+        let old = self.book_index.len();
+        let new = usize::from(capacity);
+        if new < old || new > 66 {
+            return Err(BARFileError::ReferenceError(format!(
+                "Can not change the book index from {} to {} books",
+                old, capacity
+            )));
+        }
+        if new == old {
+            return Ok(());
+        }
+        let shift = ((new - old) * BARBookIndexEntry::byte_size()) as u32;
+        let mut index: Vec<BARBookIndexEntry> = Vec::new();
+        for entry in &self.book_index {
+            index.push(match entry {
+                BARBookIndexEntry::Live {
+                    book_number,
+                    file_offset,
+                } => BARBookIndexEntry::Live {
+                    book_number: *book_number,
+                    file_offset: file_offset.checked_add(shift).ok_or_else(|| {
+                        BARFileError::InvalidFileFormat(
+                            "Archive is too big for a 32 bit offset".to_string(),
+                        )
+                    })?,
+                },
+                BARBookIndexEntry::Empty => BARBookIndexEntry::Empty,
+            });
+        }
+        index.resize_with(new, BARBookIndexEntry::default);
+
+        {
+            let file = &mut *self.file.borrow_mut();
+            let start = BARFileHeader::byte_size() + old * BARBookIndexEntry::byte_size();
+            file.seek(SeekFrom::Start(start as u64))?;
+            let mut rest: Vec<u8> = Vec::new();
+            file.read_to_end(&mut rest)?;
+            // Write a copy so that the header and index in memory only change once the file
+            // has been written
+            let header = BARFileHeader {
+                leader: self.header.leader,
+                major_version: self.header.major_version,
+                minor_version: self.header.minor_version,
+                number_of_books: capacity,
+                version_abbrev: self.header.version_abbrev.clone(),
+            };
+            file.seek(SeekFrom::Start(0))?;
+            header.write_to(file)?;
+            BARBookIndexEntry::write_array(&index, file)?;
+            file.write_all(&rest)?;
+            file.flush()?;
+        }
+        self.header.number_of_books = capacity;
+        self.book_index = index;
+        self.clear_cache();
+        Ok(())
+    }
+}
+
+impl<T: io::Read + io::Seek> BARFile<T> {
+    /// The bytes of the archive laid out with nothing unused
+    pub(crate) fn compacted(&self) -> BARResult<Vec<u8>> {
+        let start =
+            BARFileHeader::byte_size() + self.book_index.len() * BARBookIndexEntry::byte_size();
+        let mut index: Vec<BARBookIndexEntry> = Vec::new();
+        let mut books: Vec<u8> = Vec::new();
+        for i in 0..self.book_index.len() {
+            match self.try_book_from_index(i as u8)? {
+                Some(book) => {
+                    let file_offset = u32::try_from(start + books.len()).map_err(|_| {
+                        BARFileError::InvalidFileFormat(
+                            "Archive is too big for a 32 bit offset".to_string(),
+                        )
+                    })?;
+                    index.push(BARBookIndexEntry::Live {
+                        book_number: book.book_number(),
+                        file_offset,
+                    });
+                    books.append(&mut self.book_bytes(&book, &self.header)?);
+                }
+                None => index.push(BARBookIndexEntry::Empty),
+            }
+        }
+        let mut image = self.header.to_bytes();
+        for entry in &index {
+            image.append(&mut entry.to_bytes());
+        }
+        image.append(&mut books);
+        Ok(image)
+    }
+
+    /// The book entry laid out for an archive with the header, with its chapters one after
+    /// another and then its sections. The blocks and sections are copied as they are, so the
+    /// header must have the same major version as this archive, and allow markup if this archive
+    /// does.
+    pub(crate) fn book_bytes(
+        &self,
+        book: &BARBook<T>,
+        header: &BARFileHeader,
+    ) -> BARResult<Vec<u8>> {
+        let layout = BookLayout::new(header, book.number_of_chapters());
+        let start = u64::from(book.file_offset());
+        let mut entry =
+            self.read_range(Some((start, start + BARBookHeader::byte_size() as u64)))?;
+        entry.resize(layout.header_size, 0);
+        if let Some(position) = layout.verse_counts {
+            let counts = book.verse_counts()?;
+            entry[position..position + counts.len()].copy_from_slice(&counts);
+        }
+        let block_header_size = BlockHeader::size_for_version(self.header.major_version)? as u64;
+        let mut data: Vec<u8> = Vec::new();
+        for number in 1..=book.number_of_chapters() {
+            let mut offset: u32 = 0;
+            if let Some(chapter) = book.try_chapter(number)? {
+                offset = (layout.header_size + data.len()) as u32;
+                for block in chapter.blocks()?.iter() {
+                    let block_start = u64::from(block.file_offset);
+                    let block_end = block_start + block_header_size + u64::from(block.block_size);
+                    data.append(&mut self.read_range(Some((block_start, block_end)))?);
+                }
+            }
+            let position = layout.chapter_entry(number);
+            entry[position..position + 4].copy_from_slice(&offset.to_le_bytes());
+        }
+        // End of book
+        data.push(0);
+        let sections = [
+            ("annotations", book.annotations_range()?),
+            ("notes", book.notes_range()?),
+            ("interlinear data", book.interlinear_range()?),
+        ];
+        for (i, (name, range)) in sections.iter().enumerate() {
+            if range.is_none() {
+                continue;
+            }
+            if i >= layout.sections {
+                return Err(BARFileError::InvalidFileFormat(format!(
+                    "{} has {} which can not be written to a version {}.{} archive",
+                    book.book_name(),
+                    name,
+                    header.major_version,
+                    header.minor_version
+                )));
+            }
+            let offset = (layout.header_size + data.len()) as u32;
+            data.append(&mut self.read_range(*range)?);
+            let position = layout.section_offsets + i * 4;
+            entry[position..position + 4].copy_from_slice(&offset.to_le_bytes());
+        }
+        entry.append(&mut data);
+        Ok(entry)
+    }
+
+    // The bytes in a file range, empty for None
+    fn read_range(&self, range: Option<(u64, u64)>) -> BARResult<Vec<u8>> {
+        let Some((start, end)) = range else {
+            return Ok(Vec::new());
+        };
+        let reader = &mut *self.file.borrow_mut();
+        reader.seek(SeekFrom::Start(start))?;
+        let mut buf: Vec<u8> = vec![0; (end - start) as usize];
+        reader.read_exact(&mut buf)?;
+        Ok(buf)
+    }
+
+    // The lines of a compressed text section
+    fn read_text_section(&self, range: Option<(u64, u64)>) -> BARResult<Vec<String>> {
+        let bytes = self.read_range(range)?;
+        if bytes.len() < 5 {
+            return Ok(Vec::new());
+        }
+        let text = decompress_block(&CompressionAlgorithm::from(bytes[0]), &bytes[5..])?;
+        Ok(text.lines().map(str::to_string).collect())
+    }
+}
+
+impl<T: io::Read + io::Seek> BARBook<T> {
+    /// The chapters of the book as they would be written, to be changed and written back
+    pub fn to_source(&self) -> BARResult<BookSource> {
+        let mut book = BookSource::new(self.book_number());
+        for chapter in self.try_chapters() {
+            if let Some(chapter) = chapter? {
+                book.chapters.push(chapter.to_source()?);
+            }
+        }
+        Ok(book)
+    }
+}
+
+impl<T: io::Read + io::Seek> BARChapter<T> {
+    /// The chapter as it would be written, with its markup, annotations, notes and interlinear
+    /// data, to be changed and written back
+    pub fn to_source(&self) -> BARResult<ChapterSource> {
+        let mut verses: Vec<String> = Vec::new();
+        for entry in self.blocks()?.iter() {
+            if usize::from(entry.start_verse) != verses.len() + 1 {
+                return Err(BARFileError::InvalidFileFormat(format!(
+                    "Chapter {} of {} has a block starting at verse {} so can not be rewritten",
+                    self.chapter_number(),
+                    crate::barbook::book_name(self.book_number()),
+                    entry.start_verse
+                )));
+            }
+            let text = self.decompress_uncached(entry)?;
+            let lines = text.lines().count();
+            if entry.end_verse < entry.start_verse
+                || lines != usize::from(entry.end_verse - entry.start_verse) + 1
+            {
+                return Err(BARFileError::InvalidFileFormat(format!(
+                    "Block of {} {} for verses {} to {} has {} lines",
+                    crate::barbook::book_name(self.book_number()),
+                    self.chapter_number(),
+                    entry.start_verse,
+                    entry.end_verse,
+                    lines
+                )));
+            }
+            verses.extend(text.lines().map(str::to_string));
+        }
+        let mut chapter = ChapterSource::new(self.chapter_number(), verses);
+        chapter.headings = self.headings()?;
+        chapter.paragraphs = self.paragraphs()?;
+        for verse in 1..=chapter.verses.len() as u8 {
+            chapter.notes.append(&mut self.notes(verse)?);
+            chapter
+                .cross_references
+                .append(&mut self.cross_references(verse)?);
+        }
+        for (verse, words) in self.interlinear_verses()? {
+            let index = usize::from(verse) - 1;
+            if chapter.interlinear.len() <= index {
+                chapter.interlinear.resize(index + 1, Vec::new());
+            }
+            chapter.interlinear[index] = words;
+        }
+        Ok(chapter)
+    }
+}
+
+fn not_in_archive(what: &str) -> BARFileError {
+    BARFileError::ReferenceError(format!("{} is not in the archive", what))
+}
+
+// New blocks use the compression the book already uses, ZLib for a book with no chapters
+pub(super) fn edit_compression<T: io::Read + io::Seek>(
+    book: &BARBook<T>,
+) -> BARResult<CompressionAlgorithm> {
+    match book.chapters().flatten().next() {
+        Some(chapter) => Ok(chapter.blocks()?[0].compression_algorithm.clone()),
+        None => Ok(CompressionAlgorithm::ZLib),
+    }
+}
+
+// Replace the lines for a chapter in a text section, keeping chapter and verse order. None when
+// the chapter had no lines and still has none, so the section does not change.
+fn merge_lines(old: Vec<String>, chapter_number: u8, new: Vec<String>) -> Option<Vec<String>> {
+    let prefix = format!("{} ", chapter_number);
+    let (removed, mut lines): (Vec<String>, Vec<String>) =
+        old.into_iter().partition(|line| line.starts_with(&prefix));
+    if removed.is_empty() && new.is_empty() {
+        return None;
+    }
+    lines.extend(new);
+    lines.sort_by_key(|line| {
+        let mut numbers = line.split(' ').map(|part| part.parse::<u8>().unwrap_or(0));
+        (numbers.next(), numbers.next())
+    });
+    Some(lines)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(lines: &[&str]) -> Vec<String> {
+        lines.iter().map(|line| line.to_string()).collect()
+    }
+
+    #[test]
+    fn test_merge_lines() {
+        let old = lines(&["1 2 - f One", "2 1 - f Two", "10 1 - f Ten"]);
+        assert_eq!(merge_lines(old.clone(), 3, Vec::new()), None);
+        assert_eq!(
+            merge_lines(old.clone(), 2, lines(&["2 3 - f New"])).unwrap(),
+            lines(&["1 2 - f One", "2 3 - f New", "10 1 - f Ten"])
+        );
+        assert_eq!(
+            merge_lines(old.clone(), 1, Vec::new()).unwrap(),
+            lines(&["2 1 - f Two", "10 1 - f Ten"])
+        );
+        assert_eq!(
+            merge_lines(old, 3, lines(&["3 1 - f Three"])).unwrap()[2],
+            "3 1 - f Three"
+        );
+    }
+}
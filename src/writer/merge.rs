@@ -0,0 +1,197 @@
+//! Combine archives, or take some of the books of an archive.
+//!
+//! The new archive has the books in canonical order. Books are copied without decompressing
+//! them when the archives have the same major version, and so the same block headers. Otherwise,
+//! or when the book has markup that the new archive version can not hold, each book is read and
+//! compressed again.
+
+use super::edit::edit_compression;
+use super::{DEFAULT_BLOCK_SIZE, encode_book};
+use crate::barbook::barchapter::CompressionAlgorithm;
+use crate::barbook::{self, BARBook};
+use crate::error::{BARFileError, BARResult};
+use crate::{BARFile, BARFileHeader};
+use std::error::Error;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+/// What [`merge`] does when more than one archive has a book
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ConflictPolicy {
+    /// Merging fails
+    #[default]
+    Error,
+    /// Keep the book from the first archive that has it
+    KeepFirst,
+    /// Keep the book from the last archive that has it
+    KeepLast,
+}
+
+// The header of an archive and its books, encoded and ready to write
+type EncodedArchive = (BARFileHeader, Vec<(u8, Vec<u8>)>);
+
+/// Write a new archive with the books of several archives. It has the version abbreviation of
+/// the first archive, the latest archive version of them all and room for just the books it
+/// has.
+pub fn merge<T: io::Read + io::Seek>(
+    file_path: impl AsRef<Path>,
+    archives: &[&BARFile<T>],
+    policy: ConflictPolicy,
+) -> Result<BARFile<File>, Box<dyn Error>> {
+    let (header, encoded) = merge_books(archives, policy)?;
+    let barfile = BARFile::create_with_options(file_path, header)?;
+    Ok(write_books(barfile, encoded)?)
+}
+
+/// Like [`merge`] but writes the archive to anything that can be written and seeked, such as a
+/// `Cursor<Vec<u8>>`, starting at the beginning
+pub fn merge_to<T: io::Read + io::Seek, W: io::Write + io::Seek>(
+    target: W,
+    archives: &[&BARFile<T>],
+    policy: ConflictPolicy,
+) -> BARResult<BARFile<W>> {
+    let (header, encoded) = merge_books(archives, policy)?;
+    write_books(BARFile::create_in(target, header)?, encoded)
+}
+
+// The header and encoded books of the merged archive
+fn merge_books<T: io::Read + io::Seek>(
+    archives: &[&BARFile<T>],
+    policy: ConflictPolicy,
+) -> BARResult<EncodedArchive> {
+    let first = archives.first().ok_or_else(|| {
+        BARFileError::ReferenceError("There are no archives to merge".to_string())
+    })?;
+    let mut books: Vec<(&BARFile<T>, BARBook<T>)> = Vec::new();
+    for archive in archives {
+        for book in archive.try_books() {
+            let book = book?;
+            let existing = books
+                .iter()
+                .position(|(_, added)| added.book_number() == book.book_number());
+            match (existing, policy) {
+                (None, _) => books.push((*archive, book)),
+                (Some(_), ConflictPolicy::Error) => {
+                    return Err(BARFileError::ReferenceError(format!(
+                        "{} is in more than one archive",
+                        book.book_name()
+                    )));
+                }
+                (Some(_), ConflictPolicy::KeepFirst) => {}
+                (Some(index), ConflictPolicy::KeepLast) => books[index] = (*archive, book),
+            }
+        }
+    }
+    // The latest version can hold everything the others can, whatever order they are given in
+    let (major_version, minor_version) = archives
+        .iter()
+        .map(|archive| (archive.header.major_version, archive.header.minor_version))
+        .max()
+        .unwrap_or((first.header.major_version, first.header.minor_version));
+    encode_books(
+        &first.header.version_abbrev,
+        major_version,
+        minor_version,
+        books,
+    )
+}
+
+impl<T: io::Read + io::Seek> BARFile<T> {
+    /// Write a new archive with only the chosen books of this one, which can be given as a
+    /// range such as `40..=66`. Books that are not in this archive are left out.
+    pub fn extract(
+        &self,
+        file_path: impl AsRef<Path>,
+        books: impl IntoIterator<Item = u8>,
+    ) -> Result<BARFile<File>, Box<dyn Error>> {
+        let (header, encoded) = self.extract_books(books)?;
+        let barfile = BARFile::create_with_options(file_path, header)?;
+        Ok(write_books(barfile, encoded)?)
+    }
+
+    /// Like [`BARFile::extract`] but writes the archive to anything that can be written and
+    /// seeked, starting at the beginning
+    pub fn extract_to<W: io::Write + io::Seek>(
+        &self,
+        target: W,
+        books: impl IntoIterator<Item = u8>,
+    ) -> BARResult<BARFile<W>> {
+        let (header, encoded) = self.extract_books(books)?;
+        write_books(BARFile::create_in(target, header)?, encoded)
+    }
+
+    fn extract_books(&self, books: impl IntoIterator<Item = u8>) -> BARResult<EncodedArchive> {
+        let mut chosen: Vec<(&BARFile<T>, BARBook<T>)> = Vec::new();
+        for book_number in books {
+            if chosen
+                .iter()
+                .any(|(_, book)| book.book_number() == book_number)
+            {
+                continue;
+            }
+            if let Some(book) = self.book(book_number) {
+                chosen.push((self, book));
+            }
+        }
+        encode_books(
+            &self.header.version_abbrev,
+            self.header.major_version,
+            self.header.minor_version,
+            chosen,
+        )
+    }
+}
+
+// The header for the books and every book encoded for it, before anything is written
+fn encode_books<T: io::Read + io::Seek>(
+    version_abbrev: &str,
+    major_version: u8,
+    minor_version: u8,
+    mut books: Vec<(&BARFile<T>, BARBook<T>)>,
+) -> BARResult<EncodedArchive> {
+    if books.is_empty() {
+        return Err(BARFileError::ReferenceError(
+            "There are no books to write".to_string(),
+        ));
+    }
+    books.sort_by_key(|(_, book)| book.book_number());
+    let header = BARFileHeader::builder(version_abbrev)
+        .version(major_version, minor_version)
+        .book_capacity(books.len() as u8)
+        .build()?;
+    let mut encoded: Vec<(u8, Vec<u8>)> = Vec::new();
+    for (archive, book) in &books {
+        encoded.push((book.book_number(), copy_book(archive, book, &header)?));
+    }
+    Ok((header, encoded))
+}
+
+fn write_books<W: io::Write + io::Seek>(
+    mut barfile: BARFile<W>,
+    encoded: Vec<(u8, Vec<u8>)>,
+) -> BARResult<BARFile<W>> {
+    for (book_number, bytes) in encoded {
+        barfile.write_book(book_number, &bytes)?;
+    }
+    Ok(barfile)
+}
+
+// The bytes of a book entry for an archive with the header
+fn copy_book<T: io::Read + io::Seek>(
+    archive: &BARFile<T>,
+    book: &BARBook<T>,
+    header: &BARFileHeader,
+) -> BARResult<Vec<u8>> {
+    let drops_markup =
+        barbook::has_markup(archive.header.major_version, archive.header.minor_version)
+            && !barbook::has_markup(header.major_version, header.minor_version);
+    if archive.header.major_version == header.major_version && !drops_markup {
+        return archive.book_bytes(book, header);
+    }
+    let compression = match header.major_version {
+        1 => CompressionAlgorithm::Lzo,
+        _ => edit_compression(book)?,
+    };
+    encode_book(&book.to_source()?, header, &compression, DEFAULT_BLOCK_SIZE)
+}
@@ -0,0 +1,631 @@
+//! Import books from USFM (Unified Standard Format Markers).
+//!
+//! Chapters (`\c`), verses (`\v`), section headings (`\s`, `\s1`...), paragraphs (`\p`, `\pi`...)
+//! and poetry lines (`\q`, `\q1`...) are imported, along with the character styles that have
+//! [markup](crate::markup): `\add` (italic), `\wj` (red letter) and `\nd` (small caps).
+//! Footnotes (`\f`) and cross references (`\x`) are kept as notes on the verse, at the
+//! character where they were found. Cross references that do not parse as a [`Passage`] are
+//! left out. Titles and introductions are skipped. Any other marker is dropped but its text is
+//! kept. The `strong`, `lemma` and `x-morph` attributes of words such as
+//! `\w grace|strong="G5485"\w*` become [interlinear](crate::barbook::interlinear) data for the
+//! verse, and other attributes are dropped.
+
+use super::{BookSource, ChapterSource};
+use crate::barbook::annotations::Heading;
+use crate::barbook::interlinear::{self, InterlinearWord};
+use crate::barbook::notes::{CrossReference, Note};
+use crate::error::{BARFileError, BARResult};
+use crate::markup::{self, ESCAPE};
+use crate::reference::Passage;
+
+/// USFM book identifiers in canonical order
+pub const BOOK_IDS: [&str; 66] = [
+    "GEN", "EXO", "LEV", "NUM", "DEU", "JOS", "JDG", "RUT", "1SA", "2SA", "1KI", "2KI", "1CH",
+    "2CH", "EZR", "NEH", "EST", "JOB", "PSA", "PRO", "ECC", "SNG", "ISA", "JER", "LAM", "EZK",
+    "DAN", "HOS", "JOL", "AMO", "OBA", "JON", "MIC", "NAM", "HAB", "ZEP", "HAG", "ZEC", "MAL",
+    "MAT", "MRK", "LUK", "JHN", "ACT", "ROM", "1CO", "2CO", "GAL", "EPH", "PHP", "COL", "1TH",
+    "2TH", "1TI", "2TI", "TIT", "PHM", "HEB", "JAS", "1PE", "2PE", "1JN", "2JN", "3JN", "JUD",
+    "REV",
+];
+
+// Markers whose whole line is not verse text
+const SKIPPED_LINES: [&str; 17] = [
+    "ide", "h", "toc", "toca", "mt", "mte", "ms", "mr", "r", "sr", "d", "sp", "rem", "sts", "usfm",
+    "cl", "cd",
+];
+
+// Markers that start a paragraph
+const PARAGRAPHS: [&str; 7] = ["p", "pi", "pmo", "pc", "pr", "po", "ph"];
+
+// Notes. They are closed by the same marker with a *
+const NOTES: [&str; 5] = ["f", "fe", "x", "ef", "ex"];
+
+// Parts of a note giving the reference of the verse it is on
+const NOTE_ORIGINS: [&str; 2] = ["fr", "xo"];
+
+#[derive(Debug, PartialEq)]
+enum Token<'a> {
+    Marker(&'a str),
+    Text(&'a str),
+}
+
+// Split a line into markers and the text between them
+fn tokens(line: &str) -> Vec<Token<'_>> {
+    let mut result: Vec<Token> = Vec::new();
+    let mut rest = line;
+    while !rest.is_empty() {
+        match rest.find('\\') {
+            Some(0) => {
+                let mut end = rest[1..]
+                    .find(|c: char| c.is_whitespace() || c == '\\' || c == '*')
+                    .map_or(rest.len(), |i| i + 1);
+                let closing = rest[end..].starts_with('*');
+                if closing {
+                    end += 1;
+                }
+                // Markers nested in a character style start with +
+                result.push(Token::Marker(rest[1..end].trim_start_matches('+')));
+                rest = &rest[end..];
+                // The space after an opening marker is part of the marker
+                if !closing && rest.starts_with(char::is_whitespace) {
+                    let space = rest.chars().next().unwrap().len_utf8();
+                    rest = &rest[space..];
+                }
+            }
+            Some(i) => {
+                result.push(Token::Text(&rest[..i]));
+                rest = &rest[i..];
+            }
+            None => {
+                result.push(Token::Text(rest));
+                rest = "";
+            }
+        }
+    }
+    result
+}
+
+// The marker without its level number, and the level (1 if there is none)
+fn marker_level(marker: &str) -> (&str, u8) {
+    let name = marker.trim_end_matches(|c: char| c.is_ascii_digit());
+    let level = marker[name.len()..].parse().unwrap_or(1);
+    (name, level)
+}
+
+fn number(text: &str, what: &str) -> BARResult<(u8, String)> {
+    let text = text.trim_start();
+    let end = text
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(text.len());
+    let value: u8 = text[..end]
+        .parse()
+        .map_err(|_| BARFileError::InvalidFileFormat(format!("Invalid USFM {} number", what)))?;
+    // Skip the rest of a verse range such as 1-2
+    let rest = text[end..].trim_start_matches(|c: char| !c.is_whitespace());
+    Ok((value, rest.to_string()))
+}
+
+fn normalize(text: &str) -> String {
+    text.split_whitespace().collect::<Vec<&str>>().join(" ")
+}
+
+// A footnote or cross reference being read
+struct OpenNote {
+    closing: String,
+    cross_reference: bool,
+    offset: Option<u32>,
+    text: String,
+    caller: bool,   // the caller (+, - or a letter) has not been read yet
+    skipping: bool, // in the reference of the verse the note is on
+}
+
+#[derive(Default)]
+struct Importer {
+    book_number: Option<u8>,
+    chapters: Vec<ChapterSource>,
+    verse: Option<usize>, // index of the current verse in the current chapter
+    paragraph: bool,
+    headings: Vec<(u8, String)>, // (level, text) waiting for the next verse
+    line: Option<u8>,            // poetry line waiting for the next text
+    indent: u8,
+    note: Option<OpenNote>,
+    attributes: Option<String>, // closing marker of a word with attributes
+    words: Vec<(usize, usize, InterlinearWord)>, // (chapter index, verse index, word)
+}
+
+impl Importer {
+    fn line(&mut self, line: &str) -> BARResult<()> {
+        let tokens = tokens(line.trim());
+        if let Some(Token::Marker(marker)) = tokens.first() {
+            let (name, level) = marker_level(marker);
+            if name == "id" {
+                return self.id(&tokens);
+            }
+            if name == "s" {
+                let text = plain_text(&tokens[1..]);
+                if !text.is_empty() {
+                    self.headings.push((level, text));
+                }
+                return Ok(());
+            }
+            if SKIPPED_LINES.contains(&name) || (name.starts_with('i') && name != "it") {
+                return Ok(());
+            }
+        }
+        let mut tokens = tokens.into_iter();
+        while let Some(token) = tokens.next() {
+            match token {
+                Token::Text(text) => self.text(text),
+                Token::Marker(marker) if self.note.is_some() => self.note_marker(marker),
+                Token::Marker("c") => {
+                    let text = match tokens.next() {
+                        Some(Token::Text(text)) => text,
+                        _ => "",
+                    };
+                    let (chapter_number, _) = number(text, "chapter")?;
+                    self.chapters
+                        .push(ChapterSource::new(chapter_number, Vec::new()));
+                    self.verse = None;
+                }
+                Token::Marker("v") => {
+                    let text = match tokens.next() {
+                        Some(Token::Text(text)) => text,
+                        _ => "",
+                    };
+                    let (verse_number, rest) = number(text, "verse")?;
+                    self.start_verse(verse_number)?;
+                    self.text(&rest);
+                }
+                Token::Marker(marker) => self.marker(marker),
+            }
+        }
+        // Lines of the same verse are joined with a space
+        self.text(" ");
+        Ok(())
+    }
+
+    fn id(&mut self, tokens: &[Token]) -> BARResult<()> {
+        let id = match tokens.get(1) {
+            Some(Token::Text(text)) => text.split_whitespace().next().unwrap_or(""),
+            _ => "",
+        };
+        let index = BOOK_IDS
+            .iter()
+            .position(|book_id| book_id.eq_ignore_ascii_case(id))
+            .ok_or_else(|| BARFileError::ReferenceError(format!("Unknown USFM book {}", id)))?;
+        self.book_number = Some(index as u8 + 1);
+        Ok(())
+    }
+
+    fn marker(&mut self, marker: &str) {
+        let (name, level) = marker_level(marker);
+        let tag = match marker {
+            "add" => Some('i'),
+            "add*" => Some('I'),
+            "wj" => Some('r'),
+            "wj*" => Some('R'),
+            "nd" => Some('k'),
+            "nd*" => Some('K'),
+            _ => None,
+        };
+        if let Some(tag) = tag {
+            self.push(&format!("{}{}", ESCAPE, tag));
+        } else if NOTES.contains(&marker) {
+            self.open_note(marker);
+        } else if ["w", "rb", "jmp"].contains(&marker) {
+            self.attributes = Some(format!("{}*", marker));
+        } else if self.attributes.as_deref() == Some(marker) {
+            self.attributes = None;
+        } else if PARAGRAPHS.contains(&name) {
+            self.paragraph = true;
+            if self.indent > 0 {
+                self.indent = 0;
+                self.line = Some(0);
+            }
+        } else if name == "q" || name == "qm" {
+            self.indent = level.min(9);
+            self.line = Some(self.indent);
+        }
+    }
+
+    fn start_verse(&mut self, verse_number: u8) -> BARResult<()> {
+        let Some(chapter) = self.chapters.last_mut() else {
+            return Err(BARFileError::InvalidFileFormat(
+                "USFM verse before the first chapter".to_string(),
+            ));
+        };
+        if verse_number == 0 {
+            return Err(BARFileError::InvalidFileFormat(
+                "Invalid USFM verse number".to_string(),
+            ));
+        }
+        let index = usize::from(verse_number - 1);
+        if chapter.verses.len() <= index {
+            chapter.verses.resize(index + 1, String::new());
+        }
+        if self.paragraph {
+            chapter.paragraphs.push(verse_number);
+            self.paragraph = false;
+        }
+        for (level, text) in self.headings.drain(..) {
+            chapter.headings.push(Heading {
+                verse: verse_number,
+                level,
+                text,
+            });
+        }
+        self.verse = Some(index);
+        Ok(())
+    }
+
+    fn open_note(&mut self, marker: &str) {
+        // Notes are attached to the character of the plain verse text they come after
+        let offset = self
+            .verse
+            .zip(self.chapters.last())
+            .map(|(index, chapter)| {
+                normalize(&markup::strip(&chapter.verses[index]))
+                    .chars()
+                    .count() as u32
+            });
+        self.note = Some(OpenNote {
+            closing: format!("{}*", marker),
+            cross_reference: marker.ends_with('x'),
+            offset,
+            text: String::new(),
+            caller: true,
+            skipping: false,
+        });
+    }
+
+    fn note_marker(&mut self, marker: &str) {
+        let Some(note) = self.note.as_mut() else {
+            return;
+        };
+        if marker == note.closing {
+            self.close_note();
+        } else if NOTE_ORIGINS.contains(&marker) {
+            note.skipping = true;
+        } else if !marker.ends_with('*') {
+            note.skipping = false;
+        }
+    }
+
+    fn close_note(&mut self) {
+        let Some(note) = self.note.take() else {
+            return;
+        };
+        let (Some(index), Some(chapter)) = (self.verse, self.chapters.last_mut()) else {
+            return;
+        };
+        let verse = index as u8 + 1;
+        let text = normalize(&note.text);
+        if note.cross_reference {
+            let targets: Vec<Passage> = text
+                .split(';')
+                .filter_map(|target| target.trim().parse().ok())
+                .collect();
+            if !targets.is_empty() {
+                chapter.cross_references.push(CrossReference {
+                    verse,
+                    offset: note.offset,
+                    targets,
+                });
+            }
+        } else if !text.is_empty() {
+            chapter.notes.push(Note {
+                verse,
+                offset: note.offset,
+                text,
+            });
+        }
+    }
+
+    fn text(&mut self, text: &str) {
+        if let Some(note) = self.note.as_mut() {
+            let mut text = text;
+            if note.caller && !text.trim().is_empty() {
+                let caller = text.trim_start();
+                text = caller
+                    .split_once(char::is_whitespace)
+                    .map_or("", |(_, rest)| rest);
+                note.caller = false;
+            }
+            if !note.skipping {
+                note.text.push_str(text);
+            }
+            return;
+        }
+        let text = match self.attributes.as_deref() {
+            Some(closing) => {
+                let (word, attributes) = text.split_once('|').unwrap_or((text, ""));
+                if closing == "w*"
+                    && let Some(word) = word_attributes(word, attributes)
+                    && let Some(index) = self.verse
+                {
+                    self.words.push((self.chapters.len() - 1, index, word));
+                }
+                word
+            }
+            None => text,
+        };
+        if !text.trim().is_empty()
+            && let Some(indent) = self.line.take()
+        {
+            // The new line starts with the text
+            self.push(&format!(" {}{}", ESCAPE, indent));
+            self.push(text.trim_start());
+            return;
+        }
+        self.push(text);
+    }
+
+    fn push(&mut self, text: &str) {
+        if let (Some(index), Some(chapter)) = (self.verse, self.chapters.last_mut()) {
+            chapter.verses[index].push_str(text);
+        }
+    }
+
+    fn finish(self) -> BARResult<BookSource> {
+        let book_number = self
+            .book_number
+            .ok_or_else(|| BARFileError::ReferenceError("USFM has no \\id line".to_string()))?;
+        let mut chapters = self.chapters;
+        for chapter in chapters.iter_mut() {
+            for verse in chapter.verses.iter_mut() {
+                *verse = normalize(verse);
+            }
+        }
+        let mut words = self.words.into_iter().peekable();
+        while let Some((chapter_index, verse_index, word)) = words.next() {
+            let mut verse_words = vec![word];
+            while let Some((_, _, word)) =
+                words.next_if(|(c, v, _)| (*c, *v) == (chapter_index, verse_index))
+            {
+                verse_words.push(word);
+            }
+            let chapter = &mut chapters[chapter_index];
+            let text = markup::strip(&chapter.verses[verse_index]);
+            if let Some(aligned) = align_words(&text, verse_words) {
+                if chapter.interlinear.len() <= verse_index {
+                    chapter.interlinear.resize(verse_index + 1, Vec::new());
+                }
+                chapter.interlinear[verse_index] = aligned;
+            }
+        }
+        Ok(BookSource {
+            book_number,
+            chapters,
+        })
+    }
+}
+
+// The lexical data of a word from its attributes, if it has any
+fn word_attributes(word: &str, attributes: &str) -> Option<InterlinearWord> {
+    let mut result = InterlinearWord {
+        word: word.trim().to_string(),
+        ..Default::default()
+    };
+    let attributes = attributes.trim();
+    if !attributes.is_empty() && !attributes.contains('=') {
+        // The default attribute of a word is the lemma
+        result.lemma = Some(attributes.to_string());
+    }
+    let mut rest = attributes;
+    while let Some((name, value)) = rest.split_once("=\"") {
+        let Some((value, after)) = value.split_once('"') else {
+            break;
+        };
+        let value = Some(value.trim().to_string()).filter(|value| !value.is_empty());
+        match name.trim() {
+            "strong" => result.strongs = value,
+            "lemma" => result.lemma = value,
+            "x-morph" | "morph" => result.morph = value,
+            _ => {}
+        }
+        rest = after;
+    }
+    match (&result.strongs, &result.lemma, &result.morph) {
+        (None, None, None) => None,
+        _ => Some(result),
+    }
+}
+
+// Line the words with attributes up with the words of the plain verse text. The other words
+// get no data. None if a word is not found in the text.
+fn align_words(text: &str, words: Vec<InterlinearWord>) -> Option<Vec<InterlinearWord>> {
+    let text = interlinear::words(text);
+    let mut result: Vec<InterlinearWord> = Vec::new();
+    for mut word in words {
+        let surface = match interlinear::words(&word.word)[..] {
+            [surface] => surface,
+            _ => return None,
+        };
+        let position = text[result.len()..]
+            .iter()
+            .position(|text| *text == surface)?;
+        for text in &text[result.len()..result.len() + position] {
+            result.push(InterlinearWord {
+                word: text.to_string(),
+                ..Default::default()
+            });
+        }
+        word.word = surface.to_string();
+        result.push(word);
+    }
+    for text in &text[result.len()..] {
+        result.push(InterlinearWord {
+            word: text.to_string(),
+            ..Default::default()
+        });
+    }
+    Some(result)
+}
+
+// Text of the tokens without markers or notes
+fn plain_text(tokens: &[Token]) -> String {
+    let mut text = String::new();
+    let mut note: Option<String> = None;
+    for token in tokens {
+        match token {
+            Token::Marker(marker) if note.as_deref() == Some(*marker) => note = None,
+            Token::Marker(marker) if NOTES.contains(marker) => note = Some(format!("{}*", marker)),
+            Token::Text(part) if note.is_none() => text.push_str(part),
+            _ => {}
+        }
+    }
+    normalize(&text)
+}
+
+/// Import one book of USFM
+pub fn parse(text: &str) -> BARResult<BookSource> {
+    let mut importer = Importer::default();
+    for line in text.lines() {
+        importer.line(line)?;
+    }
+    importer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GENESIS: &str = r#"\id GEN Test text
+\h Genesis
+\mt1 The First Book of Moses
+\c 1
+\s1 The Creation
+\p
+\v 1 In the beginning God created the heaven and the earth.
+\v 2 And the earth was without form, and void;\f + \fr 1:2 \ft Or empty\f* and darkness
+was upon the face of the deep.\x - \xo 1:2 \xt Joh 1:1; Heb 11:3-4\x*
+\p
+\v 3 And God said, Let there be light: and there \add was\add* light.
+\c 2
+\q1 \v 1 The \nd Lord\nd* is my \w shepherd|strong="H7462"\w*;
+\q2 I shall not want.
+\p \v 2 He maketh me to lie down
+"#;
+
+    #[test]
+    fn test_tokens() {
+        assert_eq!(
+            tokens(r"\v 1 In \add the\add* \+nd Lord\+nd*"),
+            vec![
+                Token::Marker("v"),
+                Token::Text("1 In "),
+                Token::Marker("add"),
+                Token::Text("the"),
+                Token::Marker("add*"),
+                Token::Text(" "),
+                Token::Marker("nd"),
+                Token::Text("Lord"),
+                Token::Marker("nd*"),
+            ]
+        );
+        assert_eq!(marker_level("s2"), ("s", 2));
+        assert_eq!(marker_level("p"), ("p", 1));
+    }
+
+    #[test]
+    fn test_parse() {
+        let book = parse(GENESIS).unwrap();
+        assert_eq!(book.book_number, 1);
+        assert_eq!(book.chapters.len(), 2);
+        let chapter = &book.chapters[0];
+        assert_eq!(chapter.chapter_number, 1);
+        assert_eq!(
+            chapter.verses[1],
+            "And the earth was without form, and void; and darkness was upon the face of the deep."
+        );
+        assert_eq!(
+            chapter.verses[2],
+            "And God said, Let there be light: and there \x1Biwas\x1BI light."
+        );
+        assert_eq!(chapter.paragraphs, vec![1, 3]);
+        assert_eq!(
+            chapter.notes,
+            vec![Note {
+                verse: 2,
+                offset: Some(41),
+                text: "Or empty".to_string()
+            }]
+        );
+        assert_eq!(chapter.cross_references.len(), 1);
+        assert_eq!(chapter.cross_references[0].offset, Some(85));
+        assert_eq!(
+            chapter.cross_references[0].targets,
+            vec![
+                "Joh 1:1".parse::<Passage>().unwrap(),
+                "Heb 11:3-4".parse::<Passage>().unwrap()
+            ]
+        );
+        assert_eq!(
+            chapter.headings,
+            vec![Heading {
+                verse: 1,
+                level: 1,
+                text: "The Creation".to_string()
+            }]
+        );
+
+        let chapter = &book.chapters[1];
+        assert_eq!(
+            chapter.verses[0],
+            "\x1B1The \x1BkLord\x1BK is my shepherd; \x1B2I shall not want."
+        );
+        assert_eq!(chapter.verses[1], "\x1B0He maketh me to lie down");
+        assert_eq!(chapter.paragraphs, vec![2]);
+        assert_eq!(chapter.interlinear.len(), 1);
+        let words = &chapter.interlinear[0];
+        assert_eq!(words.len(), 9);
+        assert_eq!(words[4].word, "shepherd");
+        assert_eq!(words[4].strongs.as_deref(), Some("H7462"));
+        assert_eq!(
+            words[5],
+            InterlinearWord {
+                word: "I".to_string(),
+                ..Default::default()
+            }
+        );
+        assert!(book.chapters[0].interlinear.is_empty());
+    }
+
+    #[test]
+    fn test_word_attributes() {
+        let word = word_attributes(
+            "λόγος",
+            r#"lemma="λόγος" strong="G3056" x-morph="Gr,N,,,,,NMS," x-occurrence="1""#,
+        )
+        .unwrap();
+        assert_eq!(word.strongs.as_deref(), Some("G3056"));
+        assert_eq!(word.lemma.as_deref(), Some("λόγος"));
+        assert_eq!(word.morph.as_deref(), Some("Gr,N,,,,,NMS,"));
+        assert_eq!(
+            word_attributes("grace", "charis").unwrap().lemma.as_deref(),
+            Some("charis")
+        );
+        assert!(word_attributes("grace", r#"x-note="none""#).is_none());
+
+        let aligned = align_words(
+            "In the beginning, was",
+            vec![word_attributes("beginning", "arche").unwrap()],
+        );
+        assert_eq!(aligned.unwrap()[2].lemma.as_deref(), Some("arche"));
+        assert!(
+            align_words(
+                "In the end",
+                vec![word_attributes("beginning", "arche").unwrap()]
+            )
+            .is_none()
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse("\\c 1\n\\v 1 No id").is_err());
+        assert!(parse("\\id XYZ\n").is_err());
+        assert!(parse("\\id GEN\n\\v 1 No chapter").is_err());
+        assert!(parse("\\id GEN\n\\c x").is_err());
+    }
+}
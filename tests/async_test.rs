@@ -0,0 +1,62 @@
+#![cfg(feature = "async")]
+use biblearchive::asyncbar::AsyncBARFile;
+use futures_util::StreamExt;
+
+#[tokio::test]
+async fn test_async_barfile() {
+    let bar = AsyncBARFile::open("tests/data/KJV.ibar")
+        .await
+        .expect("Failed to load KJV.ibar from tests/data");
+    assert_eq!(bar.archive_version().to_string(), "2.1".to_string());
+    assert_eq!(bar.bible_version(), "KJV");
+    assert_eq!(bar.number_of_books(), 3);
+    let book = bar.book(27).await.expect("Failed to get book 27");
+    assert_eq!(book.book_abbrev(), "Da");
+    assert_eq!(book.book_name(), "Daniel");
+    assert_eq!(book.number_of_chapters(), 12);
+    assert!(book.chapter(2).await.is_none());
+    assert!(bar.book(2).await.is_none());
+}
+
+#[tokio::test]
+async fn test_async_verses() {
+    let bar = AsyncBARFile::open("tests/data/KJV.ibar")
+        .await
+        .expect("Failed to load KJV.ibar from tests/data");
+
+    let ge = bar.book_from_abbrev("Ge").await.unwrap();
+    let chapt1 = ge.chapter(1).await.unwrap();
+    assert_eq!(chapt1.number_of_verses(), 31);
+    assert_eq!(
+        chapt1.verse_text(27).await.unwrap(),
+        "So God created man in his own image, in the image of God created he him; male and female created he them."
+    );
+    let text = chapt1.chapter_text().await.unwrap();
+    assert_eq!(crc32fast::hash(text.as_bytes()), 2672530595);
+
+    let eph = bar.book_from_abbrev("Eph").await.unwrap();
+    let chapt4 = eph.chapter(4).await.unwrap();
+    assert!(chapt4.verse_text(33).await.is_err());
+    let verses: Vec<String> = chapt4.verses().map(|verse| verse.unwrap()).collect().await;
+    assert_eq!(verses.len(), 32);
+    assert_eq!(
+        verses[10],
+        "And he gave some, apostles; and some, prophets; and some, evangelists; and some, pastors and teachers;"
+    );
+}
+
+fn assert_send<T: Send>(_: &T) {}
+
+#[tokio::test]
+async fn test_async_is_send() {
+    let bar = AsyncBARFile::open("tests/data/KJV.ibar").await.unwrap();
+    let book = bar.book(1).await.unwrap();
+    let chapter = book.chapter(1).await.unwrap();
+    let future = chapter.verse_text(1);
+    assert_send(&future);
+    assert_send(&chapter.verses());
+    assert_eq!(
+        future.await.unwrap(),
+        "In the beginning God created the heaven and the earth."
+    );
+}
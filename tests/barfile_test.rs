@@ -1,5 +1,5 @@
-use biblearchive::{self, BARFile};
-use crc32fast;
+use biblearchive::search::SearchOptions;
+use biblearchive::{self, BARFile, Passage, Reference};
 
 #[test]
 fn test_barfile() {
@@ -85,8 +85,8 @@ fn test_iterators() {
         output.push(format!("Chapters: {}", book.number_of_chapters()));
         let mut count = 0;
         for chapter in book.chapters() {
-            if chapter.is_some() {
-                output.push(format!("- Chapter {}", chapter.unwrap().chapter_number()));
+            if let Some(chapter) = chapter {
+                output.push(format!("- Chapter {}", chapter.chapter_number()));
             }
             count += 1;
         }
@@ -132,7 +132,7 @@ fn test_verses() {
     let chapt1 = ge.chapter(1).unwrap();
     let verse = chapt1.verse_text(27).unwrap();
     assert_eq!(
-        verse,
+        &*verse,
         "So God created man in his own image, in the image of God created he him; male and female created he them."
     );
 
@@ -140,7 +140,7 @@ fn test_verses() {
     let chapt1 = da.chapter(1).unwrap();
     let verse = chapt1.verse_text(21).unwrap();
     assert_eq!(
-        verse,
+        &*verse,
         "And Daniel continued even unto the first year of king Cyrus."
     );
 
@@ -148,7 +148,7 @@ fn test_verses() {
     let chapt4 = eph.chapter(4).unwrap();
     let verse = chapt4.verse_text(11).unwrap();
     assert_eq!(
-        verse,
+        &*verse,
         "And he gave some, apostles; and some, prophets; and some, evangelists; and some, pastors and teachers;"
     );
 
@@ -201,18 +201,16 @@ fn test_verse_iterator() {
     let bar =
         BARFile::open("tests/data/KJV.ibar").expect("Failed to load KJV.ibar from tests/data");
     for book in bar.books() {
-        for chapter in book.chapters() {
-            if let Some(chapter) = chapter {
-                for (i, verse) in chapter.verses().enumerate() {
-                    if verse.contains("God") {
-                        println!(
-                            "{} {}:{} {}",
-                            book.book_abbrev(),
-                            chapter.chapter_number(),
-                            i + 1,
-                            verse
-                        )
-                    }
+        for chapter in book.chapters().flatten() {
+            for (i, verse) in chapter.verses().enumerate() {
+                if verse.contains("God") {
+                    println!(
+                        "{} {}:{} {}",
+                        book.book_abbrev(),
+                        chapter.chapter_number(),
+                        i + 1,
+                        verse
+                    )
                 }
             }
         }
@@ -230,3 +228,328 @@ fn test_books_in_order() {
     assert_eq!(it.next().unwrap().book_abbrev(), "Eph");
     assert!(it.next().is_none());
 }
+
+#[test]
+fn test_block_cache() {
+    let bar =
+        BARFile::open("tests/data/KJV.ibar").expect("Failed to load KJV.ibar from tests/data");
+    let ge = bar.book_from_abbrev("Ge").unwrap();
+    let first = ge.chapter(1).unwrap().verse_text(1).unwrap();
+    let stats = bar.cache_stats();
+    assert_eq!(stats.hits, 0);
+    assert_eq!(stats.misses, 1);
+    assert_eq!(stats.entries, 1);
+
+    // A freshly built chapter should reuse the block decompressed above
+    let second = ge.chapter(1).unwrap().verse_text(1).unwrap();
+    assert_eq!(&*first, &*second);
+    let stats = bar.cache_stats();
+    assert_eq!(stats.hits, 1);
+    assert_eq!(stats.misses, 1);
+
+    bar.clear_cache();
+    assert_eq!(bar.cache_stats().entries, 0);
+    assert_eq!(bar.cache_stats().used_bytes, 0);
+
+    // With no budget nothing is kept
+    bar.set_cache_budget(0);
+    ge.chapter(1).unwrap().chapter_text().unwrap();
+    ge.chapter(1).unwrap().chapter_text().unwrap();
+    let stats = bar.cache_stats();
+    assert_eq!(stats.hits, 1);
+    assert_eq!(stats.misses, 3);
+    assert_eq!(stats.entries, 0);
+}
+
+#[test]
+fn test_block_directory() {
+    let bar =
+        BARFile::open("tests/data/KJV.ibar").expect("Failed to load KJV.ibar from tests/data");
+    let ge = bar.book_from_abbrev("Ge").unwrap();
+    let blocks = ge.chapter(1).unwrap().blocks().unwrap();
+    assert_eq!(blocks.len(), 1);
+    assert_eq!(blocks[0].file_offset, 1876);
+    assert_eq!(blocks[0].start_verse, 1);
+    assert_eq!(blocks[0].end_verse, 31);
+    assert_eq!(blocks[0].block_size, 1140);
+    assert_eq!(blocks[0].compression_algorithm.to_string(), "ZLIB");
+}
+
+#[test]
+fn test_search() {
+    let bar =
+        BARFile::open("tests/data/KJV.ibar").expect("Failed to load KJV.ibar from tests/data");
+    let hits = bar.search("Belteshazzar");
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].reference.to_string(), "Da 1:7");
+    assert!(hits[0].text.contains("Belteshazzar"));
+    let hits = bar.search("light");
+    // Canonical order, so Genesis first
+    assert_eq!(hits[0].reference.book, 1);
+    assert!(bar.search("no such text").is_empty());
+}
+
+#[test]
+fn test_search_with() {
+    let bar =
+        BARFile::open("tests/data/KJV.ibar").expect("Failed to load KJV.ibar from tests/data");
+    assert!(bar.search("belteshazzar").is_empty());
+    let options = SearchOptions {
+        ignore_case: true,
+        ..Default::default()
+    };
+    let hits = bar.search_with("belteshazzar", options);
+    assert_eq!(hits.len(), 1);
+    assert_eq!(hits[0].reference.to_string(), "Da 1:7");
+    assert_eq!(hits[0].spans.len(), 1);
+    assert_eq!(&hits[0].text[hits[0].spans[0].clone()], "Belteshazzar");
+    let hits = bar.search_with("LIGHT", options);
+    assert_eq!(hits.len(), bar.search("light").len());
+    assert!(hits.iter().all(|hit| !hit.spans.is_empty()));
+}
+
+#[test]
+fn test_stats() {
+    let bar =
+        BARFile::open("tests/data/KJV.ibar").expect("Failed to load KJV.ibar from tests/data");
+    let stats = bar.stats().expect("Failed to get stats");
+    assert_eq!(stats.file_size, 4572);
+    assert_eq!(stats.unused_bytes, 0);
+    assert_eq!(stats.totals.chapters, 3);
+    assert_eq!(stats.totals.verses, 21 + 31 + 32);
+    assert_eq!(stats.totals.blocks, 3);
+    assert_eq!(stats.totals.compressed_size, 1269 + 1140 + 1512);
+    let abbrevs: Vec<&str> = stats.books.iter().map(|b| b.abbrev.as_str()).collect();
+    assert_eq!(abbrevs, vec!["Ge", "Da", "Eph"]);
+    let ge = &stats.books[0].totals;
+    let text = bar
+        .book(1)
+        .unwrap()
+        .chapter(1)
+        .unwrap()
+        .chapter_text()
+        .unwrap();
+    assert_eq!(ge.uncompressed_size, text.len() as u64);
+    assert_eq!(ge.words, text.split_whitespace().count() as u64);
+    assert_eq!(stats.compression.len(), 1);
+    assert_eq!(stats.compression[0].blocks, 3);
+    assert_eq!(stats.largest_blocks[0].reference.to_string(), "Eph 4:1");
+    assert_eq!(stats.largest_blocks[0].file_offset, 3051);
+    // Stats bypass the block cache
+    assert_eq!(bar.cache_stats().entries, 1);
+    assert!(stats.to_string().starts_with("KJV (archive version 2.1)"));
+}
+
+#[test]
+fn test_stats_corrupt_block() {
+    // A block that ends before it starts is an error rather than a panic
+    let mut bytes = std::fs::read("tests/data/KJV.ibar").unwrap();
+    bytes[3051 + 2] = 0;
+    let bar = BARFile::read(std::io::Cursor::new(bytes)).unwrap();
+    assert!(bar.stats().is_err());
+    // So is a book that can not be read, rather than being left out of the totals
+    let mut bytes = std::fs::read("tests/data/KJV.ibar").unwrap();
+    bytes[1674] = 2;
+    let bar = BARFile::read(std::io::Cursor::new(bytes)).unwrap();
+    assert!(bar.stats().is_err());
+}
+
+#[test]
+fn test_verse_counts() {
+    let bar =
+        BARFile::open("tests/data/KJV.ibar").expect("Failed to load KJV.ibar from tests/data");
+    let eph = bar.book_from_abbrev("Eph").unwrap();
+    assert_eq!(eph.verse_counts().unwrap(), vec![0, 0, 0, 32, 0, 0]);
+}
+
+#[test]
+fn test_navigator() {
+    let bar =
+        BARFile::open("tests/data/KJV.ibar").expect("Failed to load KJV.ibar from tests/data");
+    let mut nav = bar.navigator().expect("Failed to build navigator");
+    assert_eq!(nav.len(), 31 + 21 + 32);
+    assert_eq!(nav.position(), Reference::new(1, 1, 1));
+    assert!(nav.text().unwrap().starts_with("In the beginning"));
+    assert_eq!(nav.previous_verse(), None);
+    assert_eq!(nav.ordinal(), 1);
+
+    // Ge 1:31 is followed by Da 1:1 as nothing else is in the archive
+    assert_eq!(
+        nav.go_to(&Reference::new(1, 1, 31)),
+        Some(Reference::new(1, 1, 31))
+    );
+    assert_eq!(nav.next_verse(), Some(Reference::new(27, 1, 1)));
+    assert_eq!(nav.ordinal(), 32);
+    assert_eq!(nav.previous_verse(), Some(Reference::new(1, 1, 31)));
+
+    nav.go_to(&Reference::new(27, 1, 7));
+    assert!(nav.text().unwrap().contains("Belteshazzar"));
+    assert_eq!(nav.next_chapter(), Some(Reference::new(49, 4, 1)));
+    assert_eq!(nav.next_chapter(), None);
+    assert_eq!(nav.next_book(), None);
+    assert_eq!(nav.previous_book(), Some(Reference::new(27, 1, 1)));
+    assert_eq!(nav.previous_chapter(), Some(Reference::new(1, 1, 1)));
+    assert_eq!(nav.next_book(), Some(Reference::new(27, 1, 1)));
+
+    // Missing verses, chapters and ordinals leave the position alone
+    assert_eq!(nav.go_to(&Reference::new(1, 2, 1)), None);
+    assert_eq!(nav.go_to(&Reference::new(27, 1, 22)), None);
+    assert_eq!(nav.go_to_ordinal(85), None);
+    assert_eq!(nav.position(), Reference::new(27, 1, 1));
+
+    assert_eq!(nav.ordinal_of(&Reference::new(49, 4, 32)), Some(84));
+    assert_eq!(nav.reference_at(53), Some(Reference::new(49, 4, 1)));
+    for ordinal in 1..=nav.len() {
+        let reference = nav.reference_at(ordinal).unwrap();
+        assert_eq!(nav.ordinal_of(&reference), Some(ordinal));
+    }
+}
+
+#[test]
+fn test_archive_verses() {
+    let bar =
+        BARFile::open("tests/data/KJV.ibar").expect("Failed to load KJV.ibar from tests/data");
+    let verses = bar.verses().unwrap();
+    assert_eq!(verses.size_hint(), (0, Some(84)));
+    let all: Vec<(Reference, String)> = verses.map(|(r, text)| (r, text.to_string())).collect();
+    assert_eq!(all.len(), 84);
+    assert_eq!(all[0].0, Reference::new(1, 1, 1));
+    assert_eq!(all[31].0, Reference::new(27, 1, 1));
+    assert_eq!(all[83].0, Reference::new(49, 4, 32));
+    let chapter = bar.book(27).unwrap().chapter(1).unwrap();
+    assert_eq!(all[31 + 6].1, &*chapter.verse_text(7).unwrap());
+
+    // Backwards gives the same verses in reverse
+    let reversed: Vec<Reference> = bar.verses().unwrap().rev().map(|(r, _)| r).collect();
+    let forward: Vec<Reference> = all.iter().map(|(r, _)| *r).collect();
+    assert_eq!(reversed, forward.into_iter().rev().collect::<Vec<_>>());
+
+    // Meeting in the middle
+    let mut verses = bar.verses().unwrap();
+    assert_eq!(verses.next().unwrap().0, Reference::new(1, 1, 1));
+    assert_eq!(verses.next_back().unwrap().0, Reference::new(49, 4, 32));
+    assert_eq!(verses.count(), 82);
+
+    // Passages only give the verses in the archive
+    let passage = Passage::new(Reference::new(1, 1, 30), Reference::new(27, 1, 2));
+    let refs: Vec<String> = bar
+        .passage_verses(&passage)
+        .unwrap()
+        .map(|(r, _)| r.to_string())
+        .collect();
+    assert_eq!(refs, vec!["Ge 1:30", "Ge 1:31", "Da 1:1", "Da 1:2"]);
+    let passage = Passage::new(Reference::new(2, 1, 1), Reference::new(40, 1, 1));
+    assert_eq!(bar.passage_verses(&passage).unwrap().count(), 21);
+    let passage = Passage::new(Reference::new(2, 1, 1), Reference::new(3, 1, 1));
+    assert_eq!(bar.passage_verses(&passage).unwrap().count(), 0);
+
+    let verses = bar.try_verses().expect("Failed to read verse counts");
+    assert_eq!(verses.len(), 84);
+    assert!(verses.into_iter().all(|verse| verse.is_ok()));
+}
+
+#[test]
+fn test_try_books() {
+    let mut bytes = std::fs::read("tests/data/KJV.ibar").expect("Failed to read KJV.ibar");
+    let bar = BARFile::read(std::io::Cursor::new(bytes.clone())).unwrap();
+    assert_eq!(bar.try_books().filter(|book| book.is_ok()).count(), 3);
+    for book in bar.try_books() {
+        for chapter in book.unwrap().try_chapters() {
+            if let Some(chapter) = chapter.unwrap() {
+                assert!(chapter.try_verses().all(|verse| verse.is_ok()));
+            }
+        }
+    }
+
+    // Damage the book header of Genesis
+    bytes[1674] = 2;
+    let bar = BARFile::read(std::io::Cursor::new(bytes)).unwrap();
+    let books: Vec<_> = bar.try_books().collect();
+    assert_eq!(books.len(), 3);
+    assert!(books[0].is_ok());
+    assert!(books[1].is_err());
+    assert!(books[2].is_ok());
+    // books() stops at the damaged book
+    assert_eq!(bar.books().count(), 1);
+    // Walking the whole archive reports the damaged book
+    assert!(bar.try_verses().is_err());
+    assert!(bar.verses().is_err());
+    assert!(bar.navigator().is_err());
+}
+
+#[test]
+fn test_reading_plan() {
+    use biblearchive::readingplan::{Balance, ReadingPlan};
+    let bar =
+        BARFile::open("tests/data/KJV.ibar").expect("Failed to load KJV.ibar from tests/data");
+    let plan = bar.balanced_plan(2, Balance::Verses).unwrap();
+    assert_eq!(plan.to_string(), "Ge 1:1-Da 1:21\nEph 4:1-32\n");
+    let plan = bar.balanced_plan(3, Balance::Words).unwrap();
+    assert_eq!(plan.len(), 3);
+    assert_eq!(plan.day(2).unwrap()[0].to_string(), "Da 1:1-21");
+    assert!(bar.balanced_plan(4, Balance::Verses).is_err());
+
+    let plan: ReadingPlan = "Ge 1; Da 1:7\nEph 4:30-5:2\nEx 1".parse().unwrap();
+    let day = bar.plan_day(&plan, 1).unwrap();
+    assert_eq!(day[0].passage.to_string(), "Ge 1:1-31");
+    assert_eq!(day[0].verses.len(), 31);
+    assert!(day[1].verses[0].1.contains("Belteshazzar"));
+    // Clipped to the verses in the archive
+    let day = bar.plan_day(&plan, 2).unwrap();
+    assert_eq!(day[0].passage.to_string(), "Eph 4:30-32");
+    let day = bar.plan_day(&plan, 3).unwrap();
+    assert!(day[0].verses.is_empty());
+    assert!(bar.plan_day(&plan, 4).is_err());
+}
+
+#[test]
+fn test_random_verses() {
+    use biblearchive::random::{Date, Weighting};
+    let bar =
+        BARFile::open("tests/data/KJV.ibar").expect("Failed to load KJV.ibar from tests/data");
+    let date = Date::new(2024, 12, 25).unwrap();
+    let votd = bar.verse_of_the_day(date, 42).unwrap();
+    assert_eq!(bar.verse_of_the_day(date, 42).unwrap(), votd);
+    bar.verse_text(&votd).unwrap();
+    let days: Vec<Reference> = (0..10)
+        .map(|i| {
+            bar.verse_of_the_day(Date::from_days(date.days() + i), 42)
+                .unwrap()
+        })
+        .collect();
+    assert!(days.iter().any(|r| *r != votd));
+
+    let mut picker = bar.verse_picker(7).unwrap();
+    assert_eq!(picker.len(), 84);
+    for _ in 0..200 {
+        let reference = picker.verse().unwrap();
+        bar.verse_text(&reference).unwrap();
+        let passage = picker.passage(5).unwrap();
+        assert_eq!(passage.start.chapter, passage.end.chapter);
+        assert!(passage.end.verse - passage.start.verse < 5);
+        bar.verse_text(&passage.end).unwrap();
+    }
+
+    let mut picker = bar.verse_picker(7).unwrap().books(&[27, 66]);
+    assert_eq!(picker.len(), 21);
+    assert!((0..50).all(|_| picker.verse().unwrap().book == 27));
+
+    let curated = [
+        "Ge 1:1".parse().unwrap(),
+        "Eph 4:30-5:2".parse().unwrap(),
+        Passage::verse(Reference::new(43, 3, 16)),
+    ];
+    let mut picker = bar
+        .verse_picker(7)
+        .unwrap()
+        .passages(&curated)
+        .weighting(Weighting::Chapter);
+    assert_eq!(picker.len(), 4);
+    let picks: Vec<Reference> = (0..100).map(|_| picker.verse().unwrap()).collect();
+    // Chapter weighting makes Ge 1:1 as likely as the three verses of Eph 4 together
+    let genesis = picks.iter().filter(|r| r.book == 1).count();
+    assert!(genesis > 25 && genesis < 75);
+    assert!(picks.iter().all(|r| curated.iter().any(|p| p.contains(r))));
+
+    assert!(bar.verse_picker(7).unwrap().books(&[2]).verse().is_none());
+}
@@ -0,0 +1,95 @@
+/* Exercise the C API against tests/data/KJV.ibar. Built and run by tests/ffi_test.rs */
+#include <stdio.h>
+#include <string.h>
+
+#include "biblearchive.h"
+
+#define CHECK(cond)                                                          \
+    do {                                                                     \
+        if (!(cond)) {                                                       \
+            const char *err = bar_last_error();                              \
+            fprintf(stderr, "%s:%d: check failed: %s (last error: %s)\n",    \
+                    __FILE__, __LINE__, #cond, err ? err : "none");          \
+            return 1;                                                        \
+        }                                                                    \
+    } while (0)
+
+static int check_archive(BarArchive *bar) {
+    uint8_t books[66];
+    size_t count = 0;
+    uint8_t chapters = 0;
+    uint8_t verses = 0;
+    char *text = NULL;
+    BarSearchHit *hits = NULL;
+    size_t num_hits = 0;
+
+    CHECK(bar_book_count(bar) == 3);
+    CHECK(bar_books(bar, books, sizeof(books), &count) == BAR_STATUS_OK);
+    CHECK(count == 3);
+    CHECK(books[0] == 1 && books[1] == 27 && books[2] == 49);
+
+    CHECK(bar_chapter_count(bar, 1, &chapters) == BAR_STATUS_OK);
+    CHECK(chapters == 50);
+    CHECK(bar_chapter_count(bar, 2, &chapters) == BAR_STATUS_NOT_FOUND);
+
+    CHECK(bar_verse_count(bar, 1, 1, &verses) == BAR_STATUS_OK);
+    CHECK(verses == 31);
+    CHECK(bar_verse_count(bar, 1, 2, &verses) == BAR_STATUS_NOT_FOUND);
+
+    CHECK(bar_verse_text(bar, 1, 1, 1, &text) == BAR_STATUS_OK);
+    CHECK(strcmp(text, "In the beginning God created the heaven and the earth.") == 0);
+    bar_string_free(text);
+    CHECK(bar_verse_text(bar, 49, 4, 33, &text) == BAR_STATUS_REFERENCE_ERROR);
+    CHECK(bar_last_error() != NULL);
+
+    CHECK(bar_chapter_text(bar, 27, 1, &text) == BAR_STATUS_OK);
+    CHECK(strncmp(text, "In the third year", 17) == 0);
+    bar_string_free(text);
+
+    CHECK(bar_search(bar, "Belteshazzar", &hits, &num_hits) == BAR_STATUS_OK);
+    CHECK(num_hits == 1);
+    CHECK(hits[0].book == 27 && hits[0].chapter == 1 && hits[0].verse == 7);
+    CHECK(strstr(hits[0].text, "Belteshazzar") != NULL);
+    bar_search_free(hits, num_hits);
+    return 0;
+}
+
+int main(int argc, char **argv) {
+    BarArchive *bar = NULL;
+    char *name = NULL;
+    static uint8_t data[64 * 1024];
+    size_t len = 0;
+    FILE *file = NULL;
+
+    CHECK(argc == 2);
+
+    CHECK(bar_open("no/such/file.ibar", &bar) == BAR_STATUS_IO_ERROR);
+    CHECK(bar_open(NULL, &bar) == BAR_STATUS_INVALID_ARGUMENT);
+    CHECK(bar_open(argv[1], NULL) == BAR_STATUS_INVALID_ARGUMENT);
+
+    CHECK(bar_open(argv[1], &bar) == BAR_STATUS_OK);
+    CHECK(check_archive(bar) == 0);
+    bar_close(bar);
+    bar = NULL;
+
+    file = fopen(argv[1], "rb");
+    CHECK(file != NULL);
+    len = fread(data, 1, sizeof(data), file);
+    fclose(file);
+    CHECK(bar_open_memory(data, len, &bar) == BAR_STATUS_OK);
+    CHECK(check_archive(bar) == 0);
+    bar_close(bar);
+
+    CHECK(bar_open_memory(data, 10, &bar) == BAR_STATUS_IO_ERROR);
+    CHECK(bar_open_memory(data, len, NULL) == BAR_STATUS_INVALID_ARGUMENT);
+
+    name = bar_book_name(27);
+    CHECK(strcmp(name, "Daniel") == 0);
+    bar_string_free(name);
+    name = bar_book_abbrev(49);
+    CHECK(strcmp(name, "Eph") == 0);
+    bar_string_free(name);
+
+    printf("ffi_test passed\n");
+    return 0;
+}
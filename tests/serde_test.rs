@@ -0,0 +1,135 @@
+#![cfg(feature = "serde")]
+use biblearchive::barbook::barchapter::{ChapterDetails, CompressionAlgorithm};
+use biblearchive::search::SearchHit;
+use biblearchive::{ArchiveMetadata, BARFile, BARVersion, Passage, Reference};
+use serde_json::json;
+use std::time::Duration;
+
+#[test]
+fn test_reference_json() {
+    let reference = Reference::new(1, 2, 3);
+    let value = serde_json::to_value(reference).unwrap();
+    assert_eq!(value, json!({"book": 1, "chapter": 2, "verse": 3}));
+    assert_eq!(
+        serde_json::from_value::<Reference>(value).unwrap(),
+        reference
+    );
+
+    let passage = Passage::new(Reference::new(1, 1, 1), Reference::new(1, 2, 3));
+    let value = serde_json::to_value(passage).unwrap();
+    assert_eq!(
+        value,
+        json!({
+            "start": {"book": 1, "chapter": 1, "verse": 1},
+            "end": {"book": 1, "chapter": 2, "verse": 3}
+        })
+    );
+    assert_eq!(serde_json::from_value::<Passage>(value).unwrap(), passage);
+}
+
+#[test]
+fn test_version_and_algorithm_json() {
+    assert_eq!(
+        serde_json::to_value(BARVersion(2, 1)).unwrap(),
+        json!("2.1")
+    );
+    assert_eq!(
+        serde_json::from_value::<BARVersion>(json!("1.0")).unwrap(),
+        BARVersion(1, 0)
+    );
+    assert!(serde_json::from_value::<BARVersion>(json!("two")).is_err());
+    assert_eq!(
+        serde_json::to_value(CompressionAlgorithm::ZLib).unwrap(),
+        json!("ZLIB")
+    );
+    assert_eq!(
+        serde_json::from_value::<CompressionAlgorithm>(json!("LZO")).unwrap(),
+        CompressionAlgorithm::Lzo
+    );
+}
+
+#[test]
+fn test_chapter_details_json() {
+    let details = ChapterDetails {
+        number_of_blocks: 2,
+        compression_algorithm: CompressionAlgorithm::GZip,
+        compressed_size: 100,
+        uncompressed_size: 300,
+        decompress_time: Duration::from_micros(1500),
+    };
+    let value = serde_json::to_value(&details).unwrap();
+    assert_eq!(
+        value,
+        json!({
+            "number_of_blocks": 2,
+            "compression_algorithm": "GZip",
+            "compressed_size": 100,
+            "uncompressed_size": 300,
+            "decompress_time_us": 1500
+        })
+    );
+    let details: ChapterDetails = serde_json::from_value(value).unwrap();
+    assert_eq!(details.decompress_time, Duration::from_micros(1500));
+}
+
+#[test]
+fn test_archive_json() {
+    let bar =
+        BARFile::open("tests/data/KJV.ibar").expect("Failed to load KJV.ibar from tests/data");
+    let metadata = bar.metadata();
+    let value = serde_json::to_value(&metadata).unwrap();
+    assert_eq!(
+        value,
+        json!({
+            "archive_version": "2.1",
+            "bible_version": "KJV",
+            "book_capacity": 66,
+            "books": [
+                {"book": 1, "abbrev": "Ge", "name": "Genesis", "chapters": 50},
+                {"book": 27, "abbrev": "Da", "name": "Daniel", "chapters": 12},
+                {"book": 49, "abbrev": "Eph", "name": "Ephesians", "chapters": 6}
+            ]
+        })
+    );
+    assert_eq!(
+        serde_json::from_value::<ArchiveMetadata>(value).unwrap(),
+        metadata
+    );
+
+    let hits = bar.search("Belteshazzar");
+    let value = serde_json::to_value(&hits[0]).unwrap();
+    assert_eq!(
+        value["reference"],
+        json!({"book": 27, "chapter": 1, "verse": 7})
+    );
+    assert_eq!(value["text"], json!(&*hits[0].text));
+    let hit: SearchHit = serde_json::from_value(value).unwrap();
+    assert_eq!(&*hit.text, &*hits[0].text);
+}
+
+#[test]
+fn test_stats_json() {
+    let bar =
+        BARFile::open("tests/data/KJV.ibar").expect("Failed to load KJV.ibar from tests/data");
+    let value = serde_json::to_value(bar.stats().unwrap()).unwrap();
+    assert_eq!(value["archive_version"], json!("2.1"));
+    assert_eq!(value["unused_bytes"], json!(0));
+    assert_eq!(value["totals"]["verses"], json!(84));
+    assert_eq!(value["books"][1]["abbrev"], json!("Da"));
+    assert_eq!(value["compression"][0]["algorithm"], json!("ZLIB"));
+    assert_eq!(
+        value["largest_blocks"][0]["reference"],
+        json!({"book": 49, "chapter": 4, "verse": 1})
+    );
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_reading_plan_json() {
+    use biblearchive::readingplan::ReadingPlan;
+    let plan = ReadingPlan::from_json(r#"{"days": [["Ge 1-2", "Mt 1"], ["Ge 3:1-5"]]}"#).unwrap();
+    assert_eq!(plan.len(), 2);
+    assert_eq!(plan.day(2).unwrap()[0].to_string(), "Ge 3:1-5");
+    assert_eq!(ReadingPlan::from_json(&plan.to_json()).unwrap(), plan);
+    assert!(ReadingPlan::from_json(r#"{"days": [["Ge x"]]}"#).is_err());
+}
@@ -0,0 +1,703 @@
+use biblearchive::barbook::annotations::Heading;
+use biblearchive::barbook::barchapter::CompressionAlgorithm;
+use biblearchive::barbook::interlinear::InterlinearWord;
+use biblearchive::barbook::notes::Note;
+use biblearchive::writer::merge::{ConflictPolicy, merge, merge_to};
+use biblearchive::writer::{BARWriter, BookSource, ChapterSource, usfm};
+use biblearchive::{BARFile, BARFileHeader, Passage, Reference};
+use std::io::Cursor;
+use std::path::PathBuf;
+
+const GENESIS: &str = r#"\id GEN
+\c 1
+\s1 The Creation
+\p
+\v 1 In the beginning God created the heaven and the earth.\x - \xo 1:1 \xt Joh 1:1\x*
+\v 2 And the earth was without form, and void; and darkness was upon\f + \fr 1:2 \ft Or over\f* the face of the deep.
+\p
+\v 3 And God said, Let there be light: and there \add was\add* light.
+\c 2
+\v 1 Thus the heavens and the earth were finished, and all the host of them.
+"#;
+
+const JOHN: &str = r#"\id JHN
+\c 1
+\p
+\v 1 \w Ἐν|strong="G1722" lemma="ἐν" x-morph="PREP"\w* \w ἀρχῇ|strong="G0746" lemma="ἀρχή" x-morph="N-DSF"\w* \w ἦν|strong="G2258"\w* ὁ \w λόγος|strong="G3056" lemma="λόγος" x-morph="N-NSM"\w*, καὶ ὁ \w λόγος|strong="G3056" lemma="λόγος" x-morph="N-NSM"\w*
+\v 2 οὗτος ἦν ἐν ἀρχῇ πρὸς τὸν θεόν.
+"#;
+
+// A path in the temp directory that is removed when dropped
+struct TempPath(PathBuf);
+
+impl TempPath {
+    fn new(name: &str) -> Self {
+        let path = std::env::temp_dir().join(format!("{}-{}.ibar", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        TempPath(path)
+    }
+}
+
+impl Drop for TempPath {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+#[test]
+fn test_write_usfm() {
+    let path = TempPath::new("test_write_usfm");
+    let mut writer = BARWriter::new("KJV").compression(CompressionAlgorithm::ZLib);
+    writer.add_book(usfm::parse(GENESIS).unwrap()).unwrap();
+    assert!(writer.add_book(BookSource::new(1)).is_err());
+    let written = writer.write(&path.0).unwrap();
+    assert_eq!(written.number_of_books(), 1);
+
+    let bar = BARFile::open(&path.0).unwrap();
+    assert_eq!(bar.archive_version().to_string(), "2.7");
+    assert_eq!(bar.bible_version(), "KJV");
+    let book = bar.book(1).unwrap();
+    assert_eq!(book.number_of_chapters(), 2);
+    assert_eq!(book.verse_counts().unwrap(), vec![3, 1]);
+
+    let chapter = book.chapter(1).unwrap();
+    assert_eq!(
+        &*chapter.verse_text(3).unwrap(),
+        "And God said, Let there be light: and there was light."
+    );
+    assert!(chapter.verse_spans(3).unwrap()[1].style.italic);
+    assert_eq!(
+        chapter.headings().unwrap(),
+        vec![Heading {
+            verse: 1,
+            level: 1,
+            text: "The Creation".to_string()
+        }]
+    );
+    assert_eq!(chapter.paragraphs().unwrap(), vec![1, 3]);
+    assert_eq!(
+        chapter.notes(2).unwrap(),
+        vec![Note {
+            verse: 2,
+            offset: Some(63),
+            text: "Or over".to_string()
+        }]
+    );
+    let cross_references = chapter.cross_references(1).unwrap();
+    assert_eq!(cross_references.len(), 1);
+    assert_eq!(
+        cross_references[0].targets,
+        vec![Passage::verse(Reference::new(43, 1, 1))]
+    );
+    assert!(chapter.notes(1).unwrap().is_empty());
+    assert!(chapter.cross_references(2).unwrap().is_empty());
+
+    let chapter = book.chapter(2).unwrap();
+    assert!(chapter.headings().unwrap().is_empty());
+    assert!(chapter.paragraphs().unwrap().is_empty());
+    assert!(chapter.notes(1).unwrap().is_empty());
+    assert_eq!(
+        &*chapter.verse_text(1).unwrap(),
+        "Thus the heavens and the earth were finished, and all the host of them."
+    );
+
+    // The annotations and notes are accounted for
+    let stats = bar.stats().unwrap();
+    assert_eq!(stats.unused_bytes, 0);
+    // Markup tags are not counted as text
+    let text: String = (1..=2)
+        .map(|number| {
+            book.chapter(number)
+                .unwrap()
+                .chapter_text()
+                .unwrap()
+                .to_string()
+        })
+        .collect();
+    assert_eq!(stats.totals.characters, text.chars().count() as u64);
+    assert_eq!(stats.totals.words, text.split_whitespace().count() as u64);
+    assert!(stats.totals.uncompressed_size > text.len() as u64);
+}
+
+#[test]
+fn test_write_blocks() {
+    let path = TempPath::new("test_write_blocks");
+    let verses: Vec<String> = (1..=20).map(|i| format!("Verse number {}", i)).collect();
+    let mut book = BookSource::new(27);
+    book.chapters.push(ChapterSource::new(3, verses));
+    let mut writer = BARWriter::new("TEST")
+        .compression(CompressionAlgorithm::None)
+        .block_size(64);
+    writer.add_book(book).unwrap();
+    writer.write(&path.0).unwrap();
+
+    let bar = BARFile::open(&path.0).unwrap();
+    let book = bar.book(27).unwrap();
+    assert!(book.chapter(1).is_none());
+    let chapter = book.chapter(3).unwrap();
+    assert!(chapter.blocks().unwrap().len() > 1);
+    let verses: Vec<String> = chapter.verses().map(|verse| verse.to_string()).collect();
+    assert_eq!(verses.len(), 20);
+    assert_eq!(verses[19], "Verse number 20");
+
+    // Writing never replaces an existing file
+    let mut writer = BARWriter::new("TEST");
+    writer.add_book(BookSource::new(1)).unwrap();
+    assert!(writer.write(&path.0).is_err());
+
+    // A markup tag that readers would not split the same way is refused
+    for verse in ["Ends in a tag\x1B", "An \x1Bzunknown tag"] {
+        let mut book = BookSource::new(27);
+        book.chapters.push(ChapterSource::new(
+            1,
+            vec![verse.to_string(), "Next".to_string()],
+        ));
+        let mut writer = BARWriter::new("TEST");
+        writer.add_book(book).unwrap();
+        assert!(writer.write_to(Cursor::new(Vec::new())).is_err());
+    }
+}
+
+#[test]
+fn test_write_header() {
+    let path = TempPath::new("test_write_header");
+    let header = BARFileHeader::builder("Ελλην")
+        .version(2, 3)
+        .book_capacity(27)
+        .build()
+        .unwrap();
+    let mut book = BookSource::new(40);
+    book.chapters
+        .push(ChapterSource::new(1, vec!["Βίβλος γενέσεως".to_string()]));
+    let mut writer = BARWriter::with_header(header);
+    writer.add_book(book).unwrap();
+    writer.write(&path.0).unwrap();
+
+    let bar = BARFile::open(&path.0).unwrap();
+    assert_eq!(bar.archive_version().to_string(), "2.3");
+    assert_eq!(bar.bible_version(), "Ελλην");
+    assert_eq!(bar.book_capacity(), 27);
+    assert_eq!(bar.stats().unwrap().unused_bytes, 0);
+
+    // A version abbreviation that does not fit is an error and no file is written
+    let path = TempPath::new("test_write_header_abbrev");
+    let mut writer = BARWriter::new("Authorized Version");
+    writer.add_book(BookSource::new(1)).unwrap();
+    assert!(writer.write(&path.0).is_err());
+    assert!(!path.0.exists());
+    assert!(BARFile::create(&path.0, "Ελληνικά Κείμενα".to_string()).is_err());
+    assert!(!path.0.exists());
+}
+
+#[test]
+fn test_no_annotations_before_2_5() {
+    let bar = BARFile::open("tests/data/KJV.ibar").unwrap();
+    let chapter = bar.book(1).unwrap().chapter(1).unwrap();
+    assert!(chapter.headings().unwrap().is_empty());
+    assert!(chapter.paragraphs().unwrap().is_empty());
+    assert!(chapter.notes(1).unwrap().is_empty());
+    assert!(chapter.cross_references(1).unwrap().is_empty());
+}
+
+#[test]
+fn test_interlinear() {
+    let path = TempPath::new("test_interlinear");
+    let mut writer = BARWriter::new("greek");
+    writer.add_book(usfm::parse(JOHN).unwrap()).unwrap();
+    writer.write(&path.0).unwrap();
+
+    let bar = BARFile::open(&path.0).unwrap();
+    let chapter = bar.book(43).unwrap().chapter(1).unwrap();
+    let words = chapter.interlinear(1).unwrap();
+    assert_eq!(words.len(), 8);
+    assert_eq!(
+        words[1],
+        InterlinearWord {
+            word: "ἀρχῇ".to_string(),
+            lemma: Some("ἀρχή".to_string()),
+            strongs: Some("G0746".to_string()),
+            morph: Some("N-DSF".to_string()),
+        }
+    );
+    assert_eq!(words[3].word, "ὁ");
+    assert_eq!(words[3].strongs, None);
+    assert!(chapter.interlinear(2).unwrap().is_empty());
+
+    let hits = bar.search_strongs("G3056").unwrap();
+    assert_eq!(hits.len(), 2);
+    assert_eq!(hits[0].reference, Reference::new(43, 1, 1));
+    assert_eq!(hits[0].position, 4);
+    assert_eq!(hits[1].position, 7);
+    assert_eq!(hits[1].word.lemma.as_deref(), Some("λόγος"));
+    assert_eq!(bar.search_strongs("g746").unwrap().len(), 1);
+    assert!(bar.search_strongs("G9999").unwrap().is_empty());
+    assert_eq!(bar.stats().unwrap().unused_bytes, 0);
+
+    // Interlinear data must line up with the words of the verse
+    let mut chapter = ChapterSource::new(1, vec!["Ἐν ἀρχῇ".to_string()]);
+    chapter.interlinear.push(vec![InterlinearWord {
+        word: "Ἐν".to_string(),
+        ..Default::default()
+    }]);
+    let mut book = BookSource::new(43);
+    book.chapters.push(chapter);
+    let mut writer = BARWriter::new("greek");
+    writer.add_book(book).unwrap();
+    let path = TempPath::new("test_interlinear_mismatch");
+    assert!(writer.write(&path.0).is_err());
+    assert!(!path.0.exists());
+
+    let bar = BARFile::open("tests/data/KJV.ibar").unwrap();
+    assert!(bar.search_strongs("G3056").unwrap().is_empty());
+}
+
+#[test]
+fn test_edit() {
+    let path = TempPath::new("test_edit");
+    let mut writer = BARWriter::new("KJV");
+    writer.add_book(usfm::parse(GENESIS).unwrap()).unwrap();
+    writer.write(&path.0).unwrap();
+
+    let mut bar = BARFile::open_for_edit(&path.0).unwrap();
+    bar.replace_verse(
+        &Reference::new(1, 1, 3),
+        "And God said, Let there be light.",
+    )
+    .unwrap();
+    assert!(
+        bar.replace_verse(&Reference::new(1, 1, 4), "No such verse")
+            .is_err()
+    );
+    assert!(
+        bar.replace_verse(&Reference::new(2, 1, 1), "No such book")
+            .is_err()
+    );
+    let chapter = bar.book(1).unwrap().chapter(1).unwrap();
+    assert_eq!(
+        &*chapter.verse_text(3).unwrap(),
+        "And God said, Let there be light."
+    );
+    assert_eq!(chapter.headings().unwrap().len(), 1);
+    assert_eq!(chapter.paragraphs().unwrap(), vec![1, 3]);
+    assert_eq!(chapter.notes(2).unwrap()[0].text, "Or over");
+    assert!(bar.stats().unwrap().unused_bytes > 0);
+
+    // Chapter 2 gets a note and chapter 1 keeps its own
+    let mut source = ChapterSource::new(2, vec!["Thus the heavens were finished.".to_string()]);
+    source.notes.push(Note {
+        verse: 1,
+        offset: None,
+        text: "Or completed".to_string(),
+    });
+    bar.replace_chapter(1, source).unwrap();
+    let book = bar.book(1).unwrap();
+    assert_eq!(book.verse_counts().unwrap(), vec![3, 1]);
+    assert_eq!(
+        book.chapter(2).unwrap().notes(1).unwrap()[0].text,
+        "Or completed"
+    );
+    assert_eq!(
+        book.chapter(1).unwrap().notes(2).unwrap()[0].text,
+        "Or over"
+    );
+
+    // A chapter past the end of the book writes the whole book again
+    bar.replace_chapter(1, ChapterSource::new(5, vec!["Adam".to_string()]))
+        .unwrap();
+    let book = bar.book(1).unwrap();
+    assert_eq!(book.number_of_chapters(), 5);
+    assert!(book.chapter(3).is_none());
+    assert_eq!(&*book.chapter(5).unwrap().verse_text(1).unwrap(), "Adam");
+    assert_eq!(
+        &*book.chapter(1).unwrap().verse_text(3).unwrap(),
+        "And God said, Let there be light."
+    );
+
+    let mut book = BookSource::new(1);
+    book.chapters
+        .push(ChapterSource::new(1, vec!["In the beginning".to_string()]));
+    bar.replace_book(book).unwrap();
+    assert!(bar.replace_book(BookSource::new(2)).is_err());
+    drop(bar);
+
+    // The edited file is an archive that anything can read
+    let bar = BARFile::open(&path.0).unwrap();
+    let book = bar.book(1).unwrap();
+    assert_eq!(book.number_of_chapters(), 1);
+    assert_eq!(
+        &*book.chapter(1).unwrap().verse_text(1).unwrap(),
+        "In the beginning"
+    );
+    assert!(bar.stats().unwrap().unused_bytes > 0);
+    drop(bar);
+
+    let mut bar = BARFile::open_for_edit(&path.0).unwrap();
+    bar.compact().unwrap();
+    assert_eq!(bar.stats().unwrap().unused_bytes, 0);
+    assert_eq!(
+        &*bar
+            .book(1)
+            .unwrap()
+            .chapter(1)
+            .unwrap()
+            .verse_text(1)
+            .unwrap(),
+        "In the beginning"
+    );
+}
+
+#[test]
+fn test_compact_unchanged() {
+    let path = TempPath::new("test_compact_unchanged");
+    std::fs::copy("tests/data/KJV.ibar", &path.0).unwrap();
+    let mut bar = BARFile::open_for_edit(&path.0).unwrap();
+    bar.compact().unwrap();
+    drop(bar);
+    assert_eq!(
+        std::fs::read(&path.0).unwrap(),
+        std::fs::read("tests/data/KJV.ibar").unwrap()
+    );
+}
+
+#[test]
+fn test_append_book() {
+    let path = TempPath::new("test_append_book");
+    let header = BARFileHeader::builder("KJV")
+        .book_capacity(1)
+        .build()
+        .unwrap();
+    let mut writer = BARWriter::with_header(header);
+    writer.add_book(usfm::parse(JOHN).unwrap()).unwrap();
+    writer.write(&path.0).unwrap();
+
+    let mut bar = BARFile::open_for_edit(&path.0).unwrap();
+    assert!(bar.append_book(usfm::parse(JOHN).unwrap()).is_err());
+    bar.append_book(usfm::parse(GENESIS).unwrap()).unwrap();
+    assert_eq!(bar.book_capacity(), 2);
+    assert_eq!(bar.number_of_books(), 2);
+    let mut book = BookSource::new(19);
+    book.chapters.push(ChapterSource::new(
+        23,
+        vec!["The LORD is my shepherd; I shall not want.".to_string()],
+    ));
+    bar.append_book(book).unwrap();
+    assert_eq!(bar.book_capacity(), 4);
+    assert!(bar.grow_book_index(3).is_err());
+    assert!(bar.grow_book_index(67).is_err());
+    drop(bar);
+
+    // The books written before the index grew have moved along with it
+    let bar = BARFile::open(&path.0).unwrap();
+    assert_eq!(bar.number_of_books(), 3);
+    assert_eq!(
+        bar.book(43)
+            .unwrap()
+            .chapter(1)
+            .unwrap()
+            .interlinear(1)
+            .unwrap()
+            .len(),
+        8
+    );
+    assert_eq!(
+        &*bar
+            .book(1)
+            .unwrap()
+            .chapter(2)
+            .unwrap()
+            .verse_text(1)
+            .unwrap(),
+        "Thus the heavens and the earth were finished, and all the host of them."
+    );
+    assert_eq!(bar.book(19).unwrap().verse_counts().unwrap()[22], 1);
+    assert_eq!(bar.stats().unwrap().unused_bytes, 0);
+}
+
+#[test]
+fn test_extract() {
+    let kjv = BARFile::open("tests/data/KJV.ibar").unwrap();
+    let path = TempPath::new("test_extract");
+    let bar = kjv.extract(&path.0, 1..=39).unwrap();
+    assert_eq!(bar.book_capacity(), 2);
+    assert_eq!(bar.archive_version(), kjv.archive_version());
+    assert_eq!(bar.bible_version(), "KJV");
+    assert!(bar.book(49).is_none());
+    let book = bar.book(1).unwrap();
+    let original = kjv.book(1).unwrap();
+    assert_eq!(book.number_of_chapters(), original.number_of_chapters());
+    assert_eq!(
+        book.chapter(1).unwrap().chapter_text().unwrap(),
+        original.chapter(1).unwrap().chapter_text().unwrap()
+    );
+    assert_eq!(bar.stats().unwrap().unused_bytes, 0);
+
+    // The same archive in memory
+    let bytes = kjv
+        .extract_to(Cursor::new(Vec::new()), 1..=39)
+        .unwrap()
+        .into_inner()
+        .ok()
+        .unwrap()
+        .into_inner();
+    assert_eq!(std::fs::read(&path.0).unwrap(), bytes);
+
+    let path = TempPath::new("test_extract_none");
+    assert!(kjv.extract(&path.0, 50..=66).is_err());
+    assert!(!path.0.exists());
+}
+
+#[test]
+fn test_merge() {
+    let genesis = TempPath::new("test_merge_genesis");
+    let mut writer = BARWriter::new("KJV");
+    writer.add_book(usfm::parse(GENESIS).unwrap()).unwrap();
+    let genesis = (writer.write(&genesis.0).unwrap(), genesis);
+    let john = TempPath::new("test_merge_john");
+    let mut writer = BARWriter::new("greek");
+    writer.add_book(usfm::parse(JOHN).unwrap()).unwrap();
+    let john = (writer.write(&john.0).unwrap(), john);
+
+    let path = TempPath::new("test_merge");
+    let bar = merge(&path.0, &[&john.0, &genesis.0], ConflictPolicy::Error).unwrap();
+    assert_eq!(bar.bible_version(), "greek");
+    assert_eq!(bar.number_of_books(), 2);
+    assert_eq!(bar.book_from_index(0).unwrap().book_number(), 1);
+    let chapter = bar.book(1).unwrap().chapter(1).unwrap();
+    assert_eq!(chapter.notes(2).unwrap()[0].text, "Or over");
+    assert_eq!(chapter.headings().unwrap().len(), 1);
+    assert_eq!(bar.search_strongs("G3056").unwrap().len(), 2);
+    assert_eq!(bar.stats().unwrap().unused_bytes, 0);
+
+    // A book in more than one archive
+    let path = TempPath::new("test_merge_conflict");
+    assert!(merge(&path.0, &[&bar, &genesis.0], ConflictPolicy::Error).is_err());
+    assert!(!path.0.exists());
+    let kjv = BARFile::open("tests/data/KJV.ibar").unwrap();
+    let bar = merge(&path.0, &[&bar, &kjv], ConflictPolicy::KeepLast).unwrap();
+    assert_eq!(bar.number_of_books(), 4);
+    let book = bar.book(1).unwrap();
+    assert_eq!(
+        book.verse_counts().unwrap(),
+        kjv.book(1).unwrap().verse_counts().unwrap()
+    );
+    assert_eq!(
+        bar.book(43)
+            .unwrap()
+            .chapter(1)
+            .unwrap()
+            .interlinear(1)
+            .unwrap()
+            .len(),
+        8
+    );
+    let path = TempPath::new("test_merge_first");
+    let bar = merge(&path.0, &[&genesis.0, &kjv], ConflictPolicy::KeepFirst).unwrap();
+    assert_eq!(bar.book(1).unwrap().number_of_chapters(), 2);
+    assert_eq!(bar.archive_version().to_string(), "2.7");
+
+    // The latest archive version is used whatever the order, so the notes have somewhere to go
+    let path = TempPath::new("test_merge_older");
+    let bar = merge(&path.0, &[&kjv, &genesis.0], ConflictPolicy::KeepLast).unwrap();
+    assert_eq!(bar.archive_version().to_string(), "2.7");
+    assert_eq!(bar.bible_version(), "KJV");
+    let chapter = bar.book(1).unwrap().chapter(1).unwrap();
+    assert_eq!(chapter.notes(2).unwrap()[0].text, "Or over");
+    assert!(
+        merge(
+            &path.0,
+            &[] as &[&BARFile<std::fs::File>],
+            ConflictPolicy::Error
+        )
+        .is_err()
+    );
+
+    // Markup is kept, and the text read without it, in either order
+    let exodus = TempPath::new("test_merge_exodus");
+    let mut writer = BARWriter::new("KJV");
+    let source = r"\id EXO
+\c 1
+\v 1 Now these \add are\add* the names.
+";
+    writer.add_book(usfm::parse(source).unwrap()).unwrap();
+    let exodus = (writer.write(&exodus.0).unwrap(), exodus);
+    for archives in [[&kjv, &exodus.0], [&exodus.0, &kjv]] {
+        let bar = merge_to(Cursor::new(Vec::new()), &archives, ConflictPolicy::Error).unwrap();
+        assert_eq!(bar.archive_version().to_string(), "2.7");
+        assert_eq!(bar.number_of_books(), 4);
+        let chapter = bar.book(2).unwrap().chapter(1).unwrap();
+        assert_eq!(&*chapter.verse_text(1).unwrap(), "Now these are the names.");
+        assert_eq!(chapter.verse_spans(1).unwrap().len(), 3);
+    }
+}
+
+#[test]
+fn test_write_in_memory() {
+    let mut writer = BARWriter::new("KJV");
+    writer.add_book(usfm::parse(GENESIS).unwrap()).unwrap();
+    let bar = writer.write_to(Cursor::new(Vec::new())).unwrap();
+
+    // The target can not be taken back while a book still shares it
+    let book = bar.book(1).unwrap();
+    let bar = bar.into_inner().err().unwrap();
+    drop(book);
+    let bytes = bar.into_inner().ok().unwrap().into_inner();
+
+    let bar = BARFile::read(Cursor::new(bytes.as_slice())).unwrap();
+    assert_eq!(bar.archive_version().to_string(), "2.7");
+    let chapter = bar.book(1).unwrap().chapter(1).unwrap();
+    assert_eq!(chapter.notes(2).unwrap()[0].text, "Or over");
+    assert_eq!(bar.stats().unwrap().unused_bytes, 0);
+
+    // The same archive as a file
+    let path = TempPath::new("test_write_in_memory");
+    let mut writer = BARWriter::new("KJV");
+    writer.add_book(usfm::parse(GENESIS).unwrap()).unwrap();
+    writer.write(&path.0).unwrap();
+    assert_eq!(std::fs::read(&path.0).unwrap(), bytes);
+
+    // Nothing is written for an archive that can not be
+    let mut writer = BARWriter::new("Authorized Version");
+    writer.add_book(BookSource::new(1)).unwrap();
+    let mut target = Cursor::new(Vec::new());
+    assert!(writer.write_to(&mut target).is_err());
+    assert!(target.get_ref().is_empty());
+}
+
+#[test]
+fn test_reproducible() {
+    let build = |books: &[&str], compression: CompressionAlgorithm| {
+        let mut writer = BARWriter::new("KJV").compression(compression);
+        for book in books {
+            writer.add_book(usfm::parse(book).unwrap()).unwrap();
+        }
+        let bar = writer.write_to(Cursor::new(Vec::new())).unwrap();
+        bar.into_inner().ok().unwrap().into_inner()
+    };
+    // The same text gives the same bytes whatever order the books are added in
+    let bytes = build(&[JOHN, GENESIS], CompressionAlgorithm::GZip);
+    assert_eq!(bytes, build(&[GENESIS, JOHN], CompressionAlgorithm::GZip));
+    let bar = BARFile::read(Cursor::new(bytes)).unwrap();
+    assert_eq!(bar.book_from_index(0).unwrap().book_number(), 1);
+
+    // The fingerprint only depends on the text
+    let fingerprint = bar.fingerprint().unwrap();
+    for compression in [CompressionAlgorithm::ZLib, CompressionAlgorithm::None] {
+        let bytes = build(&[GENESIS, JOHN], compression);
+        let other = BARFile::read(Cursor::new(bytes)).unwrap();
+        assert_eq!(other.fingerprint().unwrap(), fingerprint);
+    }
+    assert_eq!(fingerprint.to_string().len(), 64);
+    let changed = GENESIS.replace("without form", "formless");
+    let bytes = build(&[&changed, JOHN], CompressionAlgorithm::GZip);
+    let other = BARFile::read(Cursor::new(bytes)).unwrap();
+    assert_ne!(other.fingerprint().unwrap(), fingerprint);
+
+    let kjv = BARFile::open("tests/data/KJV.ibar").unwrap();
+    let path = TempPath::new("test_reproducible");
+    let genesis = kjv.extract(&path.0, [1]).unwrap();
+    let mut writer = BARWriter::new("KJV").compression(CompressionAlgorithm::None);
+    writer
+        .add_book(kjv.book(1).unwrap().to_source().unwrap())
+        .unwrap();
+    let rewritten = writer.write_to(Cursor::new(Vec::new())).unwrap();
+    assert_eq!(
+        rewritten.fingerprint().unwrap(),
+        genesis.fingerprint().unwrap()
+    );
+    assert_ne!(kjv.fingerprint().unwrap(), genesis.fingerprint().unwrap());
+}
+
+#[test]
+fn test_to_source_damaged_block() {
+    // A block with more lines than its verse range is not rewritten as valid text
+    let mut bytes = std::fs::read("tests/data/KJV.ibar").unwrap();
+    bytes[3051 + 2] = 31;
+    let bar = BARFile::read(Cursor::new(bytes)).unwrap();
+    assert!(
+        bar.book(49)
+            .unwrap()
+            .chapter(4)
+            .unwrap()
+            .to_source()
+            .is_err()
+    );
+    assert!(bar.book(1).unwrap().to_source().is_ok());
+}
+
+#[test]
+fn test_write_v1() {
+    let header = || BARFileHeader::builder("NIV").version(1, 0).build().unwrap();
+    let mut book = BookSource::new(1);
+    book.chapters.push(ChapterSource::new(
+        1,
+        vec![
+            "In the beginning God created the heavens and the earth.".to_string(),
+            "Now the earth was formless and empty.".to_string(),
+        ],
+    ));
+    let path = TempPath::new("test_write_v1");
+    let mut writer = BARWriter::with_header(header());
+    writer.add_book(book.clone()).unwrap();
+    writer.write(&path.0).unwrap();
+
+    let bytes = std::fs::read(&path.0).unwrap();
+    assert_eq!(&bytes[..5], b"BAR\x01\x00");
+    let bar = BARFile::open(&path.0).unwrap();
+    assert_eq!(bar.archive_version().to_string(), "1.0");
+    let chapter = bar.book(1).unwrap().chapter(1).unwrap();
+    let blocks = chapter.blocks().unwrap();
+    assert_eq!(blocks[0].compression_algorithm, CompressionAlgorithm::Lzo);
+    // Chapter, start verse, end verse and size, then the LZO data with its leader
+    let block = blocks[0].file_offset as usize;
+    assert_eq!(&bytes[block..block + 3], &[1, 1, 2]);
+    assert_eq!(bytes[block + 7], 0xF1);
+    assert_eq!(
+        &*chapter.verse_text(2).unwrap(),
+        "Now the earth was formless and empty."
+    );
+    assert_eq!(bar.stats().unwrap().unused_bytes, 0);
+    drop(bar);
+
+    // Version 1 archives can be edited in place
+    let mut bar = BARFile::open_for_edit(&path.0).unwrap();
+    bar.replace_verse(&Reference::new(1, 1, 2), "Now the earth was without shape.")
+        .unwrap();
+    let chapter = bar.book(1).unwrap().chapter(1).unwrap();
+    assert_eq!(
+        &*chapter.verse_text(2).unwrap(),
+        "Now the earth was without shape."
+    );
+
+    // Anything a version 1 archive can not hold is an error and nothing is written
+    let path = TempPath::new("test_write_v1_refused");
+    let mut writer = BARWriter::with_header(header()).compression(CompressionAlgorithm::ZLib);
+    writer.add_book(book).unwrap();
+    assert!(writer.write(&path.0).is_err());
+    let mut writer = BARWriter::with_header(header());
+    writer.add_book(usfm::parse(GENESIS).unwrap()).unwrap();
+    assert!(writer.write(&path.0).is_err());
+    assert!(!path.0.exists());
+
+    // A version 2 book copied into a version 1 archive is compressed again with LZO
+    let source = BARFile::open("tests/data/KJV.ibar").unwrap();
+    let mut writer = BARWriter::with_header(header());
+    writer
+        .add_book(source.book(49).unwrap().to_source().unwrap())
+        .unwrap();
+    let bar = writer.write_to(Cursor::new(Vec::new())).unwrap();
+    let chapter = bar.book(49).unwrap().chapter(4).unwrap();
+    assert_eq!(
+        chapter.blocks().unwrap()[0].compression_algorithm,
+        CompressionAlgorithm::Lzo
+    );
+    assert_eq!(
+        chapter.chapter_text().unwrap(),
+        source
+            .book(49)
+            .unwrap()
+            .chapter(4)
+            .unwrap()
+            .chapter_text()
+            .unwrap()
+    );
+}